@@ -1,15 +1,17 @@
+#import "shaders/terrain_uniforms.wgsl"::TerrainFog
 
-const FOG_MIN_DISTANCE: f32 = 384.0;
-const FOG_COLOR: vec4<f32> = vec4<f32>(0.4, 0.4, 0.4, 1.0);
+// Computes the amount of fog (0.0 - 1.0) at the specified distance from the camera and height.
+fn ffog_calc_factor(fog: TerrainFog, distance: f32, height: f32) -> f32 {
+    let distance_t = saturate((distance - fog.start) / max(fog.end - fog.start, 0.0001));
+    let distance_factor = pow(distance_t, fog.falloff);
 
-// 
-fn ffog_calc_factor(clamped_d: f32, fog_distance: f32, chunk_size: f32) -> f32 {
-    let fog_max: f32 = fog_distance;
-    let fog_min: f32 = fog_max - 4.5 * chunk_size;
-    let new_clamped_d = clamp(fog_min, clamped_d, fog_max);
-    return 1.0 - (fog_max - new_clamped_d) / (fog_max - fog_min);
+    // height fog thickens towards the bottom height and with the distance.
+    let height_t = saturate((fog.height_top - height) / max(fog.height_top - fog.height_bottom, 0.0001));
+    let height_factor = fog.height_density * height_t * saturate(distance / fog.end);
+
+    return max(distance_factor, height_factor);
 }
 
-fn ffog_apply_fog(d: f32, fog_min: f32, chunk_size: f32, color: vec4<f32>) -> vec4<f32> {
-    return mix(color, FOG_COLOR, ffog_calc_factor(d, fog_min, 32.0));
-}
\ No newline at end of file
+fn ffog_apply_fog(fog: TerrainFog, distance: f32, height: f32, color: vec4<f32>) -> vec4<f32> {
+    return mix(color, vec4<f32>(fog.color.rgb, color.a), ffog_calc_factor(fog, distance, height));
+}
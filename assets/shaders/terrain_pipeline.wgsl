@@ -14,23 +14,30 @@
 }
 #import bevy_core_pipeline::tonemapping::tone_mapping
 
-#import "shaders/voxel_data.wgsl"::{voxel_data_extract_normal, voxel_data_extract_material_index}
-#import "shaders/terrain_uniforms.wgsl"::{VoxelMat, voxel_materials, render_distance, TERRAIN_CHUNK_LENGTH}
+#import "shaders/voxel_data.wgsl"::{voxel_data_extract_material_index, voxel_data_extract_blend_material_index, voxel_data_extract_blend_factor, voxel_light_extract_block_light, voxel_light_extract_sky_light}
+#import "shaders/terrain_uniforms.wgsl"::{VoxelMat, voxel_material, terrain_fog, terrain_textures, terrain_textures_sampler, voxel_mat_texture_layer}
 #import "shaders/noise.wgsl"::hash
 #import "shaders/fog.wgsl"::ffog_apply_fog
 
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>,
-    @location(1) voxel_data: u32,
+    @location(1) voxel_data: vec2<u32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+    @location(4) light: u32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) voxel_normal: vec3<f32>,
-    @location(1) voxel_data: u32,
+    @location(1) voxel_data: vec2<u32>,
     @location(2) world_position: vec3<f32>,
     @location(3) instance_index: u32,
+    @location(4) base_color: vec4<f32>,
+    @location(5) uv: vec2<f32>,
+    @location(6) block_light: vec3<f32>,
+    @location(7) sky_light: f32,
 };
 
 @vertex
@@ -39,12 +46,19 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     let world_position = bevy_pbr::mesh_functions::mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
 
     var out: VertexOutput;
-    let voxel_normal = voxel_data_extract_normal(vertex.voxel_data);
     out.clip_position = view_transformations::position_world_to_clip(world_position.xyz);
-    out.voxel_normal = voxel_normal;
+    out.voxel_normal = vertex.normal;
     out.voxel_data = vertex.voxel_data;
     out.world_position = world_position.xyz;
     out.instance_index = vertex.instance_index;
+    out.uv = vertex.uv;
+    out.block_light = voxel_light_extract_block_light(vertex.light);
+    out.sky_light = voxel_light_extract_sky_light(vertex.light);
+
+    // blend the material colours at the vertices of smooth surfaces.
+    let material = voxel_material(voxel_data_extract_material_index(vertex.voxel_data));
+    let blend_material = voxel_material(voxel_data_extract_blend_material_index(vertex.voxel_data));
+    out.base_color = mix(material.base_color, blend_material.base_color, voxel_data_extract_blend_factor(vertex.voxel_data));
 
     return out;
 }
@@ -55,15 +69,27 @@ struct Fragment {
     /// The normalized normal of the voxel.
     @location(0) voxel_normal: vec3<f32>,
     /// The voxel data.
-    @location(1) voxel_data: u32,
+    @location(1) voxel_data: vec2<u32>,
     /// The world position of the voxel vertex.
     @location(2) world_position: vec3<f32>,
     @location(3) instance_index: u32,
+    /// The material base color blended between the vertices.
+    @location(4) base_color: vec4<f32>,
+    /// The texture coordinates, in voxel units.
+    @location(5) uv: vec2<f32>,
+    /// The intensity of the coloured block light lighting the voxel.
+    @location(6) block_light: vec3<f32>,
+    /// The intensity of the skylight reaching the voxel.
+    @location(7) sky_light: f32,
 };
 
 fn prepare_pbr_input_from_voxel_mat(voxel_mat: VoxelMat, frag: Fragment) -> PbrInput {
-    var base_color: vec4<f32> = voxel_mat.base_color;
-    base_color = base_color + hash(vec4<f32>(floor(frag.world_position - frag.voxel_normal * 0.5), 1.0)) * 0.0226;
+    // faces without a texture fall back to the material flat colour tinted with some noise.
+    // the texture is sampled in uniform control flow in any case.
+    let texture_layer = voxel_mat_texture_layer(voxel_mat, frag.voxel_normal);
+    let texture_color = textureSample(terrain_textures, terrain_textures_sampler, frag.uv, max(texture_layer, 0));
+    let flat_color = frag.base_color + hash(vec4<f32>(floor(frag.world_position - frag.voxel_normal * 0.5), 1.0)) * 0.0226;
+    let base_color = select(flat_color, texture_color, texture_layer >= 0);
 
     let voxel_world_normal = bevy_pbr::mesh_functions::mesh_normal_local_to_world(frag.voxel_normal, frag.instance_index);
 
@@ -86,17 +112,41 @@ fn prepare_pbr_input_from_voxel_mat(voxel_mat: VoxelMat, frag: Fragment) -> PbrI
     return pbr_input;
 }
 
+/// Returns a distinct false colour for an integer value, used by the debug views.
+fn debug_palette(value: f32) -> vec3<f32> {
+    return fract(value * vec3<f32>(0.6180339, 0.7548777, 0.5698403)) * 0.7 + 0.3;
+}
+
 @fragment
 fn fragment(frag: Fragment) -> @location(0) vec4<f32> {
-    let material = voxel_materials[voxel_data_extract_material_index(frag.voxel_data)];
+    let material = voxel_material(voxel_data_extract_material_index(frag.voxel_data));
 
     /// PBR lighting input data preparation
     var pbr_input = prepare_pbr_input_from_voxel_mat(material, frag);
-    let pbr_colour = tone_mapping(apply_pbr_lighting(pbr_input), view.color_grading);
-
-    // @todo: switch to bevy_pbr::fog
+    // the scene lighting only reaches the voxels exposed to the sky, so caves get dark without relying on shadow maps.
+    // block light from emissive materials lights up the voxel colour regardless of the scene lighting.
+    let sky_lit = apply_pbr_lighting(pbr_input) * vec4<f32>(vec3<f32>(frag.sky_light), 1.0);
+    let block_light = vec4<f32>(pbr_input.material.base_color.rgb * frag.block_light, 0.0);
+    let pbr_colour = tone_mapping(sky_lit + block_light, view.color_grading);
 
     //fragment distance from camera, used to determine amount of fog to apply.
     let fog_distance = distance(frag.world_position, view.world_position);
-    return ffog_apply_fog(fog_distance, f32(render_distance) * f32(TERRAIN_CHUNK_LENGTH), f32(TERRAIN_CHUNK_LENGTH), pbr_colour);
+    var colour = ffog_apply_fog(terrain_fog, fog_distance, frag.world_position.y, pbr_colour);
+
+    // debug views selected through the terrain material specialization.
+#ifdef TERRAIN_DEBUG_NORMALS
+    colour = vec4<f32>(pbr_input.N * 0.5 + 0.5, 1.0);
+#endif
+#ifdef TERRAIN_DEBUG_MATERIAL_ID
+    colour = vec4<f32>(debug_palette(f32(voxel_data_extract_material_index(frag.voxel_data))), 1.0);
+#endif
+#ifdef TERRAIN_DEBUG_CHUNK_TINT
+    let chunk = floor(mesh_functions::get_world_from_local(frag.instance_index)[3].xyz / f32(#{CHUNK_LENGTH}u));
+    colour = vec4<f32>(colour.rgb * debug_palette(dot(chunk, vec3<f32>(1.0, 57.0, 113.0))), colour.a);
+#endif
+#ifdef TERRAIN_DEBUG_LIGHT_LEVELS
+    colour = vec4<f32>(max(frag.block_light, vec3<f32>(frag.sky_light)), 1.0);
+#endif
+
+    return colour;
 }
@@ -0,0 +1,42 @@
+#import bevy_pbr::{
+    mesh_functions,
+    prepass_io::VertexOutput,
+    view_transformations::position_world_to_clip
+}
+
+// The terrain vertex layout, the default prepass vertex shader expects the uvs at location 1 where the voxel data is.
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let model = mesh_functions::get_world_from_local(vertex.instance_index);
+
+    var out: VertexOutput;
+    out.world_position = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
+    out.position = position_world_to_clip(out.world_position.xyz);
+
+#ifdef DEPTH_CLAMP_ORTHO
+    out.clip_position_unclamped = out.position;
+    out.position.z = min(out.position.z, 1.0);
+#endif
+#ifdef VERTEX_UVS_A
+    out.uv = vertex.uv;
+#endif
+#ifdef NORMAL_PREPASS_OR_DEFERRED_PREPASS
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    // the chunks never move once spawned.
+    out.previous_world_position = out.world_position;
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+
+    return out;
+}
@@ -8,11 +8,57 @@ struct VoxelMat {
     perceptual_roughness: f32,
     metallic: f32,
     reflectance: f32,
+    // texture array layers of the top, side and bottom faces (-1 for a flat colour).
+    top_texture: i32,
+    side_texture: i32,
+    bottom_texture: i32,
+};
+
+// Distances and heights are in voxels.
+struct TerrainFog {
+    color: vec4<f32>,
+    start: f32,
+    end: f32,
+    falloff: f32,
+    height_bottom: f32,
+    height_top: f32,
+    height_density: f32,
 };
 
 @group(2) @binding(0)
-var<uniform> render_distance: u32;
+var<uniform> terrain_fog: TerrainFog;
 
-// A GPU-suited representation of voxel materials.
+// A GPU-suited representation of voxel materials, indexed by the material ids of the vertex data.
+// Devices without storage buffers get a fixed-size uniform array instead.
+#ifdef VOXEL_MATERIALS_UNIFORM
+@group(2) @binding(1)
+var<uniform> voxel_materials: array<VoxelMat, #{VOXEL_MATERIALS_UNIFORM_CAPACITY}u>;
+#else
 @group(2) @binding(1)
-var<uniform> voxel_materials: array<VoxelMat, 256>;
\ No newline at end of file
+var<storage, read> voxel_materials: array<VoxelMat>;
+#endif
+
+// Returns the material with the specified id, falling back to the first material past the end of a uniform table.
+fn voxel_material(index: u32) -> VoxelMat {
+#ifdef VOXEL_MATERIALS_UNIFORM
+    return voxel_materials[select(0u, index, index < #{VOXEL_MATERIALS_UNIFORM_CAPACITY}u)];
+#else
+    return voxel_materials[index];
+#endif
+}
+
+// The textures of the voxel materials stacked into a texture array.
+@group(2) @binding(2)
+var terrain_textures: texture_2d_array<f32>;
+@group(2) @binding(3)
+var terrain_textures_sampler: sampler;
+
+// Returns the texture array layer for a face of a voxel material given its normal or -1 if the face has no texture.
+fn voxel_mat_texture_layer(voxel_mat: VoxelMat, normal: vec3<f32>) -> i32 {
+    if normal.y > 0.5 {
+        return voxel_mat.top_texture;
+    } else if normal.y < -0.5 {
+        return voxel_mat.bottom_texture;
+    }
+    return voxel_mat.side_texture;
+}
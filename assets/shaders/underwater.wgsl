@@ -0,0 +1,30 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct UnderwaterSettings {
+    color: vec4<f32>,
+    fog_distance: f32,
+    camera_near: f32,
+};
+
+@group(0) @binding(0) var screen_texture: texture_2d<f32>;
+@group(0) @binding(1) var screen_sampler: sampler;
+#ifdef MULTISAMPLED
+@group(0) @binding(2) var depth_texture: texture_depth_multisampled_2d;
+#else
+@group(0) @binding(2) var depth_texture: texture_depth_2d;
+#endif
+@group(0) @binding(3) var<uniform> settings: UnderwaterSettings;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let colour = textureSample(screen_texture, screen_sampler, in.uv);
+
+    // the depth of the opaque scene is a reversed infinite perspective depth, the sky being at 0.0.
+    let depth = textureLoad(depth_texture, vec2<i32>(in.position.xy), 0);
+    let distance = settings.camera_near / max(depth, 0.00001);
+
+    // the water tints whatever is close and hides what is further away.
+    let tinted = colour.rgb * mix(vec3<f32>(1.0), settings.color.rgb, 0.5);
+    let fog = 1.0 - exp(-3.0 * distance / settings.fog_distance);
+    return vec4<f32>(mix(tinted, settings.color.rgb, saturate(fog)), colour.a);
+}
@@ -1,18 +1,20 @@
 
 
 //
-// Layout of voxel information encoded into a single u32
+// Layout of voxel information encoded into two u32
 //
-//  00000000    00000000    00000000    00000000    
-//  XXXXXYYY    YYZZZZZ          NNN    MATERIAL
+//  x:  00000000 00000000    00000000 00000000
+//      BLEND_MAT            MATERIAL
 //
-// X: X position
-// Y: Y position
-// Z: Z position
-// N: normal index in the VOXEL_NORMALS array
+//  y:  00000000 00000000    00000000 00000000
+//                           BLEND         NNN
+//
+// BLEND: blend factor between MATERIAL and BLEND_MAT (0 - 255)
+// BLEND_MAT: index of the material blended in at this vertex (smooth surfaces only)
+// N: normal index in the VOXEL_NORMALS array (7 for smooth surfaces)
 // MATERIAL: material index in the palette
-// 
-// The remaining 5 free bits could be used to store UV data or additional info or even extend voxel material id size.
+//
+// The remaining 21 free bits of y could be used to store UV data or additional info.
 
 // An array of voxel face normals 
 var<private> VOXEL_NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
@@ -25,8 +27,8 @@ var<private> VOXEL_NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
 );
 
 // Extracts the normal face index from the encoded voxel data
-fn voxel_data_extract_normal(voxel_data: u32) -> vec3<f32> {
-    return VOXEL_NORMALS[voxel_data >> 8u & 7u];
+fn voxel_data_extract_normal(voxel_data: vec2<u32>) -> vec3<f32> {
+    return VOXEL_NORMALS[voxel_data.y & 7u];
 }
 
 // fn voxel_data_extract_position(voxel_data: u32) -> vec3<f32> {
@@ -38,6 +40,37 @@ fn voxel_data_extract_normal(voxel_data: u32) -> vec3<f32> {
 // }
 
 // Extracts the material index from the encoded voxel data
-fn voxel_data_extract_material_index(voxel_data: u32) -> u32 {
-    return voxel_data & 255u;
+fn voxel_data_extract_material_index(voxel_data: vec2<u32>) -> u32 {
+    return voxel_data.x & 65535u;
+}
+
+// Extracts the index of the material blended in at a vertex of a smooth surface.
+fn voxel_data_extract_blend_material_index(voxel_data: vec2<u32>) -> u32 {
+    return voxel_data.x >> 16u;
+}
+
+// Extracts the blend factor between the primary and blended material.
+fn voxel_data_extract_blend_factor(voxel_data: vec2<u32>) -> f32 {
+    return f32(voxel_data.y >> 8u & 255u) / 255.0;
+}
+
+//
+// Layout of the voxel light level encoded into a u32
+//
+//  00000000    00000000    SSSSBBBB    GGGGRRRR
+//
+// R, G, B: block light level of the red, green and blue channels (0 - 15)
+// S: skylight level (0 - 15)
+//
+
+// Extracts the block light from the encoded light level, with each channel attenuated by 20% per level below the maximum.
+fn voxel_light_extract_block_light(light: u32) -> vec3<f32> {
+    let levels = vec3<u32>(light & 15u, light >> 4u & 15u, light >> 8u & 15u);
+    let intensity = pow(vec3<f32>(0.8), vec3<f32>(15u - levels));
+    return select(intensity, vec3<f32>(0.0), levels == vec3<u32>(0u));
+}
+
+// Extracts the skylight intensity from the encoded light level, attenuated by 20% per level below the maximum.
+fn voxel_light_extract_sky_light(light: u32) -> f32 {
+    return pow(0.8, f32(15u - (light >> 12u & 15u)));
 }
@@ -0,0 +1,158 @@
+#import bevy_pbr::{
+    mesh_view_bindings::{globals, view},
+    mesh_functions,
+    pbr_functions::{calculate_view, apply_pbr_lighting},
+    pbr_types::{PbrInput, pbr_input_new},
+    prepass_utils,
+    view_transformations
+}
+#import bevy_core_pipeline::tonemapping::tone_mapping
+
+#import "shaders/voxel_data.wgsl"::{voxel_light_extract_block_light, voxel_light_extract_sky_light}
+#import "shaders/terrain_uniforms.wgsl"::TerrainFog
+#import "shaders/fog.wgsl"::ffog_apply_fog
+
+// Distances are in voxels.
+struct WaterSettings {
+    shallow_color: vec4<f32>,
+    deep_color: vec4<f32>,
+    absorption: f32,
+    wave_height: f32,
+    wave_length: f32,
+    wave_speed: f32,
+    ripple_strength: f32,
+    reflectance: f32,
+};
+
+@group(2) @binding(0)
+var<uniform> water: WaterSettings;
+
+@group(2) @binding(1)
+var<uniform> water_fog: TerrainFog;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(4) light: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) block_light: vec3<f32>,
+    @location(3) sky_light: f32,
+};
+
+// Returns the height of the waves (0.0 - 1.0) at the specified world position along with its derivatives along x and z.
+fn water_waves(position: vec2<f32>, length: f32, time: f32) -> vec3<f32> {
+    // a few sine waves going in different directions so the pattern doesn't repeat visibly.
+    var directions = array<vec2<f32>, 3>(vec2<f32>(1.0, 0.0), vec2<f32>(0.6, 0.8), vec2<f32>(-0.7, 0.7));
+    var scales = array<f32, 3>(1.0, 0.63, 0.41);
+
+    var height = 0.0;
+    var derivatives = vec2<f32>(0.0);
+    for (var i = 0u; i < 3u; i++) {
+        let frequency = 6.2831853 / (length * scales[i]);
+        let phase = dot(directions[i], position) * frequency + time * (1.0 + f32(i) * 0.37);
+        height += sin(phase) / 3.0;
+        derivatives += directions[i] * frequency * cos(phase) / 3.0;
+    }
+
+    return vec3<f32>(height * 0.5 + 0.5, derivatives * 0.5);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let model = mesh_functions::get_world_from_local(vertex.instance_index);
+    var world_position = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0)).xyz;
+
+    // the surface is only lowered so the waves never uncover the voxels below the water surface.
+    if vertex.normal.y > 0.5 {
+        let waves = water_waves(world_position.xz, water.wave_length, globals.time * water.wave_speed);
+        world_position.y -= water.wave_height * waves.x;
+    }
+
+    var out: VertexOutput;
+    out.clip_position = view_transformations::position_world_to_clip(world_position);
+    out.world_position = world_position;
+    out.normal = vertex.normal;
+    out.block_light = voxel_light_extract_block_light(vertex.light);
+    out.sky_light = voxel_light_extract_sky_light(vertex.light);
+    return out;
+}
+
+struct Fragment {
+    @builtin(position) frag_coord: vec4<f32>,
+    @builtin(front_facing) front_facing: bool,
+#ifdef MULTISAMPLED
+    @builtin(sample_index) sample_index: u32,
+#endif
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) block_light: vec3<f32>,
+    @location(3) sky_light: f32,
+};
+
+// Returns the thickness of water the view ray goes through behind the fragment.
+fn water_thickness(frag: Fragment) -> f32 {
+#ifdef DEPTH_PREPASS
+#ifdef MULTISAMPLED
+    let sample_index = frag.sample_index;
+#else
+    let sample_index = 0u;
+#endif
+    let scene_depth = prepass_utils::prepass_depth(frag.frag_coord, sample_index);
+    let scene_z = view_transformations::depth_ndc_to_view_z(scene_depth);
+    let surface_z = view_transformations::depth_ndc_to_view_z(frag.frag_coord.z);
+    return max(surface_z - scene_z, 0.0);
+#else
+    // without the depth of the scene the water is assumed to be a few voxels deep.
+    return 4.0;
+#endif
+}
+
+@fragment
+fn fragment(frag: Fragment) -> @location(0) vec4<f32> {
+    // animated ripples perturb the normal of the surface, the faces of the water seen from below are left flat.
+    var normal = normalize(frag.normal);
+    if frag.normal.y > 0.5 {
+        let ripples = water_waves(frag.world_position.xz * 4.0, water.wave_length, globals.time * water.wave_speed * 2.0);
+        normal = normalize(vec3<f32>(-ripples.y * water.ripple_strength, 1.0, -ripples.z * water.ripple_strength));
+    }
+    if !frag.front_facing {
+        normal = -normal;
+    }
+
+    // the light gets absorbed as it travels through the water, turning the shallow colour into the deep one.
+    let thickness = select(0.0, water_thickness(frag), frag.front_facing);
+    let absorbed = 1.0 - exp(-thickness * water.absorption);
+    var base_color = mix(water.shallow_color, vec4<f32>(water.deep_color.rgb, 1.0), absorbed);
+
+    let V = calculate_view(vec4<f32>(frag.world_position, 1.0), view.clip_from_view[3].w == 1.0);
+
+    // Schlick approximation of the Fresnel effect, reflecting the sky at grazing angles.
+    let fresnel = water.reflectance + (1.0 - water.reflectance) * pow(1.0 - saturate(dot(normal, V)), 5.0);
+    let reflection = water_fog.color.rgb * frag.sky_light;
+    base_color = vec4<f32>(mix(base_color.rgb, reflection, fresnel), mix(base_color.a, 1.0, fresnel));
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = base_color;
+    pbr_input.material.perceptual_roughness = 0.08;
+    pbr_input.material.reflectance = water.reflectance;
+    pbr_input.frag_coord = frag.frag_coord;
+    pbr_input.world_position = vec4<f32>(frag.world_position, 1.0);
+    pbr_input.world_normal = normal;
+    pbr_input.is_orthographic = view.clip_from_view[3].w == 1.0;
+    pbr_input.N = normal;
+    pbr_input.V = V;
+
+    // lit the same way as the terrain, the scene lighting only reaches the water exposed to the sky.
+    let sky_lit = apply_pbr_lighting(pbr_input) * vec4<f32>(vec3<f32>(frag.sky_light), 1.0);
+    let block_light = vec4<f32>(base_color.rgb * frag.block_light, 0.0);
+    let colour = tone_mapping(sky_lit + block_light, view.color_grading);
+
+    let fog_distance = distance(frag.world_position, view.world_position);
+    return ffog_apply_fog(water_fog, fog_distance, frag.world_position.y, colour);
+}
@@ -1,10 +1,14 @@
+use std::time::Duration;
+
 use bevy::{
     diagnostic::{DiagnosticsStore, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
+    ecs::change_detection::DetectChangesMut,
     input::{keyboard::KeyboardInput, ButtonState},
     prelude::{
-        Color, EventReader, IntoSystemConfigs, IntoSystemSetConfigs, KeyCode, Plugin, Res, ResMut,
-        Resource, SystemSet, Update,
+        Color, EventReader, Gizmos, IntoSystemConfigs, IntoSystemSetConfigs, KeyCode, Plugin, Res,
+        ResMut, Resource, SystemSet, Transform, Update, Vec3,
     },
+    render::{render_resource::WgpuFeatures, renderer::RenderDevice},
 };
 
 use bevy_egui::{
@@ -13,8 +17,13 @@ use bevy_egui::{
 };
 
 use crate::voxel::{
-    material::VoxelMaterialRegistry, ChunkCommandQueue, ChunkEntities, ChunkLoadRadius,
-    CurrentLocalPlayerChunk, DirtyChunks,
+    material::VoxelMaterialRegistry,
+    render::{
+        CulledChunkMesher, GreedyChunkMesher, SelectedChunkMesher, TerrainDebugSettings,
+        TerrainDebugView, TerrainFogSettings, TerrainHeightFog,
+    },
+    ChunkCommandQueue, ChunkCullingSettings, ChunkEntities, ChunkLoadRadius, ChunkMeshingSet,
+    CurrentLocalPlayerChunk, DirtyChunks, SkyShadowSettings, TimeOfDay, CHUNK_LENGTH,
 };
 
 fn display_debug_stats(mut egui: EguiContexts, diagnostics: Res<DiagnosticsStore>) {
@@ -71,6 +80,234 @@ fn display_chunk_stats(
     });
 }
 
+fn display_meshing_settings(
+    mut egui: EguiContexts,
+    mut ui_state: ResMut<DebugUIState>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut chunk_mesher: ResMut<SelectedChunkMesher>,
+    loaded_chunks: Res<ChunkEntities>,
+) {
+    egui::Window::new("meshing stuff").show(egui.ctx_mut(), |ui| {
+        let selected_mesher = ui_state.selected_mesher;
+        egui::containers::ComboBox::from_label("Chunk mesher")
+            .selected_text(format!("{:?}", ui_state.selected_mesher))
+            .show_ui(ui, |content| {
+                content.selectable_value(
+                    &mut ui_state.selected_mesher,
+                    DebugChunkMesher::Greedy,
+                    "Greedy",
+                );
+                content.selectable_value(
+                    &mut ui_state.selected_mesher,
+                    DebugChunkMesher::Culled,
+                    "Culled",
+                );
+            });
+
+        if selected_mesher != ui_state.selected_mesher {
+            *chunk_mesher = match ui_state.selected_mesher {
+                DebugChunkMesher::Greedy => SelectedChunkMesher::new(GreedyChunkMesher::default()),
+                DebugChunkMesher::Culled => SelectedChunkMesher::new(CulledChunkMesher::default()),
+            };
+            loaded_chunks
+                .iter_keys()
+                .for_each(|key| dirty_chunks.mark_dirty(*key));
+        }
+    });
+}
+
+fn display_rendering_settings(
+    mut egui: EguiContexts,
+    mut fog: ResMut<TerrainFogSettings>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut shadows: ResMut<SkyShadowSettings>,
+    mut culling: ResMut<ChunkCullingSettings>,
+    mut debug_view: ResMut<TerrainDebugSettings>,
+    render_device: Res<RenderDevice>,
+) {
+    egui::Window::new("rendering stuff").show(egui.ctx_mut(), |ui| {
+        ui.heading("Debug views");
+
+        // written back only when edited as changing the view specializes the terrain pipeline again.
+        let mut debug_settings = *debug_view;
+        egui::containers::ComboBox::from_label("Terrain view")
+            .selected_text(debug_settings.view.name())
+            .show_ui(ui, |content| {
+                for view in TerrainDebugView::ALL {
+                    content.selectable_value(&mut debug_settings.view, view, view.name());
+                }
+            });
+        ui.add_enabled_ui(
+            render_device
+                .features()
+                .contains(WgpuFeatures::POLYGON_MODE_LINE),
+            |ui| ui.checkbox(&mut debug_settings.wireframe, "Wireframe"),
+        );
+        ui.checkbox(&mut debug_settings.chunk_borders, "Chunk borders");
+
+        if debug_settings != *debug_view {
+            *debug_view = debug_settings;
+        }
+
+        ui.heading("Time of day");
+
+        // written back only when edited as the sky systems react to the time of day changing.
+        let mut time = time_of_day.clone();
+        ui.checkbox(&mut time.paused, "Pause day cycle");
+        ui.checkbox(
+            &mut time.drives_fog_color,
+            "Fog color follows the time of day",
+        );
+        ui.label("Hour");
+        ui.add(Slider::new(&mut time.hour, 0.0..=24.0));
+        let mut day_length = time.day_length.as_secs_f32() / 60.0;
+        ui.label("Day length (minutes)");
+        if ui
+            .add(Slider::new(&mut day_length, 1.0..=60.0).logarithmic(true))
+            .changed()
+        {
+            time.day_length = Duration::from_secs_f32(day_length * 60.0);
+        }
+        ui.horizontal(|ui| {
+            for (label, hour) in [
+                ("Dawn", 6.0),
+                ("Noon", 12.0),
+                ("Dusk", 18.0),
+                ("Midnight", 0.0),
+            ] {
+                if ui.button(label).clicked() {
+                    time.hour = hour;
+                }
+            }
+        });
+
+        if time != *time_of_day {
+            *time_of_day = time;
+        }
+
+        ui.heading("Culling");
+        let mut culling_enabled = culling.enabled;
+        ui.checkbox(
+            &mut culling_enabled,
+            "Cull chunks hidden behind the terrain",
+        );
+        if culling_enabled != culling.enabled {
+            culling.enabled = culling_enabled;
+        }
+
+        ui.heading("Shadows");
+
+        // written back only when edited as changing the settings rebuilds the shadow cascades.
+        let mut shadow_settings = shadows.clone();
+        ui.checkbox(&mut shadow_settings.enabled, "Sky light shadows");
+        ui.add_enabled_ui(shadow_settings.enabled, |ui| {
+            ui.label("Cascades");
+            ui.add(Slider::new(&mut shadow_settings.num_cascades, 1..=4));
+            ui.label("Shadow distance (fraction of the load radius)");
+            ui.add(Slider::new(&mut shadow_settings.distance, 0.1..=1.0));
+            ui.label("First cascade far bound");
+            ui.add(Slider::new(
+                &mut shadow_settings.first_cascade_far_bound,
+                4.0..=128.0,
+            ));
+            ui.label("Cascade overlap");
+            ui.add(Slider::new(
+                &mut shadow_settings.overlap_proportion,
+                0.0..=0.5,
+            ));
+        });
+
+        if shadow_settings != *shadows {
+            *shadows = shadow_settings;
+        }
+
+        ui.heading("Fog");
+
+        // only write back the settings when they are edited to not update the terrain material every frame.
+        let mut settings = fog.clone();
+
+        // the fog color is only editable when it doesn't follow the time of day.
+        ui.add_enabled_ui(!time_of_day.drives_fog_color, |ui| {
+            let color = settings.color.to_linear();
+            let mut editable_color =
+                Rgba::from_rgba_unmultiplied(color.red, color.green, color.blue, color.alpha);
+            ui.horizontal(|ui| {
+                ui.label("Fog color");
+                if egui::widgets::color_picker::color_edit_button_rgba(
+                    ui,
+                    &mut editable_color,
+                    egui::color_picker::Alpha::Opaque,
+                )
+                .changed()
+                {
+                    let [r, g, b, a] = editable_color.to_array();
+                    settings.color = Color::linear_rgba(r, g, b, a);
+                }
+            });
+        });
+
+        ui.checkbox(
+            &mut settings.follow_load_radius,
+            "Fog end follows chunk loading radius",
+        );
+        ui.label("Fog start (chunks)");
+        ui.add(Slider::new(&mut settings.start, 0.0..=settings.end));
+        ui.add_enabled_ui(!settings.follow_load_radius, |ui| {
+            ui.label("Fog end (chunks)");
+            ui.add(Slider::new(&mut settings.end, 1.0..=32.0));
+        });
+        ui.label("Fog falloff");
+        ui.add(Slider::new(&mut settings.falloff, 0.25..=4.0));
+
+        let mut height_fog_enabled = settings.height_fog.is_some();
+        ui.checkbox(&mut height_fog_enabled, "Height fog");
+        match (height_fog_enabled, &mut settings.height_fog) {
+            (true, Some(height_fog)) => {
+                ui.label("Height fog bottom");
+                ui.add(Slider::new(&mut height_fog.bottom, 0.0..=height_fog.top));
+                ui.label("Height fog top");
+                ui.add(Slider::new(&mut height_fog.top, 0.0..=288.0));
+                ui.label("Height fog density");
+                ui.add(Slider::new(&mut height_fog.density, 0.0..=1.0));
+            }
+            (true, None) => settings.height_fog = Some(TerrainHeightFog::default()),
+            (false, _) => settings.height_fog = None,
+        }
+
+        if settings != *fog {
+            *fog = settings;
+        }
+    });
+}
+
+/// Outlines the chunk the player is in and its neighbours.
+fn draw_chunk_borders(mut gizmos: Gizmos, player_chunk: Res<CurrentLocalPlayerChunk>) {
+    let chunk_size = Vec3::splat(CHUNK_LENGTH as f32);
+
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let offset = Vec3::new(x as f32, y as f32, z as f32);
+                let center = player_chunk.chunk_min.as_vec3() + (offset + 0.5) * chunk_size;
+                let color = if offset == Vec3::ZERO {
+                    Color::srgb(1.0, 0.85, 0.1)
+                } else {
+                    Color::srgb(0.25, 0.4, 1.0)
+                };
+
+                gizmos.cuboid(
+                    Transform::from_translation(center).with_scale(chunk_size),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+fn display_chunk_borders_criteria(debug_view: Res<TerrainDebugSettings>) -> bool {
+    debug_view.chunk_borders
+}
+
 fn display_debug_ui_criteria(ui_state: Res<DebugUIState>) -> bool {
     ui_state.display_debug_info
 }
@@ -101,6 +338,9 @@ fn display_material_editor(
     mut ui_state: ResMut<DebugUIState>,
     mut materials: ResMut<VoxelMaterialRegistry>,
 ) {
+    // the registry is only flagged as changed when a property got edited, which patches the GPU material table.
+    let mut changed = false;
+
     egui::Window::new("material editor").show(egui.ctx_mut(), |ui| {
         ui.heading("Select material");
         egui::containers::ComboBox::from_label("Material")
@@ -118,7 +358,7 @@ fn display_material_editor(
                     .for_each(|(mat_index, mat)| {
                         content.selectable_value(
                             &mut ui_state.selected_mat,
-                            mat_index as u8,
+                            mat_index as u16,
                             mat.name,
                         );
                     });
@@ -129,7 +369,10 @@ fn display_material_editor(
         // base_color
         ui.label("Base color");
 
-        let selected_mat = materials.get_mut_by_id(ui_state.selected_mat).unwrap();
+        let selected_mat = materials
+            .bypass_change_detection()
+            .get_mut_by_id(ui_state.selected_mat)
+            .unwrap();
 
         let base_color = selected_mat.base_color.to_linear();
         let mut editable_color = Rgba::from_rgba_unmultiplied(
@@ -138,39 +381,57 @@ fn display_material_editor(
             base_color.blue,
             base_color.alpha,
         );
-        egui::widgets::color_picker::color_edit_button_rgba(
+        if egui::widgets::color_picker::color_edit_button_rgba(
             ui,
             &mut editable_color,
             egui::color_picker::Alpha::Opaque,
-        );
-        let [r, g, b, a] = editable_color.to_array();
-        selected_mat.base_color = Color::srgba(r, g, b, a);
+        )
+        .changed()
+        {
+            let [r, g, b, a] = editable_color.to_array();
+            selected_mat.base_color = Color::linear_rgba(r, g, b, a);
+            changed = true;
+        }
         ui.label("Perceptual Roughness");
-        ui.add(Slider::new(
-            &mut selected_mat.perceptual_roughness,
-            0.0..=1.0f32,
-        ));
+        changed |= ui
+            .add(Slider::new(
+                &mut selected_mat.perceptual_roughness,
+                0.0..=1.0f32,
+            ))
+            .changed();
         ui.label("Metallic");
-        ui.add(Slider::new(&mut selected_mat.metallic, 0.0..=1.0f32));
+        changed |= ui
+            .add(Slider::new(&mut selected_mat.metallic, 0.0..=1.0f32))
+            .changed();
         ui.label("Reflectance");
-        ui.add(Slider::new(&mut selected_mat.reflectance, 0.0..=1.0f32));
+        changed |= ui
+            .add(Slider::new(&mut selected_mat.reflectance, 0.0..=1.0f32))
+            .changed();
         ui.label("Emissive");
 
-        let emissive = selected_mat.base_color.to_linear();
+        let emissive = selected_mat.emissive.to_linear();
         let mut editable_emissive = Rgba::from_rgba_unmultiplied(
             emissive.red,
             emissive.green,
             emissive.blue,
             emissive.alpha,
         );
-        egui::widgets::color_picker::color_edit_button_rgba(
+        if egui::widgets::color_picker::color_edit_button_rgba(
             ui,
             &mut editable_emissive,
             egui::color_picker::Alpha::Opaque,
-        );
-        let [r, g, b, a] = editable_emissive.to_array();
-        selected_mat.emissive = Color::srgba(r, g, b, a);
+        )
+        .changed()
+        {
+            let [r, g, b, a] = editable_emissive.to_array();
+            selected_mat.emissive = Color::linear_rgba(r, g, b, a);
+            changed = true;
+        }
     });
+
+    if changed {
+        materials.set_changed();
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, SystemSet)]
@@ -194,11 +455,18 @@ impl Plugin for DebugUIPlugins {
                     display_material_editor
                         .in_set(DebugUISet::Display)
                         .run_if(display_mat_debug_ui_criteria),
+                    draw_chunk_borders.run_if(display_chunk_borders_criteria),
                 ),
             )
             .add_systems(
                 Update,
-                (display_debug_stats, display_chunk_stats)
+                (
+                    display_debug_stats,
+                    display_chunk_stats,
+                    // chunks need to be marked dirty before the meshing systems run.
+                    display_meshing_settings.before(ChunkMeshingSet),
+                    display_rendering_settings,
+                )
                     .in_set(DebugUISet::Display)
                     .distributive_run_if(display_debug_ui_criteria),
             )
@@ -212,11 +480,19 @@ impl Plugin for DebugUIPlugins {
     }
 }
 
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+enum DebugChunkMesher {
+    #[default]
+    Greedy,
+    Culled,
+}
+
 #[derive(Default, Resource)]
 struct DebugUIState {
     display_debug_info: bool,
     display_mat_debug: bool,
 
     // DD
-    pub selected_mat: u8,
+    pub selected_mat: u16,
+    selected_mesher: DebugChunkMesher,
 }
@@ -6,7 +6,10 @@
 
 use std::f32::consts::PI;
 
-use bevy::{core_pipeline::fxaa::Fxaa, prelude::*};
+use bevy::{
+    core_pipeline::{fxaa::Fxaa, prepass::DepthPrepass},
+    prelude::*,
+};
 
 mod debug;
 mod voxel;
@@ -32,6 +35,9 @@ fn setup(mut cmds: Commands) {
     })
     .insert(voxel::player::PlayerController::default())
     .insert(Fxaa::default())
+    // the water and underwater shading need the depth of the opaque terrain.
+    .insert(DepthPrepass)
+    .insert(voxel::render::UnderwaterSettings::default())
     .insert(bevy_atmosphere::plugin::AtmosphereCamera::default());
 
     cmds.insert_resource(AmbientLight {
@@ -0,0 +1,58 @@
+/// The light level of a voxel.
+///
+/// Block light is stored as three 4 bit red, green and blue channels so emissive materials can cast coloured light,
+/// followed by a 4 bit skylight channel.
+#[derive(Copy, Clone, Hash, Debug, Default, PartialEq, Eq)]
+pub struct VoxelLight(pub u16);
+
+impl VoxelLight {
+    /// The maximum level of a light channel.
+    pub const MAX_LEVEL: u8 = 15;
+
+    /// The index of the skylight channel.
+    pub const SKY_CHANNEL: usize = 3;
+
+    /// The number of light channels, block light and skylight included.
+    pub const CHANNELS: usize = 4;
+
+    pub const NONE: Self = Self(0);
+
+    /// Returns the light with the specified red, green and blue block light levels.
+    #[inline]
+    pub const fn from_block_light(levels: [u8; 3]) -> Self {
+        Self(
+            (levels[0] & 15) as u16
+                | ((levels[1] & 15) as u16) << 4
+                | ((levels[2] & 15) as u16) << 8,
+        )
+    }
+
+    /// Returns the level of a light channel.
+    #[inline]
+    pub const fn level(self, channel: usize) -> u8 {
+        (self.0 >> (channel * 4) & 15) as u8
+    }
+
+    /// Sets the level of a light channel.
+    #[inline]
+    pub fn set_level(&mut self, channel: usize, level: u8) {
+        let shift = channel * 4;
+        self.0 = self.0 & !(15 << shift) | ((level & 15) as u16) << shift;
+    }
+
+    /// Returns the skylight level.
+    #[inline]
+    pub const fn sky_light(self) -> u8 {
+        self.level(Self::SKY_CHANNEL)
+    }
+
+    /// Returns the channel-wise maximum of two lights.
+    #[inline]
+    pub fn max(self, other: Self) -> Self {
+        let mut light = self;
+        for channel in 0..Self::CHANNELS {
+            light.set_level(channel, self.level(channel).max(other.level(channel)));
+        }
+        light
+    }
+}
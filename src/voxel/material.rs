@@ -20,11 +20,14 @@ pub struct MaterialRegistryInfo {
     pub perceptual_roughness: f32,
     pub metallic: f32,
     pub reflectance: f32,
+    pub meshing_mode: VoxelMeshingMode,
+    pub shape: VoxelShape,
+    pub textures: VoxelMaterialTextures,
 }
 
 /// Helper / marker trait for voxel materials.
 pub trait VoxelMaterial {
-    const ID: u8;
+    const ID: u16;
 
     fn into_voxel() -> Voxel {
         Voxel(Self::ID)
@@ -39,11 +42,62 @@ macro_rules! voxel_material {
             pub const NAME: &'static str = stringify!($types);
         }
         impl $crate::voxel::material::VoxelMaterial for $types {
-            const ID: u8 = $id;
+            const ID: u16 = $id;
         }
     };
 }
 
+/// Describes how the voxels of a material are turned into mesh geometry.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoxelMeshingMode {
+    /// Voxels are meshed as cubes whose faces get greedily merged.
+    #[default]
+    Blocky,
+    /// Voxels are meshed as a smooth surface extracted from a signed density field using surface nets.
+    Smooth,
+}
+
+/// The geometry of the voxels of a material.
+/// Voxels with a shape other than [`VoxelShape::Cube`] don't hide the faces of their neighbours.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum VoxelShape {
+    /// A full cube.
+    #[default]
+    Cube,
+    /// Two diagonal planes crossing each other, mostly used for plants.
+    Cross,
+    /// The bottom half of a cube.
+    Slab,
+    /// A stair whose steps go up towards +Z.
+    Stair,
+    /// A small model made of boxes in the voxel local space (`[0; 3]` to `[1; 3]`).
+    Custom(&'static [VoxelModelBox]),
+}
+
+/// An axis aligned box of a custom voxel model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelModelBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+/// Paths (relative to the `assets` folder) of the textures applied to the faces of the voxels of a material.
+/// Faces without a texture are shaded using the flat base colour of the material.
+/// All the textures used by the registered materials must have the same size.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxelMaterialTextures {
+    pub top: Option<&'static str>,
+    pub side: Option<&'static str>,
+    pub bottom: Option<&'static str>,
+}
+
+impl VoxelMaterialTextures {
+    /// Returns the texture paths in the top, side, bottom order.
+    pub fn faces(&self) -> [Option<&'static str>; 3] {
+        [self.top, self.side, self.bottom]
+    }
+}
+
 bitflags! {
     pub struct VoxelMaterialFlags : u32 {
         const SOLID = 0;
@@ -69,11 +123,11 @@ pub struct VoxelMaterialRegistry {
 #[allow(dead_code)]
 impl VoxelMaterialRegistry {
     #[inline]
-    pub fn get_by_id(&self, id: u8) -> Option<&MaterialRegistryInfo> {
+    pub fn get_by_id(&self, id: u16) -> Option<&MaterialRegistryInfo> {
         self.materials.get(id as usize)
     }
 
-    pub fn get_mut_by_id(&mut self, id: u8) -> Option<&mut MaterialRegistryInfo> {
+    pub fn get_mut_by_id(&mut self, id: u16) -> Option<&mut MaterialRegistryInfo> {
         self.materials.get_mut(id as usize)
     }
 
@@ -83,11 +137,23 @@ impl VoxelMaterialRegistry {
             .map(|x| self.materials.get(*x).unwrap())
     }
 
-    pub fn get_id_for_type<M: 'static>(&self) -> Option<u8> {
-        self.mat_ids.get(&TypeId::of::<M>()).map(|x| *x as u8)
+    pub fn get_id_for_type<M: 'static>(&self) -> Option<u16> {
+        self.mat_ids.get(&TypeId::of::<M>()).map(|x| *x as u16)
+    }
+
+    /// Returns the id of the material with the specified name, e.g. to refer to materials from data files.
+    pub fn get_id_by_name(&self, name: &str) -> Option<u16> {
+        self.materials
+            .iter()
+            .position(|mat| mat.name == name)
+            .map(|x| x as u16)
     }
 
     pub fn register_material<M: 'static>(&mut self, mat: MaterialRegistryInfo) {
+        assert!(
+            self.materials.len() <= u16::MAX as usize,
+            "voxel material ids are limited to 16 bits"
+        );
         self.materials.push(mat);
         info!(
             "Registered material {:?} (ID: {})",
@@ -19,3 +19,6 @@ pub mod sdf;
 
 mod voxel;
 pub use voxel::*;
+
+mod light;
+pub use light::*;
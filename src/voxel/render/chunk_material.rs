@@ -1,11 +1,32 @@
-use crate::voxel::material::VoxelMaterialRegistry;
+use super::{
+    GpuTerrainFog, TerrainDebugKey, TerrainDebugViewPlugin, TerrainFogPlugin, TerrainFogSettings,
+    TerrainTextureArray, TerrainTexturesPlugin, TerrainTexturesSet, WaterMaterialPlugin,
+};
+use crate::voxel::{
+    material::{MaterialRegistryInfo, VoxelMaterialRegistry},
+    CHUNK_LENGTH,
+};
 use bevy::{
+    log::warn_once,
     prelude::*,
     reflect::TypePath,
     render::{
         extract_component::ExtractComponent,
         mesh::MeshVertexAttribute,
-        render_resource::{AsBindGroup, ShaderType, VertexFormat},
+        render_asset::RenderAssets,
+        render_resource::{
+            binding_types::{
+                sampler, storage_buffer_read_only_sized, texture_2d_array, uniform_buffer,
+                uniform_buffer_sized,
+            },
+            encase::{StorageBuffer, UniformBuffer},
+            AsBindGroup, AsBindGroupError, BindGroupLayout, BindGroupLayoutEntries,
+            BindGroupLayoutEntry, BindGroupLayoutEntryBuilder, Buffer, BufferInitDescriptor,
+            BufferUsages, OwnedBindingResource, PolygonMode, SamplerBindingType, ShaderDefVal,
+            ShaderStages, ShaderType, TextureSampleType, UnpreparedBindGroup, VertexFormat,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::{FallbackImage, GpuImage},
     },
 };
 
@@ -15,10 +36,13 @@ pub struct VoxelTerrainMesh;
 
 impl VoxelTerrainMesh {
     pub const ATTRIBUTE_DATA: MeshVertexAttribute =
-        MeshVertexAttribute::new("Vertex_Data", 0x696969, VertexFormat::Uint32);
+        MeshVertexAttribute::new("Vertex_Data", 0x696969, VertexFormat::Uint32x2);
+    /// The light level of the vertex, as packed by [`VoxelLight`](crate::voxel::VoxelLight).
+    pub const ATTRIBUTE_LIGHT: MeshVertexAttribute =
+        MeshVertexAttribute::new("Vertex_Light", 0x4c49474854, VertexFormat::Uint32);
 }
 
-#[derive(ShaderType, Clone, Copy, Debug, Default)]
+#[derive(ShaderType, Clone, Copy, Debug, Default, PartialEq)]
 pub struct GpuVoxelMaterial {
     base_color: LinearRgba,
     flags: u32,
@@ -26,25 +50,296 @@ pub struct GpuVoxelMaterial {
     perceptual_roughness: f32,
     metallic: f32,
     reflectance: f32,
+    // texture array layers of the faces (-1 for a flat colour).
+    top_texture: i32,
+    side_texture: i32,
+    bottom_texture: i32,
+}
+
+impl GpuVoxelMaterial {
+    /// The entry of the ids without a registered material.
+    fn placeholder() -> Self {
+        Self {
+            base_color: Color::WHITE.into(),
+            flags: 0,
+            top_texture: -1,
+            side_texture: -1,
+            bottom_texture: -1,
+            ..Default::default()
+        }
+    }
+
+    fn new(material: &MaterialRegistryInfo, terrain_textures: &TerrainTextureArray) -> Self {
+        Self {
+            base_color: material.base_color.into(),
+            flags: material.flags.bits(),
+            emissive: material.emissive.into(),
+            perceptual_roughness: material.perceptual_roughness,
+            metallic: material.metallic,
+            reflectance: material.reflectance,
+            top_texture: terrain_textures.layer_of(material.textures.top),
+            side_texture: terrain_textures.layer_of(material.textures.side),
+            bottom_texture: terrain_textures.layer_of(material.textures.bottom),
+        }
+    }
+}
+
+/// How the [`GpuVoxelMaterialTable`] is bound to the terrain shaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoxelMaterialTableBinding {
+    /// A runtime-sized storage buffer growing along with the [`VoxelMaterialRegistry`].
+    Storage,
+    /// A fixed-size uniform array for the devices without storage buffers (such as WebGL2).
+    /// The materials past its capacity are rendered with a placeholder.
+    Uniform { capacity: u32 },
+}
+
+impl VoxelMaterialTableBinding {
+    /// The most materials held by a uniform table, even if the device allows for bigger uniform buffers.
+    pub const MAX_UNIFORM_CAPACITY: u32 = 256;
+
+    /// Returns the binding supported by the specified device, preferring storage buffers.
+    pub fn for_device(render_device: &RenderDevice) -> Self {
+        let limits = render_device.limits();
+        if limits.max_storage_buffers_per_shader_stage > 0 {
+            return Self::Storage;
+        }
+
+        let capacity =
+            limits.max_uniform_buffer_binding_size as u64 / GpuVoxelMaterial::min_size().get();
+        Self::Uniform {
+            capacity: (capacity as u32).min(Self::MAX_UNIFORM_CAPACITY),
+        }
+    }
+
+    fn buffer_usage(self) -> BufferUsages {
+        match self {
+            Self::Storage => BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            Self::Uniform { .. } => BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        }
+    }
+
+    fn layout_entry(self) -> BindGroupLayoutEntryBuilder {
+        match self {
+            Self::Storage => storage_buffer_read_only_sized(false, None),
+            Self::Uniform { .. } => uniform_buffer_sized(false, None),
+        }
+    }
+
+    /// Returns the shader defs selecting the matching declaration of the table in `terrain_uniforms.wgsl`.
+    fn shader_defs(self) -> Vec<ShaderDefVal> {
+        match self {
+            Self::Storage => vec![],
+            Self::Uniform { capacity } => vec![
+                "VOXEL_MATERIALS_UNIFORM".into(),
+                ShaderDefVal::UInt("VOXEL_MATERIALS_UNIFORM_CAPACITY".into(), capacity),
+            ],
+        }
+    }
+}
+
+/// The table of the voxel materials indexed by the material ids of the vertex data.
+///
+/// The table lives in a single GPU buffer shared by every chunk, only the entries of the materials which changed get uploaded.
+/// The buffer only gets reallocated when a storage table needs to grow to fit more materials.
+#[derive(Resource)]
+pub struct GpuVoxelMaterialTable {
+    binding: VoxelMaterialTableBinding,
+    buffer: Buffer,
+    entries: Vec<GpuVoxelMaterial>,
+}
+
+impl GpuVoxelMaterialTable {
+    /// The initial capacity of a storage table.
+    const MIN_STORAGE_CAPACITY: usize = 64;
+
+    pub fn binding(&self) -> VoxelMaterialTableBinding {
+        self.binding
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    fn create_buffer(
+        binding: VoxelMaterialTableBinding,
+        entries: &[GpuVoxelMaterial],
+        render_device: &RenderDevice,
+    ) -> Buffer {
+        // both bindings share the same layout as the stride of the entries is a multiple of 16 bytes.
+        let mut bytes = StorageBuffer::new(Vec::new());
+        bytes.write(entries).unwrap();
+
+        render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("voxel_material_table"),
+            contents: bytes.as_ref(),
+            usage: binding.buffer_usage(),
+        })
+    }
+
+    /// Replaces the entries of the table, writing the runs of entries which differ from the current ones to the GPU buffer.
+    /// Returns whether the buffer had to be reallocated to fit the entries.
+    fn update(
+        &mut self,
+        materials: &[GpuVoxelMaterial],
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) -> bool {
+        let capacity = match self.binding {
+            VoxelMaterialTableBinding::Storage => {
+                materials.len().next_power_of_two().max(self.entries.len())
+            }
+            VoxelMaterialTableBinding::Uniform { capacity } => capacity as usize,
+        };
+
+        if materials.len() > capacity {
+            warn_once!(
+                "The voxel material table only fits {} of the {} registered materials on this device",
+                capacity,
+                materials.len()
+            );
+        }
+
+        let mut entries = vec![GpuVoxelMaterial::placeholder(); capacity];
+        let len = materials.len().min(capacity);
+        entries[..len].copy_from_slice(&materials[..len]);
+
+        if capacity != self.entries.len() {
+            self.buffer = Self::create_buffer(self.binding, &entries, render_device);
+            self.entries = entries;
+            return true;
+        }
+
+        let stride = GpuVoxelMaterial::min_size().get();
+        let mut index = 0;
+
+        while index < entries.len() {
+            if entries[index] == self.entries[index] {
+                index += 1;
+                continue;
+            }
+
+            let start = index;
+            while index < entries.len() && entries[index] != self.entries[index] {
+                index += 1;
+            }
+
+            let mut bytes = StorageBuffer::new(Vec::new());
+            bytes.write(&entries[start..index]).unwrap();
+            render_queue.write_buffer(&self.buffer, start as u64 * stride, bytes.as_ref());
+            self.entries[start..index].copy_from_slice(&entries[start..index]);
+        }
+
+        false
+    }
 }
 
-#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+impl FromWorld for GpuVoxelMaterialTable {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let binding = VoxelMaterialTableBinding::for_device(render_device);
+        let capacity = match binding {
+            VoxelMaterialTableBinding::Storage => Self::MIN_STORAGE_CAPACITY,
+            VoxelMaterialTableBinding::Uniform { capacity } => capacity as usize,
+        };
+
+        let entries = vec![GpuVoxelMaterial::placeholder(); capacity];
+        let buffer = Self::create_buffer(binding, &entries, render_device);
+
+        Self {
+            binding,
+            buffer,
+            entries,
+        }
+    }
+}
+
+/// The terrain material.
+///
+/// The bind group is laid out by hand as the binding of the material table depends on the device.
+#[derive(Asset, TypePath, Debug, Clone)]
 pub struct GpuTerrainUniforms {
-    #[uniform(0)]
-    pub render_distance: u32,
-    #[uniform(1)]
-    pub materials: [GpuVoxelMaterial; 256],
+    pub fog: GpuTerrainFog,
+    /// The buffer of the [`GpuVoxelMaterialTable`].
+    pub materials: Buffer,
+    pub table_binding: VoxelMaterialTableBinding,
+    pub textures: Option<Handle<Image>>,
+    pub debug: TerrainDebugKey,
+}
+
+/// The terrain pipeline specialization key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TerrainMaterialKey {
+    pub debug: TerrainDebugKey,
+    pub table_binding: VoxelMaterialTableBinding,
 }
 
-impl Default for GpuTerrainUniforms {
-    fn default() -> Self {
+impl From<&GpuTerrainUniforms> for TerrainMaterialKey {
+    fn from(material: &GpuTerrainUniforms) -> Self {
         Self {
-            render_distance: 16,
-            materials: [default(); 256],
+            debug: material.debug,
+            table_binding: material.table_binding,
         }
     }
 }
 
+impl AsBindGroup for GpuTerrainUniforms {
+    type Data = TerrainMaterialKey;
+
+    fn label() -> Option<&'static str> {
+        Some("terrain_material")
+    }
+
+    fn unprepared_bind_group(
+        &self,
+        _layout: &BindGroupLayout,
+        render_device: &RenderDevice,
+        images: &RenderAssets<GpuImage>,
+        fallback_image: &FallbackImage,
+    ) -> Result<UnpreparedBindGroup<Self::Data>, AsBindGroupError> {
+        let mut fog = UniformBuffer::new(Vec::new());
+        fog.write(&self.fog).unwrap();
+        let fog = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("terrain_fog"),
+            contents: fog.as_ref(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let textures = match &self.textures {
+            Some(handle) => images
+                .get(handle)
+                .ok_or(AsBindGroupError::RetryNextUpdate)?,
+            None => &fallback_image.d2_array,
+        };
+
+        Ok(UnpreparedBindGroup {
+            bindings: vec![
+                (0, OwnedBindingResource::Buffer(fog)),
+                (1, OwnedBindingResource::Buffer(self.materials.clone())),
+                (
+                    2,
+                    OwnedBindingResource::TextureView(textures.texture_view.clone()),
+                ),
+                (3, OwnedBindingResource::Sampler(textures.sampler.clone())),
+            ],
+            data: self.into(),
+        })
+    }
+
+    fn bind_group_layout_entries(render_device: &RenderDevice) -> Vec<BindGroupLayoutEntry> {
+        BindGroupLayoutEntries::sequential(
+            ShaderStages::VERTEX_FRAGMENT,
+            (
+                uniform_buffer::<GpuTerrainFog>(false),
+                VoxelMaterialTableBinding::for_device(render_device).layout_entry(),
+                texture_2d_array(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+            ),
+        )
+        .to_vec()
+    }
+}
+
 impl Material for GpuTerrainUniforms {
     fn vertex_shader() -> bevy::render::render_resource::ShaderRef {
         "shaders/terrain_pipeline.wgsl".into()
@@ -54,66 +349,96 @@ impl Material for GpuTerrainUniforms {
         "shaders/terrain_pipeline.wgsl".into()
     }
 
+    fn prepass_vertex_shader() -> bevy::render::render_resource::ShaderRef {
+        "shaders/terrain_prepass.wgsl".into()
+    }
+
     fn specialize(
         _pipeline: &bevy::pbr::MaterialPipeline<Self>,
         descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
         layout: &bevy::render::mesh::MeshVertexBufferLayoutRef,
-        _key: bevy::pbr::MaterialPipelineKey<Self>,
+        key: bevy::pbr::MaterialPipelineKey<Self>,
     ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
         let vertex_layout = layout.0.get_layout(&[
             Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
             VoxelTerrainMesh::ATTRIBUTE_DATA.at_shader_location(1),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(2),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(3),
+            VoxelTerrainMesh::ATTRIBUTE_LIGHT.at_shader_location(4),
         ])?;
         descriptor.vertex.buffers = vec![vertex_layout];
+
+        if key.bind_group_data.debug.wireframe {
+            descriptor.primitive.polygon_mode = PolygonMode::Line;
+        }
+
+        let mut shader_defs = key.bind_group_data.table_binding.shader_defs();
+        shader_defs.push(ShaderDefVal::UInt("CHUNK_LENGTH".into(), CHUNK_LENGTH));
+        if let Some(shader_def) = key.bind_group_data.debug.view.shader_def() {
+            shader_defs.push(shader_def.into());
+        }
+
+        descriptor
+            .vertex
+            .shader_defs
+            .extend_from_slice(&shader_defs);
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader_defs.extend(shader_defs);
+        }
+
         Ok(())
     }
 }
 
-fn update_chunk_material_singleton(
-    mut commands: Commands,
-    mut materials: ResMut<Assets<GpuTerrainUniforms>>,
-    chunk_material: ResMut<ChunkMaterialSingleton>,
+/// Patches the entries of the material table for the materials which changed in the registry,
+/// pointing the terrain material to the new buffer if the table had to grow.
+fn update_voxel_material_table(
     voxel_materials: Res<VoxelMaterialRegistry>,
-    mut chunk_entities: Query<(Entity, &mut Handle<GpuTerrainUniforms>)>,
+    terrain_textures: Res<TerrainTextureArray>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    chunk_material: Res<ChunkMaterialSingleton>,
+    mut materials: ResMut<Assets<GpuTerrainUniforms>>,
+    mut table: ResMut<GpuVoxelMaterialTable>,
 ) {
-    if chunk_material.is_changed() {
-        let mut gpu_mats = GpuTerrainUniforms {
-            materials: [GpuVoxelMaterial {
-                base_color: Color::WHITE.into(),
-                flags: 0,
-                ..Default::default()
-            }; 256],
-            render_distance: 32,
-        };
+    let entries: Vec<_> = voxel_materials
+        .iter_mats()
+        .map(|material| GpuVoxelMaterial::new(material, &terrain_textures))
+        .collect();
 
-        voxel_materials
-            .iter_mats()
-            .enumerate()
-            .for_each(|(index, material)| {
-                gpu_mats.materials[index].base_color = material.base_color.into();
-                gpu_mats.materials[index].flags = material.flags.bits();
-                gpu_mats.materials[index].emissive = material.emissive.into();
-                gpu_mats.materials[index].perceptual_roughness = material.perceptual_roughness;
-                gpu_mats.materials[index].metallic = material.metallic;
-                gpu_mats.materials[index].reflectance = material.reflectance;
-            });
-
-        let chunk_material = materials.add(gpu_mats);
-        commands.insert_resource(ChunkMaterialSingleton(chunk_material.clone()));
-
-        for (_, mut mat) in &mut chunk_entities {
-            *mat = chunk_material.clone();
+    if table.update(&entries, &render_device, &render_queue) {
+        if let Some(material) = materials.get_mut(&**chunk_material) {
+            material.materials = table.buffer().clone();
         }
     }
 }
 
+fn update_chunk_material_textures(
+    terrain_textures: Res<TerrainTextureArray>,
+    chunk_material: Res<ChunkMaterialSingleton>,
+    mut materials: ResMut<Assets<GpuTerrainUniforms>>,
+) {
+    if let Some(material) = materials.get_mut(&**chunk_material) {
+        material.textures = terrain_textures.image().cloned();
+    }
+}
+
 #[derive(Resource, Deref, DerefMut)]
 pub struct ChunkMaterialSingleton(Handle<GpuTerrainUniforms>);
 
 impl FromWorld for ChunkMaterialSingleton {
     fn from_world(world: &mut World) -> Self {
+        let table = world.resource::<GpuVoxelMaterialTable>();
+        let material = GpuTerrainUniforms {
+            fog: GpuTerrainFog::from(&TerrainFogSettings::default()),
+            materials: table.buffer().clone(),
+            table_binding: table.binding(),
+            textures: None,
+            debug: default(),
+        };
+
         let mut materials = world.resource_mut::<Assets<GpuTerrainUniforms>>();
-        Self(materials.add(GpuTerrainUniforms::default()))
+        Self(materials.add(material))
     }
 }
 
@@ -127,12 +452,23 @@ impl Plugin for ChunkMaterialPlugin {
     fn build(&self, app: &mut App) {
         // @todo: figure out race conditions w/ other systems
         app.add_plugins(MaterialPlugin::<GpuTerrainUniforms>::default())
+            .add_plugins(TerrainTexturesPlugin)
+            .add_plugins(TerrainFogPlugin)
+            .add_plugins(TerrainDebugViewPlugin)
+            .add_plugins(WaterMaterialPlugin)
+            .init_resource::<GpuVoxelMaterialTable>()
             .init_resource::<ChunkMaterialSingleton>()
             .add_systems(
                 Update,
-                update_chunk_material_singleton
-                    .run_if(resource_changed::<VoxelMaterialRegistry>)
-                    .in_set(ChunkMaterialSet),
+                (
+                    update_voxel_material_table.run_if(
+                        resource_changed::<VoxelMaterialRegistry>
+                            .or_else(resource_changed::<TerrainTextureArray>),
+                    ),
+                    update_chunk_material_textures.run_if(resource_changed::<TerrainTextureArray>),
+                )
+                    .in_set(ChunkMaterialSet)
+                    .after(TerrainTexturesSet),
             );
     }
 }
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use super::{ChunkMaterialSet, ChunkMaterialSingleton, GpuTerrainUniforms};
+
+/// A visualisation of the terrain data replacing the shaded terrain colour.
+///
+/// There is no level of detail nor ambient occlusion in the terrain meshes yet, so their visualisation is deferred
+/// until the meshers produce them.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TerrainDebugView {
+    #[default]
+    Shaded,
+    /// The world space normals of the faces.
+    Normals,
+    /// A false colour per material id.
+    MaterialId,
+    /// The shaded terrain tinted with a random colour per chunk.
+    ChunkTint,
+    /// The block light and skylight levels baked into the vertices.
+    LightLevels,
+}
+
+impl TerrainDebugView {
+    pub const ALL: [Self; 5] = [
+        Self::Shaded,
+        Self::Normals,
+        Self::MaterialId,
+        Self::ChunkTint,
+        Self::LightLevels,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Shaded => "Shaded",
+            Self::Normals => "Face normals",
+            Self::MaterialId => "Material ids",
+            Self::ChunkTint => "Chunk tint",
+            Self::LightLevels => "Light levels",
+        }
+    }
+
+    /// Returns the shader def enabling this view in the terrain shader.
+    pub const fn shader_def(self) -> Option<&'static str> {
+        match self {
+            Self::Shaded => None,
+            Self::Normals => Some("TERRAIN_DEBUG_NORMALS"),
+            Self::MaterialId => Some("TERRAIN_DEBUG_MATERIAL_ID"),
+            Self::ChunkTint => Some("TERRAIN_DEBUG_CHUNK_TINT"),
+            Self::LightLevels => Some("TERRAIN_DEBUG_LIGHT_LEVELS"),
+        }
+    }
+}
+
+/// Settings of the terrain debug visualisations.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TerrainDebugSettings {
+    pub view: TerrainDebugView,
+    /// Renders the terrain triangles as lines, this requires the `POLYGON_MODE_LINE` GPU feature.
+    pub wireframe: bool,
+    /// Outlines the chunks around the player.
+    pub chunk_borders: bool,
+}
+
+/// The terrain pipeline specialization key derived from the [`TerrainDebugSettings`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TerrainDebugKey {
+    pub view: TerrainDebugView,
+    pub wireframe: bool,
+}
+
+impl From<&TerrainDebugSettings> for TerrainDebugKey {
+    fn from(settings: &TerrainDebugSettings) -> Self {
+        Self {
+            view: settings.view,
+            wireframe: settings.wireframe,
+        }
+    }
+}
+
+fn update_terrain_debug_view(
+    settings: Res<TerrainDebugSettings>,
+    chunk_material: Res<ChunkMaterialSingleton>,
+    mut materials: ResMut<Assets<GpuTerrainUniforms>>,
+) {
+    let key = TerrainDebugKey::from(&*settings);
+    // the material is only touched when the pipeline needs to be specialized again.
+    if materials
+        .get(&**chunk_material)
+        .is_some_and(|material| material.debug != key)
+    {
+        materials.get_mut(&**chunk_material).unwrap().debug = key;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, SystemSet)]
+/// Systems keeping the terrain material up to date with the [`TerrainDebugSettings`].
+pub struct TerrainDebugViewSet;
+
+pub struct TerrainDebugViewPlugin;
+
+impl Plugin for TerrainDebugViewPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainDebugSettings>().add_systems(
+            Update,
+            update_terrain_debug_view
+                .run_if(resource_changed::<TerrainDebugSettings>)
+                .in_set(TerrainDebugViewSet)
+                .before(ChunkMaterialSet),
+        );
+    }
+}
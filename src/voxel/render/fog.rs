@@ -0,0 +1,138 @@
+use bevy::{prelude::*, render::render_resource::ShaderType};
+
+use super::{ChunkMaterialSet, ChunkMaterialSingleton, GpuTerrainUniforms};
+use crate::voxel::{ChunkLoadRadius, CHUNK_LENGTH};
+
+/// Settings of the distance fog applied to the terrain.
+/// Distances are expressed in chunks so the fog can be matched against the [`ChunkLoadRadius`].
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct TerrainFogSettings {
+    /// Driven by the day / night cycle unless [`TimeOfDay::drives_fog_color`](crate::voxel::TimeOfDay::drives_fog_color)
+    /// is disabled.
+    pub color: Color,
+    /// The distance at which the fog starts.
+    pub start: f32,
+    /// The distance at which the terrain is completely hidden in the fog.
+    pub end: f32,
+    /// The exponent of the fog curve between `start` and `end`: 1.0 is linear, higher values keep the fog thin for longer.
+    pub falloff: f32,
+    /// Keeps `end` at the horizontal load radius so the edge of the loaded world stays hidden,
+    /// moving `start` along to keep the width of the fog band.
+    pub follow_load_radius: bool,
+    pub height_fog: Option<TerrainHeightFog>,
+}
+
+impl Default for TerrainFogSettings {
+    fn default() -> Self {
+        Self {
+            color: Color::srgb(0.4, 0.4, 0.4),
+            start: 11.5,
+            end: 16.0,
+            falloff: 1.0,
+            follow_load_radius: true,
+            height_fog: None,
+        }
+    }
+}
+
+/// Additional fog pooling in low areas of the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainHeightFog {
+    /// The height (in voxels) at which the height fog is the densest.
+    pub bottom: f32,
+    /// The height (in voxels) above which there's no height fog.
+    pub top: f32,
+    /// The fog amount at the `bottom` height when looking at the fog end distance (from 0.0 to 1.0).
+    pub density: f32,
+}
+
+impl Default for TerrainHeightFog {
+    fn default() -> Self {
+        Self {
+            bottom: 64.0,
+            top: 112.0,
+            density: 0.6,
+        }
+    }
+}
+
+/// The GPU representation of the [`TerrainFogSettings`].
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct GpuTerrainFog {
+    color: LinearRgba,
+    start: f32,
+    end: f32,
+    falloff: f32,
+    height_bottom: f32,
+    height_top: f32,
+    height_density: f32,
+}
+
+impl From<&TerrainFogSettings> for GpuTerrainFog {
+    fn from(settings: &TerrainFogSettings) -> Self {
+        let height_fog = settings.height_fog.unwrap_or(TerrainHeightFog {
+            density: 0.0,
+            ..default()
+        });
+
+        Self {
+            color: settings.color.into(),
+            start: settings.start * CHUNK_LENGTH as f32,
+            end: settings.end * CHUNK_LENGTH as f32,
+            falloff: settings.falloff,
+            height_bottom: height_fog.bottom,
+            height_top: height_fog.top,
+            height_density: height_fog.density,
+        }
+    }
+}
+
+fn follow_chunk_load_radius(
+    load_radius: Res<ChunkLoadRadius>,
+    mut fog_settings: ResMut<TerrainFogSettings>,
+) {
+    if !fog_settings.follow_load_radius {
+        return;
+    }
+
+    let end = load_radius.horizontal as f32;
+    if fog_settings.end != end {
+        let band = fog_settings.end - fog_settings.start;
+        fog_settings.end = end;
+        fog_settings.start = (end - band).max(0.0);
+    }
+}
+
+fn update_terrain_fog(
+    fog_settings: Res<TerrainFogSettings>,
+    chunk_material: Res<ChunkMaterialSingleton>,
+    mut materials: ResMut<Assets<GpuTerrainUniforms>>,
+) {
+    if let Some(material) = materials.get_mut(&**chunk_material) {
+        material.fog = GpuTerrainFog::from(&*fog_settings);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, SystemSet)]
+/// Systems keeping the terrain fog up to date with the [`TerrainFogSettings`].
+pub struct TerrainFogSet;
+
+pub struct TerrainFogPlugin;
+
+impl Plugin for TerrainFogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainFogSettings>().add_systems(
+            Update,
+            (
+                follow_chunk_load_radius.run_if(
+                    resource_changed::<ChunkLoadRadius>
+                        .or_else(resource_changed::<TerrainFogSettings>),
+                ),
+                update_terrain_fog.run_if(resource_changed::<TerrainFogSettings>),
+            )
+                .chain()
+                .in_set(TerrainFogSet)
+                .before(ChunkMaterialSet),
+        );
+    }
+}
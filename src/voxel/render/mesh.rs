@@ -1,120 +1,493 @@
-use std::marker::PhantomData;
+use std::{cell::RefCell, sync::Arc};
 
-use crate::voxel::{storage::VoxelBuffer, MaterialVoxel};
+use crate::voxel::{
+    material::{VoxelMaterialFlags, VoxelMaterialRegistry, VoxelMeshingMode, VoxelShape},
+    storage::VoxelBuffer,
+    Voxel, VoxelLight, CHUNK_LENGTH,
+};
 use bevy::{
-    prelude::Mesh,
-    render::mesh::{Indices, VertexAttributeValues},
+    prelude::{Mesh, Resource},
+    render::{
+        mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
+        render_asset::RenderAssetUsages,
+    },
+};
+use block_mesh::{
+    greedy_quads, visible_block_faces, GreedyQuadsBuffer, MergeVoxel, OrientedBlockFace,
+    UnitQuadBuffer, UnorientedQuad, Voxel as MeshableVoxel, VoxelVisibility,
+    RIGHT_HANDED_Y_UP_CONFIG,
 };
-use block_mesh::{greedy_quads, GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG};
-use ndcopy::copy3;
-use ndshape::{RuntimeShape, Shape};
+use ndshape::{ConstShape, ConstShape3u32, Shape};
+use thread_local::ThreadLocal;
 
 use super::VoxelTerrainMesh;
 
-/// Intermediate buffers for greedy meshing of voxel data which are reusable between frames to not allocate.
-pub struct MeshBuffers<T, S: Shape<3, Coord = u32>>
-where
-    T: Copy + Default + MaterialVoxel,
-{
-    // A padded buffer to run greedy meshing algorithm on
-    scratch_buffer: VoxelBuffer<T, RuntimeShape<u32, 3>>,
-    greedy_buffer: GreedyQuadsBuffer,
-    _phantom: PhantomData<S>,
+pub const PADDED_CHUNK_LENGTH: u32 = CHUNK_LENGTH + 2;
+pub type PaddedChunkShape =
+    ConstShape3u32<PADDED_CHUNK_LENGTH, PADDED_CHUNK_LENGTH, PADDED_CHUNK_LENGTH>;
+
+/// A view of the voxels of a chunk padded with one voxel on each side.
+pub type PaddedChunkBuffer = VoxelBuffer<Voxel, PaddedChunkShape>;
+
+/// The light levels of the voxels of a chunk padded with the light levels of the neighbouring chunks.
+pub type PaddedLightBuffer = VoxelBuffer<VoxelLight, PaddedChunkShape>;
+
+/// Returns the position in front of the face of a voxel for the faces in the `RIGHT_HANDED_Y_UP_CONFIG` order.
+#[inline]
+pub fn face_neighbour(pos: [u32; 3], face_index: usize) -> [u32; 3] {
+    let mut neighbour = pos;
+    let axis = face_index % 3;
+    if face_index < 3 {
+        neighbour[axis] -= 1;
+    } else {
+        neighbour[axis] += 1;
+    }
+    neighbour
+}
+
+/// The meshing related properties of a voxel material.
+#[derive(Clone, Copy, Default)]
+pub struct MaterialMeshingInfo {
+    pub mode: VoxelMeshingMode,
+    pub shape: VoxelShape,
+    /// Liquids are see-through and meshed separately from the rest of the terrain.
+    pub liquid: bool,
+}
+
+/// Meshing related properties of the registered voxel materials indexed by material id.
+/// This is a cheap to clone snapshot of the [`VoxelMaterialRegistry`] which can be moved into meshing tasks.
+#[derive(Resource, Clone, Default)]
+pub struct MeshingMaterials(Arc<[MaterialMeshingInfo]>);
+
+impl MeshingMaterials {
+    pub fn from_registry(registry: &VoxelMaterialRegistry) -> Self {
+        Self(
+            registry
+                .iter_mats()
+                .map(|mat| MaterialMeshingInfo {
+                    mode: mat.meshing_mode,
+                    shape: mat.shape,
+                    liquid: mat.flags.contains(VoxelMaterialFlags::LIQUID),
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the meshing properties of the material with the specified id.
+    #[inline]
+    pub fn get(&self, id: u16) -> MaterialMeshingInfo {
+        self.0.get(id as usize).copied().unwrap_or_default()
+    }
+
+    /// Returns the shape of the material with the specified id.
+    #[inline]
+    pub fn shape(&self, id: u16) -> VoxelShape {
+        self.get(id).shape
+    }
+}
+
+/// Packs the per vertex voxel data read by the terrain shader (see `voxel_data.wgsl` for the layout).
+#[inline]
+pub const fn encode_voxel_data(
+    material: u16,
+    normal_index: u32,
+    blend_material: u16,
+    blend: u8,
+) -> [u32; 2] {
+    [
+        (blend_material as u32) << 16 | material as u32,
+        (blend as u32) << 8 | (normal_index & 7),
+    ]
 }
 
-impl<T, S: Shape<3, Coord = u32>> MeshBuffers<T, S>
-where
-    T: Copy + Default + MaterialVoxel,
-{
-    pub fn new(shape: S) -> Self {
-        let padded_shape = RuntimeShape::<u32, 3>::new(shape.as_array().map(|x| x + 2));
+/// Projects a position onto the plane orthogonal to the specified axis to get texture coordinates,
+/// with the texture upright on the side faces.
+#[inline]
+pub fn planar_uv(position: [f32; 3], axis: usize) -> [f32; 2] {
+    let [x, y, z] = position;
+    match axis {
+        0 => [z, -y],
+        1 => [x, z],
+        _ => [x, -y],
+    }
+}
+
+/// The description of a chunk mesh as produced by a [`ChunkMesher`].
+#[derive(Default, Clone)]
+pub struct ChunkMeshData {
+    pub indices: Vec<u32>,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Voxel data packed with [`encode_voxel_data`].
+    pub data: Vec<[u32; 2]>,
+    /// Texture coordinates in voxel units, so textures repeat once per voxel.
+    pub uvs: Vec<[f32; 2]>,
+    /// The light levels lighting the vertices.
+    pub lights: Vec<u32>,
+}
+
+impl ChunkMeshData {
+    /// Appends a quad generated by the block-mesh crate for the specified voxel face.
+    pub fn push_block_face(
+        &mut self,
+        face_index: usize,
+        face: &OrientedBlockFace,
+        quad: &UnorientedQuad,
+        material: u16,
+        light: VoxelLight,
+    ) {
+        self.indices
+            .extend_from_slice(&face.quad_mesh_indices(self.positions.len() as u32));
+        self.positions
+            .extend_from_slice(&face.quad_mesh_positions(quad, 1.0));
+        self.normals.extend_from_slice(&face.quad_mesh_normals());
+        self.data
+            .extend_from_slice(&[encode_voxel_data(material, face_index as u32, material, 0); 4]);
+        // greedy quads span several voxels so the texture gets tiled over their width and height.
+        self.uvs.extend_from_slice(&face.tex_coords(
+            RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
+            true,
+            quad,
+        ));
+        self.lights.extend_from_slice(&[light.0 as u32; 4]);
+    }
+
+    /// Appends a quad whose corners are wound counter-clockwise when looking at its front side.
+    pub fn push_quad(
+        &mut self,
+        corners: [[f32; 3]; 4],
+        normal: [f32; 3],
+        uvs: [[f32; 2]; 4],
+        data: [u32; 2],
+        light: VoxelLight,
+    ) {
+        let start = self.positions.len() as u32;
+        self.indices
+            .extend_from_slice(&[start, start + 1, start + 2, start, start + 2, start + 3]);
+        self.positions.extend_from_slice(&corners);
+        self.normals.extend_from_slice(&[normal; 4]);
+        self.uvs.extend_from_slice(&uvs);
+        self.data.extend_from_slice(&[data; 4]);
+        self.lights.extend_from_slice(&[light.0 as u32; 4]);
+    }
+
+    /// Builds a render mesh from this mesh description.
+    pub fn into_mesh(self) -> Mesh {
+        let mut render_mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(self.positions),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(self.normals),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            VertexAttributeValues::Float32x2(self.uvs),
+        );
+
+        //todo: in the future we might want to encode all the information onto a single uint32
+        render_mesh.insert_attribute(
+            VoxelTerrainMesh::ATTRIBUTE_DATA,
+            VertexAttributeValues::Uint32x2(self.data),
+        );
+
+        render_mesh.insert_attribute(
+            VoxelTerrainMesh::ATTRIBUTE_LIGHT,
+            VertexAttributeValues::Uint32(self.lights),
+        );
+
+        render_mesh.insert_indices(Indices::U32(self.indices));
+        render_mesh
+    }
+}
+
+/// The mesh descriptions of a chunk, liquids being rendered apart from the rest of the terrain.
+#[derive(Default, Clone)]
+pub struct ChunkMeshOutput {
+    pub terrain: ChunkMeshData,
+    pub liquid: ChunkMeshData,
+}
+
+impl ChunkMeshOutput {
+    /// Builds the terrain and liquid render meshes.
+    pub fn into_meshes(self) -> (Mesh, Mesh) {
+        (self.terrain.into_mesh(), self.liquid.into_mesh())
+    }
+}
+
+/// Turns the full cube voxels of a chunk into a mesh description.
+/// Implement this to customize how chunks are meshed and select it through the [`SelectedChunkMesher`] resource.
+/// The meshing plugin pads the chunk with the voxels of its neighbours before calling the mesher, and meshes the
+/// voxels with other shapes or a smooth meshing mode itself.
+pub trait ChunkMesher: 'static + Send + Sync {
+    /// Meshes the faces of the blocky cube voxels of a padded chunk view into `output`, lighting them with the light
+    /// levels of the padded light view. The padding voxels only hide the faces of the chunk voxels.
+    fn mesh_chunk(
+        &self,
+        voxels: &PaddedChunkBuffer,
+        lights: &PaddedLightBuffer,
+        materials: &MeshingMaterials,
+        output: &mut ChunkMeshOutput,
+    );
+}
+
+/// The [`ChunkMesher`] used by the meshing tasks.
+#[derive(Resource, Clone)]
+pub struct SelectedChunkMesher(Arc<dyn ChunkMesher>);
+
+impl SelectedChunkMesher {
+    pub fn new(mesher: impl ChunkMesher) -> Self {
+        Self(Arc::new(mesher))
+    }
+}
 
+impl std::ops::Deref for SelectedChunkMesher {
+    type Target = dyn ChunkMesher;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl Default for SelectedChunkMesher {
+    fn default() -> Self {
+        Self::new(GreedyChunkMesher::default())
+    }
+}
+
+/// A voxel as seen by the block meshers, with its visibility resolved from the meshing mode of its material.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct BlockyVoxel {
+    material: u16,
+    visibility: VoxelVisibility,
+    /// The light levels in front of each face of the voxel, so differently lit faces don't get merged.
+    face_lights: [VoxelLight; 6],
+}
+
+impl Default for BlockyVoxel {
+    fn default() -> Self {
         Self {
-            greedy_buffer: GreedyQuadsBuffer::new(padded_shape.size() as usize),
-            scratch_buffer: VoxelBuffer::<T, RuntimeShape<u32, 3>>::new_empty(padded_shape),
-            _phantom: Default::default(),
+            material: 0,
+            visibility: VoxelVisibility::Empty,
+            face_lights: [VoxelLight::NONE; 6],
         }
     }
 }
 
-// Processes the voxel data buffer specified as a parameter and generate.
-//todo: don't populate mesh directly, introduce a meshbuilding system.
-pub fn mesh_buffer<T, S>(
-    buffer: &VoxelBuffer<T, S>,
-    mesh_buffers: &mut MeshBuffers<T, S>,
-    render_mesh: &mut Mesh,
-    scale: f32,
-) where
-    T: Copy + Default + MaterialVoxel,
-    S: Shape<3, Coord = u32>,
-{
-    mesh_buffers
-        .greedy_buffer
-        .reset(buffer.shape().size() as usize);
-
-    let dst_shape = mesh_buffers.scratch_buffer.shape().clone();
-
-    copy3(
-        buffer.shape().as_array(),
-        buffer.slice(),
-        buffer.shape(),
-        [0; 3],
-        mesh_buffers.scratch_buffer.slice_mut(),
-        &dst_shape,
-        [1; 3],
-    );
+impl MeshableVoxel for BlockyVoxel {
+    #[inline]
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.visibility
+    }
+}
+
+impl MergeVoxel for BlockyVoxel {
+    type MergeValue = (u16, [VoxelLight; 6]);
+
+    #[inline]
+    fn merge_value(&self) -> Self::MergeValue {
+        (self.material, self.face_lights)
+    }
+}
+
+/// Fills a buffer with the voxels of the padded chunk view to be meshed as blocks.
+/// Smooth and non-cube voxels are considered empty so they don't occlude the faces of their neighbours,
+/// while liquids are translucent so the terrain faces they cover are still meshed.
+fn prepare_blocky_voxels(
+    voxels: &PaddedChunkBuffer,
+    lights: &PaddedLightBuffer,
+    materials: &MeshingMaterials,
+    blocky: &mut [BlockyVoxel],
+) {
+    for (index, (voxel, blocky)) in voxels.slice().iter().zip(blocky.iter_mut()).enumerate() {
+        let info = materials.get(voxel.0);
+        *blocky = match (voxel.get_visibility(), info.mode, info.shape) {
+            (VoxelVisibility::Empty, _, _)
+            | (_, VoxelMeshingMode::Smooth, _)
+            | (_, _, VoxelShape::Cross | VoxelShape::Slab | VoxelShape::Stair)
+            | (_, _, VoxelShape::Custom(_)) => BlockyVoxel::default(),
+            (_, VoxelMeshingMode::Blocky, VoxelShape::Cube) => {
+                let pos = PaddedChunkShape {}.delinearize(index as u32);
+                // the padding voxels only hide the faces of the chunk voxels, their own faces are never meshed.
+                let is_padding = pos
+                    .iter()
+                    .any(|coord| *coord == 0 || *coord == PADDED_CHUNK_LENGTH - 1);
+
+                BlockyVoxel {
+                    material: voxel.0,
+                    visibility: if info.liquid {
+                        VoxelVisibility::Translucent
+                    } else {
+                        VoxelVisibility::Opaque
+                    },
+                    face_lights: std::array::from_fn(|face| {
+                        if is_padding {
+                            VoxelLight::NONE
+                        } else {
+                            lights.voxel_at(face_neighbour(pos, face).into())
+                        }
+                    }),
+                }
+            }
+        };
+    }
+}
+
+/// Appends a quad of blocky voxel faces to the mesh of its material.
+/// Liquid quads are split into unit quads so the liquid surface has a vertex per voxel corner to animate.
+fn push_blocky_quad(
+    output: &mut ChunkMeshOutput,
+    face_index: usize,
+    face: &OrientedBlockFace,
+    quad: &UnorientedQuad,
+    voxel: &BlockyVoxel,
+) {
+    let light = voxel.face_lights[face_index];
+
+    if voxel.visibility != VoxelVisibility::Translucent {
+        output
+            .terrain
+            .push_block_face(face_index, face, quad, voxel.material, light);
+        return;
+    }
 
-    greedy_quads(
-        mesh_buffers.scratch_buffer.slice(),
-        mesh_buffers.scratch_buffer.shape(),
-        [0; 3],
-        mesh_buffers
-            .scratch_buffer
-            .shape()
-            .as_array()
-            .map(|axis| axis - 1),
-        &RIGHT_HANDED_Y_UP_CONFIG.faces,
-        &mut mesh_buffers.greedy_buffer,
+    let unit_quad = |minimum| UnorientedQuad {
+        minimum,
+        width: 1,
+        height: 1,
+    };
+    let [origin, u_corner, v_corner, _] = face.quad_corners(&unit_quad(quad.minimum));
+    let (u, v) = (
+        (u_corner - origin).to_array(),
+        (v_corner - origin).to_array(),
     );
 
-    let num_indices = mesh_buffers.greedy_buffer.quads.num_quads() * 6;
-    let num_vertices = mesh_buffers.greedy_buffer.quads.num_quads() * 4;
-    let mut indices = Vec::with_capacity(num_indices);
-    let mut positions = Vec::with_capacity(num_vertices);
-    let mut data = Vec::with_capacity(num_vertices);
+    for i in 0..quad.width {
+        for j in 0..quad.height {
+            let minimum =
+                std::array::from_fn(|axis| quad.minimum[axis] + u[axis] * i + v[axis] * j);
+            output.liquid.push_block_face(
+                face_index,
+                face,
+                &unit_quad(minimum),
+                voxel.material,
+                light,
+            );
+        }
+    }
+}
 
+/// Appends the quads of blocky voxel faces, grouped per face in the `RIGHT_HANDED_Y_UP_CONFIG` order.
+fn push_blocky_quads<Q: Copy + Into<UnorientedQuad>>(
+    output: &mut ChunkMeshOutput,
+    blocky: &[BlockyVoxel],
+    groups: &[Vec<Q>; 6],
+) {
     //normal face index depends on the quad orientation config
-    for (block_face_normal_index, (group, face)) in mesh_buffers
-        .greedy_buffer
-        .quads
-        .groups
-        .as_ref()
+    for (block_face_normal_index, (group, face)) in groups
         .iter()
         .zip(RIGHT_HANDED_Y_UP_CONFIG.faces.iter())
         .enumerate()
     {
         for quad in group {
-            indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-            positions.extend_from_slice(&face.quad_mesh_positions(quad, scale));
-            data.extend_from_slice(
-                &[(block_face_normal_index as u32) << 8u32
-                    | buffer
-                        .voxel_at(quad.minimum.map(|x| x - 1).into())
-                        .as_mat_id() as u32; 4],
-            );
+            let quad: UnorientedQuad = (*quad).into();
+            let voxel = blocky[PaddedChunkShape {}.linearize(quad.minimum) as usize];
+            push_blocky_quad(output, block_face_normal_index, face, &quad, &voxel);
         }
     }
+}
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::Float32x3(positions),
-    );
+/// Intermediate buffers of the blocky meshers which are reusable between chunks to not allocate.
+struct BlockyMeshBuffers<Q> {
+    blocky: Vec<BlockyVoxel>,
+    quads: Q,
+}
 
-    //todo: in the future we might want to encode all the information onto a single uint32
-    render_mesh.insert_attribute(
-        VoxelTerrainMesh::ATTRIBUTE_DATA,
-        VertexAttributeValues::Uint32(data),
-    );
+impl<Q> BlockyMeshBuffers<Q> {
+    fn new(quads: Q) -> Self {
+        Self {
+            blocky: vec![BlockyVoxel::default(); PaddedChunkShape::USIZE],
+            quads,
+        }
+    }
+}
+
+/// Meshes blocky voxels by merging adjacent faces of the same material into bigger quads.
+#[derive(Default)]
+pub struct GreedyChunkMesher {
+    buffers: ThreadLocal<RefCell<BlockyMeshBuffers<GreedyQuadsBuffer>>>,
+}
 
-    render_mesh.insert_indices(Indices::U32(indices.clone()));
+impl ChunkMesher for GreedyChunkMesher {
+    fn mesh_chunk(
+        &self,
+        voxels: &PaddedChunkBuffer,
+        lights: &PaddedLightBuffer,
+        materials: &MeshingMaterials,
+        output: &mut ChunkMeshOutput,
+    ) {
+        let mut buffers = self
+            .buffers
+            .get_or(|| {
+                RefCell::new(BlockyMeshBuffers::new(GreedyQuadsBuffer::new(
+                    PaddedChunkShape::USIZE,
+                )))
+            })
+            .borrow_mut();
+        let buffers = &mut *buffers;
+
+        prepare_blocky_voxels(voxels, lights, materials, &mut buffers.blocky);
+        greedy_quads(
+            &buffers.blocky,
+            &PaddedChunkShape {},
+            [0; 3],
+            [PADDED_CHUNK_LENGTH - 1; 3],
+            &RIGHT_HANDED_Y_UP_CONFIG.faces,
+            &mut buffers.quads,
+        );
+        push_blocky_quads(output, &buffers.blocky, &buffers.quads.quads.groups);
+    }
+}
+
+/// Meshes blocky voxels by emitting a quad for every face that isn't hidden by a neighbouring voxel.
+/// This is faster than [`GreedyChunkMesher`] at the expense of generating more geometry.
+#[derive(Default)]
+pub struct CulledChunkMesher {
+    buffers: ThreadLocal<RefCell<BlockyMeshBuffers<UnitQuadBuffer>>>,
+}
+
+impl ChunkMesher for CulledChunkMesher {
+    fn mesh_chunk(
+        &self,
+        voxels: &PaddedChunkBuffer,
+        lights: &PaddedLightBuffer,
+        materials: &MeshingMaterials,
+        output: &mut ChunkMeshOutput,
+    ) {
+        let mut buffers = self
+            .buffers
+            .get_or(|| RefCell::new(BlockyMeshBuffers::new(UnitQuadBuffer::new())))
+            .borrow_mut();
+        let buffers = &mut *buffers;
+
+        prepare_blocky_voxels(voxels, lights, materials, &mut buffers.blocky);
+        buffers.quads.reset();
+        visible_block_faces(
+            &buffers.blocky,
+            &PaddedChunkShape {},
+            [0; 3],
+            [PADDED_CHUNK_LENGTH - 1; 3],
+            &RIGHT_HANDED_Y_UP_CONFIG.faces,
+            &mut buffers.quads,
+        );
+        push_blocky_quads(output, &buffers.blocky, &buffers.quads.groups);
+    }
 }
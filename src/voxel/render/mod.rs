@@ -1,5 +1,26 @@
 mod mesh;
 pub use mesh::*;
 
+mod shapes;
+pub use shapes::*;
+
+mod smooth;
+pub use smooth::*;
+
 mod chunk_material;
 pub use chunk_material::*;
+
+mod terrain_textures;
+pub use terrain_textures::*;
+
+mod fog;
+pub use fog::*;
+
+mod debug_view;
+pub use debug_view::*;
+
+mod water;
+pub use water::*;
+
+mod underwater;
+pub use underwater::*;
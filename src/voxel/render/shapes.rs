@@ -0,0 +1,169 @@
+use crate::voxel::{
+    material::{VoxelMeshingMode, VoxelModelBox, VoxelShape},
+    VoxelLight, CHUNK_LENGTH,
+};
+use bevy::math::{IVec3, Vec3};
+use block_mesh::{Voxel as MeshableVoxel, VoxelVisibility};
+
+use super::{
+    encode_voxel_data, planar_uv, ChunkMeshData, MeshingMaterials, PaddedChunkBuffer,
+    PaddedLightBuffer,
+};
+
+const SLAB_MODEL: &[VoxelModelBox] = &[VoxelModelBox {
+    min: [0.0, 0.0, 0.0],
+    max: [1.0, 0.5, 1.0],
+}];
+
+const STAIR_MODEL: &[VoxelModelBox] = &[
+    VoxelModelBox {
+        min: [0.0, 0.0, 0.0],
+        max: [1.0, 0.5, 1.0],
+    },
+    VoxelModelBox {
+        min: [0.0, 0.5, 0.5],
+        max: [1.0, 1.0, 1.0],
+    },
+];
+
+/// Emits the geometry of the voxels whose shape isn't a full cube.
+pub fn mesh_shaped_voxels(
+    voxels: &PaddedChunkBuffer,
+    lights: &PaddedLightBuffer,
+    materials: &MeshingMaterials,
+    output: &mut ChunkMeshData,
+) {
+    // Whether the voxel at the specified position hides the faces of its neighbours.
+    let is_occluding = |pos: IVec3| {
+        let voxel = voxels.voxel_at(pos.as_uvec3().to_array().into());
+        let info = materials.get(voxel.0);
+        voxel.get_visibility() == VoxelVisibility::Opaque
+            && info.mode == VoxelMeshingMode::Blocky
+            && info.shape == VoxelShape::Cube
+    };
+
+    for x in 1..=CHUNK_LENGTH {
+        for y in 1..=CHUNK_LENGTH {
+            for z in 1..=CHUNK_LENGTH {
+                let voxel = voxels.voxel_at([x, y, z].into());
+                if voxel.get_visibility() == VoxelVisibility::Empty {
+                    continue;
+                }
+
+                let pos = IVec3::new(x as i32, y as i32, z as i32);
+                // shaped voxels let light through so they are lit by their own light level.
+                let light = lights.voxel_at([x, y, z].into());
+                let model = match materials.shape(voxel.0) {
+                    VoxelShape::Cube => continue,
+                    VoxelShape::Cross => {
+                        push_cross(output, pos.as_vec3(), voxel.0, light);
+                        continue;
+                    }
+                    VoxelShape::Slab => SLAB_MODEL,
+                    VoxelShape::Stair => STAIR_MODEL,
+                    VoxelShape::Custom(model) => model,
+                };
+
+                push_model(output, pos, voxel.0, light, model, is_occluding);
+            }
+        }
+    }
+}
+
+/// Emits two double sided diagonal planes crossing in the middle of the voxel.
+fn push_cross(output: &mut ChunkMeshData, origin: Vec3, material: u16, light: VoxelLight) {
+    let data = encode_voxel_data(material, 7, material, 0);
+
+    for (corners, normal) in [
+        (
+            [[0., 0., 0.], [1., 0., 1.], [1., 1., 1.], [0., 1., 0.]],
+            Vec3::new(-1., 0., 1.),
+        ),
+        (
+            [[1., 0., 0.], [0., 0., 1.], [0., 1., 1.], [1., 1., 0.]],
+            Vec3::new(-1., 0., -1.),
+        ),
+    ] {
+        let corners = corners.map(|c| (origin + Vec3::from_array(c)).to_array());
+        let normal = normal.normalize();
+        let [a, b, c, d] = corners;
+        let uvs = [[0., 1.], [1., 1.], [1., 0.], [0., 0.]];
+        let [ua, ub, uc, ud] = uvs;
+
+        output.push_quad(corners, normal.to_array(), uvs, data, light);
+        output.push_quad(
+            [a, d, c, b],
+            (-normal).to_array(),
+            [ua, ud, uc, ub],
+            data,
+            light,
+        );
+    }
+}
+
+/// Emits the faces of the boxes of a voxel model, culling the faces lying against an occluding neighbour.
+fn push_model(
+    output: &mut ChunkMeshData,
+    pos: IVec3,
+    material: u16,
+    light: VoxelLight,
+    model: &[VoxelModelBox],
+    is_occluding: impl Fn(IVec3) -> bool,
+) {
+    let origin = pos.as_vec3();
+
+    for model_box in model {
+        let min = Vec3::from_array(model_box.min);
+        let max = Vec3::from_array(model_box.max);
+
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            for (sign, plane) in [(-1, min[axis]), (1, max[axis])] {
+                let normal = IVec3::AXES[axis] * sign;
+
+                // faces on the voxel boundary are hidden by occluding neighbours.
+                let on_boundary = (sign < 0 && plane <= 0.0) || (sign > 0 && plane >= 1.0);
+                if on_boundary && is_occluding(pos + normal) {
+                    continue;
+                }
+
+                let corner = |cu: f32, cv: f32| {
+                    let mut c = Vec3::ZERO;
+                    c[axis] = plane;
+                    c[u] = cu;
+                    c[v] = cv;
+                    c
+                };
+
+                let corners = [
+                    corner(min[u], min[v]),
+                    corner(max[u], min[v]),
+                    corner(max[u], max[v]),
+                    corner(min[u], max[v]),
+                ];
+                let corners = if sign > 0 {
+                    corners
+                } else {
+                    let [a, b, c, d] = corners;
+                    [a, d, c, b]
+                };
+
+                // uvs are computed in the voxel local space so partial faces only show part of the texture.
+                let uvs = corners.map(|c| planar_uv(c.to_array(), axis));
+                let corners = corners.map(|c| (origin + c).to_array());
+
+                // matches the face order of the `RIGHT_HANDED_Y_UP_CONFIG` block faces.
+                let face_index = if sign > 0 { axis + 3 } else { axis };
+
+                output.push_quad(
+                    corners,
+                    normal.as_vec3().to_array(),
+                    uvs,
+                    encode_voxel_data(material, face_index as u32, material, 0),
+                    light,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::cmp::Reverse;
+
+use crate::voxel::{
+    material::{VoxelMeshingMode, VoxelShape},
+    Voxel, VoxelLight, CHUNK_LENGTH,
+};
+use bevy::math::{IVec3, Vec3};
+use block_mesh::{Voxel as MeshableVoxel, VoxelVisibility};
+use ndshape::{ConstShape, ConstShape3u32};
+
+use super::{
+    encode_voxel_data, planar_uv, ChunkMeshData, MeshingMaterials, PaddedChunkBuffer,
+    PaddedChunkShape, PaddedLightBuffer,
+};
+
+// The density samples map one to one to the voxels of the padded chunk.
+type DensityShape = PaddedChunkShape;
+type CellsShape = ConstShape3u32<{ CHUNK_LENGTH + 1 }, { CHUNK_LENGTH + 1 }, { CHUNK_LENGTH + 1 }>;
+
+/// Intermediate buffers for surface nets meshing which are reusable between chunks to not allocate.
+pub struct SurfaceNetsBuffers {
+    // The signed density samples.
+    densities: Vec<f32>,
+    // Index of the vertex generated in each cell (or `u32::MAX` if the cell has none).
+    cell_vertices: Vec<u32>,
+}
+
+impl Default for SurfaceNetsBuffers {
+    fn default() -> Self {
+        Self {
+            densities: vec![1.0; DensityShape::USIZE],
+            cell_vertices: vec![u32::MAX; CellsShape::USIZE],
+        }
+    }
+}
+
+/// Meshes the voxels using the [`VoxelMeshingMode::Smooth`] meshing mode using the surface nets algorithm.
+///
+/// Voxels of other materials are considered to be outside of the surface, so smooth surfaces
+/// end against blocky voxels which get meshed with their own faces.
+pub fn mesh_smooth_voxels(
+    voxels: &PaddedChunkBuffer,
+    lights: &PaddedLightBuffer,
+    buffers: &mut SurfaceNetsBuffers,
+    materials: &MeshingMaterials,
+    output: &mut ChunkMeshData,
+) {
+    let is_smooth = |voxel: Voxel| {
+        let info = materials.get(voxel.0);
+        voxel.get_visibility() != VoxelVisibility::Empty
+            && info.mode == VoxelMeshingMode::Smooth
+            && info.shape == VoxelShape::Cube
+    };
+
+    if !voxels.slice().iter().any(|voxel| is_smooth(*voxel)) {
+        return;
+    }
+
+    let chunk_max = IVec3::splat(CHUNK_LENGTH as i32);
+
+    // The padding holds the border voxels of the neighbouring chunks, so smooth surfaces flow across chunk borders.
+    let voxel_at_sample = |sample: IVec3| voxels.voxel_at(sample.as_uvec3().to_array().into());
+
+    let density_at = |densities: &[f32], sample: IVec3| {
+        densities[DensityShape::linearize(sample.as_uvec3().to_array()) as usize]
+    };
+
+    for (index, density) in buffers.densities.iter_mut().enumerate() {
+        let sample = IVec3::from_array(DensityShape::delinearize(index as u32).map(|x| x as i32));
+        *density = if is_smooth(voxel_at_sample(sample)) {
+            -1.0
+        } else {
+            1.0
+        };
+    }
+
+    // Place a vertex in every cell crossed by the surface.
+    buffers.cell_vertices.fill(u32::MAX);
+
+    for (index, cell_vertex) in buffers.cell_vertices.iter_mut().enumerate() {
+        let cell = IVec3::from_array(CellsShape::delinearize(index as u32).map(|x| x as i32));
+        let corners = CELL_CORNERS.map(|offset| density_at(&buffers.densities, cell + offset));
+
+        let Some(vertex) = surface_nets_vertex(&corners) else {
+            continue;
+        };
+
+        let (material, blend_material, blend) = blend_cell_materials(&corners, |corner| {
+            voxel_at_sample(cell + CELL_CORNERS[corner]).0
+        });
+
+        *cell_vertex = output.positions.len() as u32;
+
+        // Samples are located at the center of voxels.
+        let position = (cell.as_vec3() + vertex + Vec3::splat(0.5)).to_array();
+        let normal = surface_nets_normal(&corners);
+
+        output.positions.push(position);
+        output.normals.push(normal.to_array());
+        // textures are projected along the dominant axis of the surface normal.
+        let normal_axis = (0..3)
+            .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+            .unwrap_or(1);
+        output.uvs.push(planar_uv(position, normal_axis));
+        output
+            .data
+            .push(encode_voxel_data(material, 7, blend_material, blend));
+        // the vertex is lit by the brightest of the samples outside of the surface around it.
+        let light = CELL_CORNERS
+            .iter()
+            .map(|offset| lights.voxel_at((cell + *offset).as_uvec3().to_array().into()))
+            .fold(VoxelLight::NONE, VoxelLight::max);
+        output.lights.push(light.0 as u32);
+    }
+
+    // Connect the vertices of the 4 cells sharing each edge crossed by the surface.
+    // Only the edges starting from a voxel of this chunk are processed so the chunks don't overlap.
+    for x in 1..=chunk_max.x {
+        for y in 1..=chunk_max.y {
+            for z in 1..=chunk_max.z {
+                let sample = IVec3::new(x, y, z);
+                let inside = density_at(&buffers.densities, sample) < 0.0;
+
+                for axis in 0..3 {
+                    let [n, u, v] = [axis, (axis + 1) % 3, (axis + 2) % 3].map(|a| IVec3::AXES[a]);
+
+                    if inside == (density_at(&buffers.densities, sample + n) < 0.0) {
+                        continue;
+                    }
+
+                    let quad = [sample - u - v, sample - v, sample, sample - u].map(|cell| {
+                        buffers.cell_vertices
+                            [CellsShape::linearize(cell.as_uvec3().to_array()) as usize]
+                    });
+
+                    if quad.contains(&u32::MAX) {
+                        continue;
+                    }
+
+                    // quads are wound counter-clockwise when looking at them from the outside of the surface.
+                    if inside {
+                        output.indices.extend_from_slice(&[
+                            quad[0], quad[1], quad[2], quad[0], quad[2], quad[3],
+                        ]);
+                    } else {
+                        output.indices.extend_from_slice(&[
+                            quad[0], quad[2], quad[1], quad[0], quad[3], quad[2],
+                        ]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+const CELL_CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(0, 1, 1),
+    IVec3::new(1, 1, 1),
+];
+
+/// The 12 edges of a cell as pairs of corner indices.
+const CELL_EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [2, 3],
+    [4, 5],
+    [6, 7],
+    [0, 2],
+    [1, 3],
+    [4, 6],
+    [5, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+/// Computes the position of the surface nets vertex in a cell relative to its minimum corner
+/// as the average of the surface crossings on the cell edges.
+fn surface_nets_vertex(corners: &[f32; 8]) -> Option<Vec3> {
+    let (sum, count) = CELL_EDGES
+        .iter()
+        .filter(|[a, b]| (corners[*a] < 0.0) != (corners[*b] < 0.0))
+        .fold((Vec3::ZERO, 0), |(sum, count), [a, b]| {
+            let t = corners[*a] / (corners[*a] - corners[*b]);
+            let crossing = CELL_CORNERS[*a]
+                .as_vec3()
+                .lerp(CELL_CORNERS[*b].as_vec3(), t);
+            (sum + crossing, count + 1)
+        });
+
+    (count > 0).then(|| sum / count as f32)
+}
+
+/// Computes the smoothed surface normal in a cell from the gradient of the density field.
+fn surface_nets_normal(corners: &[f32; 8]) -> Vec3 {
+    let gradient = CELL_CORNERS
+        .iter()
+        .zip(corners)
+        .fold(Vec3::ZERO, |gradient, (corner, density)| {
+            gradient + (corner.as_vec3() * 2.0 - Vec3::ONE) * *density
+        });
+
+    gradient.try_normalize().unwrap_or(Vec3::Y)
+}
+
+/// Picks the two most represented materials among the solid corners of a cell along with the blend factor between them.
+fn blend_cell_materials(corners: &[f32; 8], material_at: impl Fn(usize) -> u16) -> (u16, u16, u8) {
+    let mut counts: [(u16, u32); 8] = [(0, 0); 8];
+
+    (0..8)
+        .filter(|corner| corners[*corner] < 0.0)
+        .for_each(|corner| {
+            let material = material_at(corner);
+            if let Some(entry) = counts
+                .iter_mut()
+                .find(|(mat, count)| *count == 0 || *mat == material)
+            {
+                *entry = (material, entry.1 + 1);
+            }
+        });
+
+    counts.sort_unstable_by_key(|(_, count)| Reverse(*count));
+
+    let (primary, primary_count) = counts[0];
+    match counts[1] {
+        (_, 0) => (primary, primary, 0),
+        (secondary, secondary_count) => (
+            primary,
+            secondary,
+            (secondary_count * 255 / (primary_count + secondary_count)) as u8,
+        ),
+    }
+}
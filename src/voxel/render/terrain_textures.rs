@@ -0,0 +1,158 @@
+use crate::voxel::material::VoxelMaterialRegistry;
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+        },
+        texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+    },
+};
+
+/// The textures of the registered voxel materials stacked into the layers of a single 2D texture array.
+#[derive(Resource, Default)]
+pub struct TerrainTextureArray {
+    /// The texture paths in layer order.
+    layers: Vec<&'static str>,
+    /// The handles of the individual textures being loaded.
+    sources: Vec<Handle<Image>>,
+    /// The texture array, available once every texture got loaded.
+    image: Option<Handle<Image>>,
+}
+
+impl TerrainTextureArray {
+    /// Returns the texture array if it is ready to be used.
+    #[inline]
+    pub fn image(&self) -> Option<&Handle<Image>> {
+        self.image.as_ref()
+    }
+
+    /// Returns the layer of the texture with the specified path or `-1` if there's no such texture
+    /// or the texture array isn't ready yet.
+    pub fn layer_of(&self, path: Option<&'static str>) -> i32 {
+        path.filter(|_| self.image.is_some())
+            .and_then(|path| self.layers.iter().position(|layer| *layer == path))
+            .map_or(-1, |layer| layer as i32)
+    }
+}
+
+/// Starts loading the textures referenced by the registered materials whenever they change.
+fn load_terrain_textures(
+    registry: Res<VoxelMaterialRegistry>,
+    asset_server: Res<AssetServer>,
+    mut textures: ResMut<TerrainTextureArray>,
+) {
+    let mut layers = Vec::new();
+    for path in registry
+        .iter_mats()
+        .flat_map(|material| material.textures.faces())
+        .flatten()
+    {
+        if !layers.contains(&path) {
+            layers.push(path);
+        }
+    }
+
+    if layers == textures.layers {
+        return;
+    }
+
+    textures.sources = layers.iter().map(|path| asset_server.load(*path)).collect();
+    textures.layers = layers;
+    textures.image = None;
+}
+
+/// Stacks the material textures into the texture array once all of them are loaded.
+fn build_terrain_texture_array(
+    mut textures: ResMut<TerrainTextureArray>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if textures.image.is_some() || textures.sources.is_empty() {
+        return;
+    }
+
+    let Some(sources) = textures
+        .sources
+        .iter()
+        .map(|handle| images.get(handle))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+
+    let size = sources[0].size();
+    let mut data = Vec::new();
+
+    for (source, path) in sources.iter().zip(&textures.layers) {
+        if source.size() != size {
+            error!(
+                "Terrain texture {} is {}x{} while terrain textures should be {}x{}",
+                path,
+                source.width(),
+                source.height(),
+                size.x,
+                size.y
+            );
+            textures.sources.clear();
+            return;
+        }
+
+        match (*source).clone().try_into_dynamic() {
+            Ok(image) => data.extend(image.to_rgba8().into_raw()),
+            Err(err) => {
+                error!("Unsupported format for terrain texture {}: {}", path, err);
+                textures.sources.clear();
+                return;
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: sources.len() as u32,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+
+    // a single layer array would otherwise be viewed as a regular 2D texture.
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+
+    // textures repeat over greedy quads spanning multiple voxels.
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::nearest()
+    });
+
+    info!("Built terrain texture array with {} layers", sources.len());
+    textures.image = Some(images.add(image));
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, SystemSet)]
+/// Systems that load the voxel material textures into the [`TerrainTextureArray`].
+pub struct TerrainTexturesSet;
+
+pub struct TerrainTexturesPlugin;
+
+impl Plugin for TerrainTexturesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainTextureArray>().add_systems(
+            Update,
+            (
+                load_terrain_textures.run_if(resource_changed::<VoxelMaterialRegistry>),
+                build_terrain_texture_array,
+            )
+                .chain()
+                .in_set(TerrainTexturesSet),
+        );
+    }
+}
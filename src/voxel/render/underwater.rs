@@ -0,0 +1,284 @@
+use bevy::{
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+        prepass::ViewPrepassTextures,
+    },
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        extract_component::{
+            ComponentUniforms, DynamicUniformIndex, ExtractComponent, ExtractComponentPlugin,
+            UniformComponentPlugin,
+        },
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            binding_types::{
+                sampler, texture_2d, texture_depth_2d, texture_depth_2d_multisampled,
+                uniform_buffer,
+            },
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, MultisampleState, Operations,
+            PipelineCache, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
+            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            ShaderType, TextureFormat, TextureSampleType,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::BevyDefault,
+        view::ViewTarget,
+        RenderApp,
+    },
+};
+
+use crate::voxel::{
+    material::{VoxelMaterialFlags, VoxelMaterialRegistry},
+    storage::ChunkMap,
+    ChunkShape, CurrentLocalPlayerChunk, Voxel,
+};
+
+/// Tints and fogs the view of a camera while the camera is inside a liquid voxel.
+/// The camera needs a [`DepthPrepass`](bevy::core_pipeline::prepass::DepthPrepass) for the fog to be applied.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct UnderwaterSettings {
+    pub color: Color,
+    /// The distance (in voxels) at which the scene gets completely hidden in the underwater fog.
+    pub fog_distance: f32,
+    /// Whether the camera is currently under water, kept up to date from the voxel at the player position.
+    pub submerged: bool,
+}
+
+impl Default for UnderwaterSettings {
+    fn default() -> Self {
+        Self {
+            color: Color::srgb_u8(22, 78, 120),
+            fog_distance: 24.0,
+            submerged: false,
+        }
+    }
+}
+
+/// The GPU representation of the [`UnderwaterSettings`], only extracted for submerged cameras.
+#[derive(Component, ShaderType, Clone, Copy, Debug)]
+pub struct GpuUnderwaterSettings {
+    color: LinearRgba,
+    fog_distance: f32,
+    /// The near plane of the camera to linearize the reversed infinite perspective depth.
+    camera_near: f32,
+}
+
+impl ExtractComponent for UnderwaterSettings {
+    type QueryData = (&'static UnderwaterSettings, Option<&'static Projection>);
+    type QueryFilter = ();
+    type Out = GpuUnderwaterSettings;
+
+    fn extract_component(
+        (settings, projection): QueryItem<'_, Self::QueryData>,
+    ) -> Option<Self::Out> {
+        let camera_near = match projection {
+            Some(Projection::Perspective(perspective)) => perspective.near,
+            _ => PerspectiveProjection::default().near,
+        };
+
+        settings.submerged.then(|| GpuUnderwaterSettings {
+            color: settings.color.into(),
+            fog_distance: settings.fog_distance.max(0.01),
+            camera_near,
+        })
+    }
+}
+
+/// Updates whether the cameras are under water from the material of the voxel at the player position.
+fn update_camera_submersion(
+    player_chunk: Res<CurrentLocalPlayerChunk>,
+    chunks: Res<ChunkMap<Voxel, ChunkShape>>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut cameras: Query<&mut UnderwaterSettings>,
+) {
+    let submerged = chunks
+        .voxel_at(player_chunk.world_pos)
+        .and_then(|voxel| registry.get_by_id(voxel.0))
+        .is_some_and(|material| material.flags.contains(VoxelMaterialFlags::LIQUID));
+
+    for mut settings in &mut cameras {
+        if settings.submerged != submerged {
+            settings.submerged = submerged;
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct UnderwaterLabel;
+
+#[derive(Default)]
+struct UnderwaterNode;
+
+impl ViewNode for UnderwaterNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static ViewPrepassTextures,
+        &'static DynamicUniformIndex<GpuUnderwaterSettings>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, prepass_textures, settings_index): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let underwater_pipeline = world.resource::<UnderwaterPipeline>();
+        let multisampled = world.resource::<Msaa>().samples() > 1;
+        let (layout, pipeline_id) = &underwater_pipeline.variants[multisampled as usize];
+
+        let (Some(pipeline), Some(depth), Some(settings)) = (
+            world
+                .resource::<PipelineCache>()
+                .get_render_pipeline(*pipeline_id),
+            prepass_textures.depth_view(),
+            world
+                .resource::<ComponentUniforms<GpuUnderwaterSettings>>()
+                .uniforms()
+                .binding(),
+        ) else {
+            return Ok(());
+        };
+
+        let post_process = view_target.post_process_write();
+        let bind_group = render_context.render_device().create_bind_group(
+            "underwater_bind_group",
+            layout,
+            &BindGroupEntries::sequential((
+                post_process.source,
+                &underwater_pipeline.sampler,
+                depth,
+                settings,
+            )),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("underwater_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[settings_index.index()]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+/// The underwater post-process pipelines, for single and multisampled depth textures.
+#[derive(Resource)]
+struct UnderwaterPipeline {
+    variants: [(BindGroupLayout, CachedRenderPipelineId); 2],
+    sampler: Sampler,
+}
+
+impl FromWorld for UnderwaterPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.load_asset("shaders/underwater.wgsl");
+        let render_device = world.resource::<RenderDevice>().clone();
+
+        let variants = [false, true].map(|multisampled| {
+            let layout = render_device.create_bind_group_layout(
+                "underwater_bind_group_layout",
+                &BindGroupLayoutEntries::sequential(
+                    ShaderStages::FRAGMENT,
+                    (
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                        sampler(SamplerBindingType::Filtering),
+                        if multisampled {
+                            texture_depth_2d_multisampled()
+                        } else {
+                            texture_depth_2d()
+                        },
+                        uniform_buffer::<GpuUnderwaterSettings>(true),
+                    ),
+                ),
+            );
+
+            let pipeline_id = world.resource_mut::<PipelineCache>().queue_render_pipeline(
+                RenderPipelineDescriptor {
+                    label: Some("underwater_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader: shader.clone(),
+                        shader_defs: if multisampled {
+                            vec!["MULTISAMPLED".into()]
+                        } else {
+                            vec![]
+                        },
+                        entry_point: "fragment".into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: TextureFormat::bevy_default(),
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                },
+            );
+
+            (layout, pipeline_id)
+        });
+
+        Self {
+            variants,
+            sampler: render_device.create_sampler(&SamplerDescriptor::default()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, SystemSet)]
+/// Systems detecting whether the cameras are under water.
+pub struct UnderwaterSet;
+
+/// Renders the underwater post-process of the cameras with [`UnderwaterSettings`].
+pub struct UnderwaterPlugin;
+
+impl Plugin for UnderwaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            ExtractComponentPlugin::<UnderwaterSettings>::default(),
+            UniformComponentPlugin::<GpuUnderwaterSettings>::default(),
+        ))
+        .add_systems(Update, update_camera_submersion.in_set(UnderwaterSet));
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<UnderwaterNode>>(Core3d, UnderwaterLabel)
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::Tonemapping,
+                    UnderwaterLabel,
+                    Node3d::EndMainPassPostProcessing,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<UnderwaterPipeline>();
+    }
+}
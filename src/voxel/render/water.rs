@@ -0,0 +1,168 @@
+use bevy::{
+    prelude::*,
+    reflect::TypePath,
+    render::render_resource::{AsBindGroup, ShaderType},
+};
+
+use super::{GpuTerrainFog, TerrainFogSet, TerrainFogSettings, UnderwaterPlugin, VoxelTerrainMesh};
+
+/// Settings of the shading of the water surface.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct WaterSettings {
+    /// The colour of shallow water.
+    pub shallow_color: Color,
+    /// The colour the water takes as the light travels deeper through it.
+    pub deep_color: Color,
+    /// How fast the light gets absorbed along its path through the water, per voxel.
+    pub absorption: f32,
+    /// The height of the waves (in voxels).
+    pub wave_height: f32,
+    /// The length of the waves (in voxels).
+    pub wave_length: f32,
+    pub wave_speed: f32,
+    /// The strength of the animated ripples perturbing the surface normals.
+    pub ripple_strength: f32,
+    /// The reflectance of the water when looking at it straight down, following the Schlick approximation of the Fresnel effect.
+    pub reflectance: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            shallow_color: Color::srgba_u8(78, 167, 215, 90),
+            deep_color: Color::srgb_u8(12, 48, 82),
+            absorption: 0.12,
+            wave_height: 0.08,
+            wave_length: 9.0,
+            wave_speed: 1.2,
+            ripple_strength: 0.25,
+            reflectance: 0.02,
+        }
+    }
+}
+
+/// The GPU representation of the [`WaterSettings`].
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct GpuWaterSettings {
+    shallow_color: LinearRgba,
+    deep_color: LinearRgba,
+    absorption: f32,
+    wave_height: f32,
+    wave_length: f32,
+    wave_speed: f32,
+    ripple_strength: f32,
+    reflectance: f32,
+}
+
+impl From<&WaterSettings> for GpuWaterSettings {
+    fn from(settings: &WaterSettings) -> Self {
+        Self {
+            shallow_color: settings.shallow_color.into(),
+            deep_color: settings.deep_color.into(),
+            absorption: settings.absorption,
+            wave_height: settings.wave_height,
+            wave_length: settings.wave_length.max(0.01),
+            wave_speed: settings.wave_speed,
+            ripple_strength: settings.ripple_strength,
+            reflectance: settings.reflectance,
+        }
+    }
+}
+
+/// The material of the liquid meshes of the chunks.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct WaterMaterial {
+    #[uniform(0)]
+    pub water: GpuWaterSettings,
+    #[uniform(1)]
+    pub fog: GpuTerrainFog,
+}
+
+impl Default for WaterMaterial {
+    fn default() -> Self {
+        Self {
+            water: GpuWaterSettings::from(&WaterSettings::default()),
+            fog: GpuTerrainFog::from(&TerrainFogSettings::default()),
+        }
+    }
+}
+
+impl Material for WaterMaterial {
+    fn vertex_shader() -> bevy::render::render_resource::ShaderRef {
+        "shaders/water_pipeline.wgsl".into()
+    }
+
+    fn fragment_shader() -> bevy::render::render_resource::ShaderRef {
+        "shaders/water_pipeline.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &bevy::pbr::MaterialPipeline<Self>,
+        descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
+        layout: &bevy::render::mesh::MeshVertexBufferLayoutRef,
+        _key: bevy::pbr::MaterialPipelineKey<Self>,
+    ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
+        let vertex_layout = layout.0.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            VoxelTerrainMesh::ATTRIBUTE_DATA.at_shader_location(1),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(2),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(3),
+            VoxelTerrainMesh::ATTRIBUTE_LIGHT.at_shader_location(4),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        // the surface can be seen from below the water.
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct WaterMaterialSingleton(Handle<WaterMaterial>);
+
+impl FromWorld for WaterMaterialSingleton {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<WaterMaterial>>();
+        Self(materials.add(WaterMaterial::default()))
+    }
+}
+
+fn update_water_material(
+    water_settings: Res<WaterSettings>,
+    fog_settings: Res<TerrainFogSettings>,
+    water_material: Res<WaterMaterialSingleton>,
+    mut materials: ResMut<Assets<WaterMaterial>>,
+) {
+    if let Some(material) = materials.get_mut(&**water_material) {
+        material.water = GpuWaterSettings::from(&*water_settings);
+        material.fog = GpuTerrainFog::from(&*fog_settings);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, SystemSet)]
+/// Systems keeping the water material up to date with the [`WaterSettings`] and the terrain fog.
+pub struct WaterMaterialSet;
+
+pub struct WaterMaterialPlugin;
+
+impl Plugin for WaterMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<WaterMaterial>::default())
+            .add_plugins(UnderwaterPlugin)
+            .init_resource::<WaterSettings>()
+            .init_resource::<WaterMaterialSingleton>()
+            .add_systems(
+                Update,
+                update_water_material
+                    .run_if(
+                        resource_changed::<WaterSettings>
+                            .or_else(resource_changed::<TerrainFogSettings>),
+                    )
+                    .in_set(WaterMaterialSet)
+                    .after(TerrainFogSet),
+            );
+    }
+}
@@ -1,5 +1,5 @@
 use ilattice::{morton::Morton3i32, vector::Map as VecMap};
-use std::{collections::BTreeMap, hash::Hash};
+use std::{collections::BTreeMap, hash::Hash, sync::Arc};
 
 use bevy::{math::IVec3, prelude::Resource};
 use ndshape::Shape;
@@ -9,13 +9,16 @@ use crate::voxel::CHUNK_LENGTH;
 use super::buffer::VoxelBuffer;
 
 /// Provides an interface to query or modify voxel data for worlds or scenes split into multiple voxel data buffers of a same shape with no level of detail.
+///
+/// Buffers are stored with shared copy-on-write ownership, so a snapshot of a buffer can be cheaply taken (e.g. for meshing it in a background task)
+/// while the map keeps being edited: the buffer only gets copied when it is modified while a snapshot of it is alive.
 #[derive(Resource)]
 pub struct ChunkMap<V, S>
 where
     V: Clone + Copy + Default + PartialEq + Eq + Hash,
     S: Shape<3, Coord = u32> + Clone,
 {
-    chunks: BTreeMap<Morton3i32, VoxelBuffer<V, S>>,
+    chunks: BTreeMap<Morton3i32, Arc<VoxelBuffer<V, S>>>,
     shape_mask: IVec3,
     shape: S,
 }
@@ -65,14 +68,34 @@ where
     #[inline]
     pub fn buffer_at(&self, minimum: IVec3) -> Option<&VoxelBuffer<V, S>> {
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
-        self.chunks.get(&minimum.into())
+        self.chunks.get(&minimum.into()).map(Arc::as_ref)
+    }
+
+    /// Returns a shared snapshot of the [`VoxelBuffer<V, S>`] at the specified minimum if there's one.
+    /// Edits made to the map after taking the snapshot aren't visible in the snapshot.
+    #[inline]
+    pub fn shared_buffer_at(&self, minimum: IVec3) -> Option<Arc<VoxelBuffer<V, S>>> {
+        let minimum = ilattice::glam::IVec3::from(minimum.to_array());
+        self.chunks.get(&minimum.into()).cloned()
+    }
+
+    /// Returns shared snapshots of the buffer at the specified minimum and of its 26 neighbours,
+    /// indexed by `(x + 1) * 9 + (y + 1) * 3 + (z + 1)` for a neighbour offset of `(x, y, z)` chunks.
+    pub fn shared_neighbourhood_at(&self, minimum: IVec3) -> [Option<Arc<VoxelBuffer<V, S>>>; 27] {
+        let chunk_size = IVec3::from(self.shape.as_array().map(|x| x as i32));
+        std::array::from_fn(|index| {
+            let offset =
+                IVec3::new(index as i32 / 9, index as i32 / 3 % 3, index as i32 % 3) - IVec3::ONE;
+            self.shared_buffer_at(minimum + offset * chunk_size)
+        })
     }
 
     /// Returns a mutable reference to the [`VoxelBuffer<V, S>`] at the specified minimum if there's one.
+    /// The buffer gets copied first if snapshots of it are still alive.
     #[inline]
     pub fn buffer_at_mut(&mut self, minimum: IVec3) -> Option<&mut VoxelBuffer<V, S>> {
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
-        self.chunks.get_mut(&minimum.into())
+        self.chunks.get_mut(&minimum.into()).map(Arc::make_mut)
     }
 
     /// Inserts a new buffer at the specified minimum.
@@ -80,7 +103,7 @@ where
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
 
         assert!(buffer.shape().as_array() == self.shape.as_array());
-        self.chunks.insert(minimum.into(), buffer);
+        self.chunks.insert(minimum.into(), Arc::new(buffer));
     }
 
     /// Inserts a new buffer inititalized with the default value of [`V`] at the specified minimum.
@@ -88,7 +111,7 @@ where
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
         self.chunks.insert(
             minimum.into(),
-            VoxelBuffer::<V, S>::new_empty(self.shape.clone()),
+            Arc::new(VoxelBuffer::<V, S>::new_empty(self.shape.clone())),
         );
     }
 
@@ -97,11 +120,14 @@ where
         &mut self,
         iter: T,
     ) {
-        self.chunks.extend(iter);
+        self.chunks.extend(
+            iter.into_iter()
+                .map(|(key, buffer)| (key, Arc::new(buffer))),
+        );
     }
 
     /// Removes the buffer at the specified minimum and returns it if it exists.
-    pub fn remove(&mut self, pos: IVec3) -> Option<VoxelBuffer<V, S>> {
+    pub fn remove(&mut self, pos: IVec3) -> Option<Arc<VoxelBuffer<V, S>>> {
         let pos = ilattice::glam::IVec3::from(pos.to_array());
         self.chunks.remove(&pos.into())
     }
@@ -0,0 +1,575 @@
+use std::{collections::HashMap as StdHashMap, sync::Arc};
+
+use bevy::{
+    asset::{
+        io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadDirectError, LoadState,
+        LoadedFolder, RecursiveDependencyLoadState,
+    },
+    math::{IVec2, IVec3, UVec2, Vec2},
+    prelude::*,
+    utils::HashMap,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::voxel::{
+    material::VoxelMaterialRegistry,
+    storage::VoxelBuffer,
+    terraingen::{
+        climate::ClimateRange,
+        common::{feature_voxels, make_pine_tree, make_rock, make_tree},
+        noise,
+        structures::{StructurePlacement, StructureTemplate, SurfaceAlignment},
+        WorldSeed, DECORATION_REACH, TERRAIN_GENERATOR,
+    },
+    ChunkShape, RegenerateTerrain, Voxel,
+};
+
+use super::{carve_strata, make_cacti, BiomeRelief, BiomeTerrainGenerator};
+
+/// The folder (relative to the `assets` folder) the biome definitions get loaded from.
+const BIOMES_FOLDER: &str = "biomes";
+
+/// A biome defined in a `.biome.ron` asset file.
+#[derive(Asset, TypePath, Clone, Debug, Deserialize)]
+pub struct BiomeAsset {
+    /// The name of the biome, a biome definition replaces the registered biomes with the same name.
+    pub name: String,
+    /// The region of the climate space the biome thrives in.
+    pub climate: ClimateRange,
+    #[serde(default)]
+    pub relief: BiomeRelief,
+    /// The material layers covering the terrain, from the surface down.
+    #[serde(default)]
+    pub strata: Vec<StrataLayer>,
+    #[serde(default)]
+    pub decorations: Vec<DecorationRule>,
+    #[serde(default)]
+    pub structures: Vec<StructureRule>,
+    /// The templates of the structures, loaded along the biome definition by their path.
+    #[serde(skip)]
+    pub templates: StdHashMap<String, StructureTemplateAsset>,
+}
+
+/// A layer of material of the strata of a biome.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StrataLayer {
+    /// The name of the material the layer is made of.
+    pub material: String,
+    /// The thickness (in voxels) of the layer.
+    pub depth: u32,
+}
+
+/// The features a biome may be decorated with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum DecorationFeature {
+    /// A single voxel on top of the surface, e.g. tall grass or flowers.
+    Plant,
+    /// A sphere of material, `size` being its radius.
+    Boulder,
+    /// A cactus, `size` being its height.
+    Cactus,
+    /// A tree with a round crown of leaves.
+    Tree,
+    /// A tree with a cone of leaves.
+    PineTree,
+}
+
+/// Describes how a feature gets scattered over the columns of a biome.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DecorationRule {
+    pub feature: DecorationFeature,
+    /// The chance of a column to get the feature, from 0 to 1.
+    pub chance: f32,
+    /// The name of the material the feature (or the trunk of a tree) is made of.
+    pub material: String,
+    /// The name of the material the leaves of a tree are made of.
+    #[serde(default)]
+    pub leaves: Option<String>,
+    /// The range the size of the feature gets picked in, see [`DecorationFeature`].
+    #[serde(default = "DecorationRule::default_size")]
+    pub size: (f32, f32),
+}
+
+impl DecorationRule {
+    fn default_size() -> (f32, f32) {
+        (1.0, 1.0)
+    }
+}
+
+/// Describes how a structure gets scattered over a biome, see [`StructurePlacement`].
+#[derive(Clone, Debug, Deserialize)]
+pub struct StructureRule {
+    /// The path (relative to the `assets` folder) of the `.structure.ron` file of the structure template.
+    pub template: String,
+    pub spacing: u32,
+    pub chance: f32,
+    #[serde(default)]
+    pub alignment: SurfaceAlignment,
+    #[serde(default)]
+    pub offset: i32,
+    #[serde(default)]
+    pub rotate: bool,
+    /// The names of the biomes the structure spawns in, or only the biome placing it if empty.
+    #[serde(default)]
+    pub biomes: Vec<String>,
+}
+
+/// A structure template defined in a `.structure.ron` asset file.
+#[derive(Asset, TypePath, Clone, Debug, Deserialize)]
+pub struct StructureTemplateAsset {
+    /// The position in the template of the voxel resting right above the surface of the terrain.
+    pub anchor: (u32, u32, u32),
+    /// The names of the materials of the characters making up the layers, `Void` carving air into the terrain.
+    pub palette: StdHashMap<char, String>,
+    /// The horizontal slices of the template from the bottom up, each slice being made of rows along the z axis of
+    /// characters along the x axis. The spaces are left out of the replace mask and keep the voxels of the world.
+    pub layers: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Error)]
+pub enum BiomeAssetLoaderError {
+    #[error("could not read the definition: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the definition: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not load the structure template: {0}")]
+    Template(#[from] LoadDirectError),
+}
+
+/// Loads the biome definitions from the `.biome.ron` files.
+#[derive(Default)]
+pub struct BiomeAssetLoader;
+
+impl AssetLoader for BiomeAssetLoader {
+    type Asset = BiomeAsset;
+    type Settings = ();
+    type Error = BiomeAssetLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let mut biome: BiomeAsset = ron::de::from_bytes(&bytes)?;
+
+        // the templates are loaded as dependencies so editing a template reloads the biomes placing it.
+        for rule in &biome.structures {
+            if !biome.templates.contains_key(&rule.template) {
+                let template = load_context
+                    .loader()
+                    .direct()
+                    .load::<StructureTemplateAsset>(rule.template.clone())
+                    .await?;
+                biome
+                    .templates
+                    .insert(rule.template.clone(), template.take());
+            }
+        }
+
+        Ok(biome)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["biome.ron"]
+    }
+}
+
+/// Loads the structure templates from the `.structure.ron` files.
+#[derive(Default)]
+pub struct StructureTemplateAssetLoader;
+
+impl AssetLoader for StructureTemplateAssetLoader {
+    type Asset = StructureTemplateAsset;
+    type Settings = ();
+    type Error = BiomeAssetLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["structure.ron"]
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BiomeDefinitionError {
+    #[error("unknown material {0}")]
+    UnknownMaterial(String),
+    #[error("the {0:?} decorations need a leaves material")]
+    MissingLeaves(DecorationFeature),
+    #[error("the {0:?} decorations can't be larger than {DECORATION_REACH} voxels")]
+    FeatureTooLarge(DecorationFeature),
+    #[error("the structure template {0} is not loaded")]
+    MissingTemplate(String),
+    #[error("invalid structure template {0}: {1}")]
+    InvalidTemplate(String, &'static str),
+    #[error("the structures need a non zero spacing")]
+    ZeroSpacing,
+}
+
+/// A decoration rule with its materials resolved to voxels.
+struct DataDecoration {
+    feature: DecorationFeature,
+    chance: f32,
+    material: Voxel,
+    leaves: Voxel,
+    size: (f32, f32),
+}
+
+/// A biome terrain generator built from a [`BiomeAsset`].
+pub struct DataBiomeTerrainGenerator {
+    name: String,
+    relief: BiomeRelief,
+    /// The material of each layer of the strata, from the surface down.
+    strata: Vec<Voxel>,
+    decorations: Vec<DataDecoration>,
+    structures: Vec<StructurePlacement>,
+}
+
+impl DataBiomeTerrainGenerator {
+    /// Builds the generator of a biome definition, looking up its materials in the material registry.
+    pub fn new(
+        asset: &BiomeAsset,
+        registry: &VoxelMaterialRegistry,
+    ) -> Result<Self, BiomeDefinitionError> {
+        let material = |name: &str| {
+            registry
+                .get_id_by_name(name)
+                .map(Voxel)
+                .ok_or_else(|| BiomeDefinitionError::UnknownMaterial(name.to_owned()))
+        };
+
+        let mut strata = Vec::new();
+        for layer in &asset.strata {
+            let voxel = material(&layer.material)?;
+            strata.extend((0..layer.depth).map(|_| voxel));
+        }
+
+        let decorations = asset
+            .decorations
+            .iter()
+            .map(|rule| {
+                let leaves = match (rule.feature, &rule.leaves) {
+                    (_, Some(leaves)) => material(leaves)?,
+                    (DecorationFeature::Tree | DecorationFeature::PineTree, None) => {
+                        return Err(BiomeDefinitionError::MissingLeaves(rule.feature))
+                    }
+                    (_, None) => Voxel::default(),
+                };
+
+                // the decorations spreading further than the reach would get cut at the chunk borders.
+                if rule.feature == DecorationFeature::Boulder
+                    && rule.size.1 > DECORATION_REACH as f32
+                {
+                    return Err(BiomeDefinitionError::FeatureTooLarge(rule.feature));
+                }
+
+                Ok(DataDecoration {
+                    feature: rule.feature,
+                    chance: rule.chance,
+                    material: material(&rule.material)?,
+                    leaves,
+                    size: rule.size,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let structures = asset
+            .structures
+            .iter()
+            .map(|rule| {
+                let template = asset
+                    .templates
+                    .get(&rule.template)
+                    .ok_or_else(|| BiomeDefinitionError::MissingTemplate(rule.template.clone()))?;
+                if rule.spacing == 0 {
+                    return Err(BiomeDefinitionError::ZeroSpacing);
+                }
+
+                let mut placement = StructurePlacement::new(
+                    Arc::new(structure_template(&rule.template, template, material)?),
+                    rule.spacing,
+                    rule.chance,
+                );
+                placement.alignment = rule.alignment;
+                placement.offset = rule.offset;
+                placement.rotate = rule.rotate;
+                placement.biomes = if rule.biomes.is_empty() {
+                    vec![asset.name.clone()]
+                } else {
+                    rule.biomes.clone()
+                };
+                Ok(placement)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            name: asset.name.clone(),
+            relief: asset.relief,
+            strata,
+            decorations,
+            structures,
+        })
+    }
+}
+
+/// Builds a structure template from its definition, `material` looking up the materials by name.
+fn structure_template(
+    path: &str,
+    asset: &StructureTemplateAsset,
+    material: impl Fn(&str) -> Result<Voxel, BiomeDefinitionError>,
+) -> Result<StructureTemplate, BiomeDefinitionError> {
+    let invalid = |reason| BiomeDefinitionError::InvalidTemplate(path.to_owned(), reason);
+
+    let size_y = asset.layers.len();
+    let size_z = asset.layers.first().map_or(0, Vec::len);
+    let size_x = asset
+        .layers
+        .first()
+        .and_then(|layer| layer.first())
+        .map_or(0, |row| row.chars().count());
+    if size_x * size_y * size_z == 0 {
+        return Err(invalid("the template is empty"));
+    }
+
+    let palette = asset
+        .palette
+        .iter()
+        .map(|(key, name)| Ok((*key, material(name)?)))
+        .collect::<Result<StdHashMap<_, _>, _>>()?;
+
+    let mut voxels = Vec::with_capacity(size_x * size_y * size_z);
+    for layer in &asset.layers {
+        if layer.len() != size_z {
+            return Err(invalid("the layers must have the same number of rows"));
+        }
+
+        for row in layer {
+            if row.chars().count() != size_x {
+                return Err(invalid("the rows must have the same length"));
+            }
+
+            for key in row.chars() {
+                voxels.push(match key {
+                    ' ' => None,
+                    key => Some(
+                        *palette
+                            .get(&key)
+                            .ok_or_else(|| invalid("a character is missing from the palette"))?,
+                    ),
+                });
+            }
+        }
+    }
+
+    let size = UVec3::new(size_x as u32, size_y as u32, size_z as u32);
+    let anchor = UVec3::from(asset.anchor);
+    if anchor.cmpge(size).any() {
+        return Err(invalid("the anchor lies outside of the template"));
+    }
+
+    Ok(StructureTemplate::new(
+        path.to_owned(),
+        size,
+        anchor.as_ivec3(),
+        voxels,
+    ))
+}
+
+impl BiomeTerrainGenerator for DataBiomeTerrainGenerator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn relief(&self) -> BiomeRelief {
+        self.relief
+    }
+
+    fn carve_column(
+        &self,
+        chunk_key: IVec3,
+        column: UVec2,
+        surface: i32,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        if let Some(num_layers) = self.strata.len().checked_sub(1) {
+            carve_strata(
+                chunk_key,
+                column,
+                surface,
+                num_layers as u32,
+                |layer| self.strata[layer as usize],
+                buffer,
+            );
+        }
+    }
+
+    fn decorate_column(
+        &self,
+        chunk_key: IVec3,
+        seed: WorldSeed,
+        column: IVec2,
+        surface: i32,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let pos = IVec3::new(column.x, surface - chunk_key.y, column.y);
+        let world_column = (column + chunk_key.xz()).as_vec2();
+
+        for (index, decoration) in self.decorations.iter().enumerate() {
+            // every rule rolls its own dice so the features don't all spawn on the same columns.
+            let roll = noise::rand2to1(
+                world_column * 0.1,
+                Vec2::new(12.989, 78.233) + Vec2::new(31.417, 17.931) * index as f32,
+                seed,
+            )
+            .abs();
+
+            if roll >= decoration.chance {
+                continue;
+            }
+
+            let (min, max) = decoration.size;
+            let size = min + (max - min) * roll / decoration.chance;
+
+            match decoration.feature {
+                DecorationFeature::Plant => feature_voxels(pos.as_vec3(), pos.as_vec3())
+                    .for_each(|voxel| *buffer.voxel_at_mut(voxel) = decoration.material),
+                DecorationFeature::Boulder => make_rock(buffer, pos, size, decoration.material),
+                DecorationFeature::Cactus => {
+                    make_cacti(buffer, pos, size as u32, decoration.material)
+                }
+                DecorationFeature::Tree => {
+                    make_tree(buffer, pos, decoration.material, decoration.leaves)
+                }
+                DecorationFeature::PineTree => {
+                    make_pine_tree(buffer, pos, decoration.material, decoration.leaves)
+                }
+            }
+        }
+    }
+
+    fn structures(&self) -> &[StructurePlacement] {
+        &self.structures
+    }
+}
+
+/// The biome definitions loaded from the assets, registered into the terrain generator.
+#[derive(Resource, Default)]
+pub struct LoadedBiomes {
+    /// Keeps the biome definitions of the biomes folder loaded.
+    folder: Handle<LoadedFolder>,
+    /// The names of the biomes registered from each definition.
+    names: HashMap<AssetId<BiomeAsset>, String>,
+    /// Whether the biome definitions found in the folder at startup are registered.
+    loaded: bool,
+}
+
+fn load_biome_assets(asset_server: Res<AssetServer>, mut biomes: ResMut<LoadedBiomes>) {
+    biomes.folder = asset_server.load_folder(BIOMES_FOLDER);
+}
+
+/// Holds back the terrain generation until the biome definitions found at startup are registered,
+/// so the first chunks don't get generated without them and then again once they are loaded.
+pub fn biomes_loaded(biomes: Res<LoadedBiomes>) -> bool {
+    biomes.loaded
+}
+
+/// Registers the biome definitions into the terrain generator as they get loaded, modified or removed.
+/// Once the definitions found at startup are loaded, the changes regenerate the terrain of the loaded chunks,
+/// which loses the voxel edits made to them.
+fn register_biome_assets(
+    mut events: EventReader<AssetEvent<BiomeAsset>>,
+    assets: Res<Assets<BiomeAsset>>,
+    asset_server: Res<AssetServer>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut biomes: ResMut<LoadedBiomes>,
+    mut regenerate: EventWriter<RegenerateTerrain>,
+) {
+    let mut changed = false;
+
+    for event in events.read() {
+        match *event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                let Some(asset) = assets.get(id) else {
+                    continue;
+                };
+
+                // an invalid definition keeps the previous one registered.
+                let biome = match DataBiomeTerrainGenerator::new(asset, &registry) {
+                    Ok(biome) => biome,
+                    Err(err) => {
+                        error!("Invalid definition for biome {}: {}", asset.name, err);
+                        continue;
+                    }
+                };
+
+                let mut generator = TERRAIN_GENERATOR.write().unwrap();
+                let generator = Arc::make_mut(&mut generator);
+                if let Some(name) = biomes.names.insert(id, asset.name.clone()) {
+                    generator.unregister_biome_generator(&name);
+                }
+                generator
+                    .unregister_biome_generator(&asset.name)
+                    .register_biome_generator(asset.climate, Box::new(biome));
+
+                info!("Registered biome {}", asset.name);
+                changed = true;
+            }
+            AssetEvent::Removed { id } => {
+                if let Some(name) = biomes.names.remove(&id) {
+                    Arc::make_mut(&mut TERRAIN_GENERATOR.write().unwrap())
+                        .unregister_biome_generator(&name);
+                    info!("Unregistered biome {}", name);
+                    changed = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // no terrain is generated before the startup definitions are loaded, so there is nothing to regenerate.
+    if changed && biomes.loaded {
+        regenerate.send(RegenerateTerrain);
+    }
+
+    // the definitions which fail to load don't hold back the terrain generation.
+    if !biomes.loaded
+        && (matches!(
+            asset_server.recursive_dependency_load_state(&biomes.folder),
+            RecursiveDependencyLoadState::Loaded | RecursiveDependencyLoadState::Failed
+        ) || matches!(
+            asset_server.load_state(&biomes.folder),
+            LoadState::Failed(_)
+        ))
+    {
+        biomes.loaded = true;
+    }
+}
+
+/// Loads the biome definitions from the `assets/biomes` folder.
+/// The definitions get reloaded when their files change if the `file_watcher` feature of bevy is enabled.
+pub struct DataBiomesPlugin;
+
+impl Plugin for DataBiomesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BiomeAsset>()
+            .init_asset::<StructureTemplateAsset>()
+            .init_asset_loader::<BiomeAssetLoader>()
+            .init_asset_loader::<StructureTemplateAssetLoader>()
+            .init_resource::<LoadedBiomes>()
+            .add_systems(Startup, load_biome_assets)
+            .add_systems(Update, register_biome_assets);
+    }
+}
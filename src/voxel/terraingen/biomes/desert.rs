@@ -1,20 +1,28 @@
-use bevy::math::{IVec3, UVec3, Vec2, Vec3, Vec3Swizzles};
-use ilattice::prelude::{Extent, UVec3 as ILUVec3};
+use bevy::math::{IVec3, Vec2, Vec3, Vec3Swizzles};
 
 use crate::voxel::{
     material::VoxelMaterial,
     materials::{Cactus, Sand, Sandstone},
     sdf,
     storage::VoxelBuffer,
-    terraingen::noise,
-    ChunkShape, Voxel, CHUNK_LENGTH,
+    terraingen::{common::feature_voxels, noise, WorldSeed},
+    ChunkShape, Voxel,
 };
 
-use super::LayeredBiomeTerrainGenerator;
+use super::{BiomeRelief, LayeredBiomeTerrainGenerator};
 
 pub struct BasicDesertBiomeTerrainGenerator;
 
 impl LayeredBiomeTerrainGenerator for BasicDesertBiomeTerrainGenerator {
+    const NAME: &'static str = "desert";
+
+    // low and wide dunes.
+    const RELIEF: BiomeRelief = BiomeRelief {
+        base_height: 134.0,
+        amplitude: 9.0,
+        roughness: 0.8,
+    };
+
     fn fill_strata(&self, layer: u32) -> Voxel {
         match layer {
             0..=5 => Sand::into_voxel(),
@@ -25,32 +33,40 @@ impl LayeredBiomeTerrainGenerator for BasicDesertBiomeTerrainGenerator {
     fn place_decoration(
         &self,
         key: IVec3,
-        pos: UVec3,
+        seed: WorldSeed,
+        pos: IVec3,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     ) {
         let cacti_spawn_chance = noise::rand2to1(
             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
             Vec2::new(12.989, 78.233),
+            seed,
         );
 
         if cacti_spawn_chance > 0.992 {
             let size = ((cacti_spawn_chance - 0.992) * 2000.0) as u32 + 2;
-            make_cacti(buffer, pos, size);
+            make_cacti(buffer, pos, size, Cactus::into_voxel());
         }
     }
 }
 
-fn make_cacti(buffer: &mut VoxelBuffer<Voxel, ChunkShape>, pos: UVec3, size: u32) {
-    Extent::from_min_and_shape(ILUVec3::ZERO, ILUVec3::splat(CHUNK_LENGTH))
-        .iter3()
-        .map(|x| x.as_vec3())
-        .filter(|vec| {
-            sdf::sdf_v_capsule(
-                Vec3::from_array(vec.to_array()) - pos.as_vec3() - Vec3::Y,
-                size as f32,
-                1.5,
-            ) < 0.0
-        })
-        .map(|x| ILUVec3::from(x.as_uvec3().to_array()))
-        .for_each(|x| *buffer.voxel_at_mut(x) = Cactus::into_voxel());
+pub fn make_cacti(
+    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    pos: IVec3,
+    size: u32,
+    cactus: Voxel,
+) {
+    let origin = pos.as_vec3() + Vec3::Y;
+    feature_voxels(
+        origin - Vec3::splat(1.5),
+        origin + Vec3::new(1.5, size as f32 + 1.5, 1.5),
+    )
+    .filter(|voxel| {
+        sdf::sdf_v_capsule(
+            Vec3::from_array(voxel.as_vec3().to_array()) - origin,
+            size as f32,
+            1.5,
+        ) < 0.0
+    })
+    .for_each(|voxel| *buffer.voxel_at_mut(voxel) = cactus);
 }
@@ -1,20 +1,23 @@
-use std::ops::Div;
-
-use bevy::math::{IVec3, UVec3};
-use ilattice::{glam::UVec2, prelude::Extent};
+use bevy::math::{IVec2, IVec3, UVec2};
 
 use crate::voxel::{
     material::VoxelMaterial,
-    materials::{Dirt, Grass},
+    materials::{Dirt, Grass, Rock},
     storage::VoxelBuffer,
-    terraingen::noise::Heightmap,
-    ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_LENGTH_U,
+    terraingen::WorldSeed,
+    ChunkShape, Voxel, CHUNK_LENGTH,
 };
 
-use super::BiomeTerrainGenerator;
+use super::{BiomeRelief, BiomeTerrainGenerator};
 
 /// A biome terrain generator that applies a set of layers on top of the terrain.
 pub trait LayeredBiomeTerrainGenerator: BiomeTerrainGenerator {
+    /// The name of the biome, see [`BiomeTerrainGenerator::name`].
+    const NAME: &'static str;
+
+    /// The shape of the terrain in this biome, see [`BiomeTerrainGenerator::relief`].
+    const RELIEF: BiomeRelief = BiomeRelief::DEFAULT;
+
     /// The height function to use for applying the biome material layers on top of the terrain.
     fn fill_strata(&self, layer: u32) -> Voxel {
         match layer {
@@ -28,60 +31,83 @@ pub trait LayeredBiomeTerrainGenerator: BiomeTerrainGenerator {
         8
     }
 
+    /// Places the decorations growing at `pos`, the voxel above the surface of a column.
+    /// `pos` is local to the chunk being generated but may lie outside of it, as the decorations of the neighbouring
+    /// columns may reach into the chunk, so the decorations have to clip their voxels to the chunk.
     fn place_decoration(
         &self,
         _key: IVec3,
-        _pos: UVec3,
+        _seed: WorldSeed,
+        _pos: IVec3,
         _buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     ) {
     }
 }
 
 impl<T: LayeredBiomeTerrainGenerator> BiomeTerrainGenerator for T {
-    fn carve_terrain(
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn relief(&self) -> BiomeRelief {
+        Self::RELIEF
+    }
+
+    fn carve_column(
         &self,
         chunk_key: IVec3,
-        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        column: UVec2,
+        surface: i32,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     ) {
-        Extent::from_min_and_shape(UVec2::ZERO, UVec2::splat(CHUNK_LENGTH))
-            .iter2()
-            .for_each(|pos| {
-                let height = heightmap.get(pos.into());
-                // we only want to apply surface layer decoration on top of the surface chunk
-                if height.div(CHUNK_LENGTH) == (chunk_key.y as u32).div(CHUNK_LENGTH) {
-                    let local_height = height.rem_euclid(CHUNK_LENGTH);
-
-                    for h in 0..=self.num_layers() {
-                        let remaining_height = local_height.checked_sub(h);
-
-                        if let Some(uh) = remaining_height {
-                            *buffer.voxel_at_mut([pos.x, uh, pos.y].into()) = self.fill_strata(h);
-                        }
-                    }
-                }
-            });
+        carve_strata(
+            chunk_key,
+            column,
+            surface,
+            self.num_layers(),
+            |layer| self.fill_strata(layer),
+            buffer,
+        );
     }
 
-    fn decorate_terrain(
+    fn decorate_column(
         &self,
         chunk_key: IVec3,
-        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        seed: WorldSeed,
+        column: IVec2,
+        surface: i32,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     ) {
-        if chunk_key.y <= 96 {
-            return;
-        }
+        self.place_decoration(
+            chunk_key,
+            seed,
+            IVec3::new(column.x, surface - chunk_key.y, column.y),
+            buffer,
+        );
+    }
+}
+
+/// Covers the rock right under the surface of a column with the material layers of a biome,
+/// `fill_strata` giving the material of each layer from the top one.
+pub fn carve_strata(
+    chunk_key: IVec3,
+    column: UVec2,
+    surface: i32,
+    num_layers: u32,
+    fill_strata: impl Fn(u32) -> Voxel,
+    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+) {
+    // the layers lie right under the surface and may spread over the chunk below the surface chunk.
+    // only the rock gets covered, leaving the caves and the sea untouched.
+    for layer in 0..=num_layers {
+        let local_height = surface - 1 - layer as i32 - chunk_key.y;
 
-        Extent::from_min_and_shape(UVec2::ZERO, UVec2::splat(CHUNK_LENGTH))
-            .iter2()
-            .for_each(|pos| {
-                let height = heightmap.get(pos.into());
+        if (0..CHUNK_LENGTH as i32).contains(&local_height) {
+            let voxel = buffer.voxel_at_mut([column.x, local_height as u32, column.y].into());
 
-                if height.div(CHUNK_LENGTH) == (chunk_key.y as u32).div(CHUNK_LENGTH) {
-                    let local_height = height.rem_euclid(CHUNK_LENGTH);
-                    self.place_decoration(chunk_key, [pos.x, local_height, pos.y].into(), buffer);
-                }
-            });
+            if *voxel == Rock::into_voxel() {
+                *voxel = fill_strata(layer);
+            }
+        }
     }
 }
@@ -1,10 +1,12 @@
-use crate::voxel::{storage::VoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH_U};
+use crate::voxel::{storage::VoxelBuffer, ChunkShape, Voxel};
 
-use super::noise::Heightmap;
+use super::{structures::StructurePlacement, WorldSeed};
 
 mod layered;
-use bevy::math::IVec3;
+use bevy::math::{IVec2, IVec3, UVec2, Vec2};
 pub use layered::*;
+use noise::NoiseFn;
+use serde::Deserialize;
 
 mod plains;
 pub use plains::*;
@@ -15,24 +17,77 @@ pub use desert::*;
 mod snowy_plains;
 pub use snowy_plains::*;
 
+mod data;
+pub use data::*;
+
+/// The shape of the terrain in a biome, the heights being blended between the neighbouring biomes near their borders.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BiomeRelief {
+    /// The average height (in voxels) of the terrain.
+    pub base_height: f32,
+    /// How far (in voxels) the terrain rises above and sinks below its average height.
+    pub amplitude: f32,
+    /// Scales the frequency of the height noise, higher values giving closer and steeper hills.
+    pub roughness: f32,
+}
+
+impl BiomeRelief {
+    pub const DEFAULT: Self = Self {
+        base_height: 132.0,
+        amplitude: 20.0,
+        roughness: 1.0,
+    };
+
+    /// Returns the height of the terrain at a column of the world given the height noise.
+    pub fn height(&self, noise: &impl NoiseFn<f64, 2>, column: Vec2) -> f32 {
+        let sample = noise.get((column.as_dvec2() * self.roughness as f64).to_array()) as f32;
+        sample.mul_add(self.amplitude, self.base_height)
+    }
+}
+
+impl Default for BiomeRelief {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 /// A trait representing a terrain generator for a biome.
 /// A biome can be defined as a collection of features that are applied on top of the terrain.
+/// The biomes are sampled per column of the world so a chunk may span several biomes.
 pub trait BiomeTerrainGenerator: 'static + Sync + Send {
-    /// Carve the terrain using the materials for the biome.
-    fn carve_terrain(
+    /// The name of the biome, used to refer to it from the world generation settings (e.g. ore distributions).
+    fn name(&self) -> &str;
+
+    /// The shape of the terrain in this biome.
+    fn relief(&self) -> BiomeRelief;
+
+    /// Carve a column of the terrain using the materials for the biome.
+    /// `surface` is the world height of the voxel right above the topmost solid voxel of the column.
+    fn carve_column(
         &self,
         chunk_key: IVec3,
-        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        column: UVec2,
+        surface: i32,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     );
 
-    /// Decorate the terrain with this biome specific features (e.g. flowers, trees, ores etc).
-    fn decorate_terrain(
+    /// Decorate a column of the terrain with this biome specific features (e.g. flowers, trees etc).
+    /// The columns around the chunk get decorated as well since their features may reach into the chunk,
+    /// `column` is thus local to the chunk but may lie outside of it.
+    fn decorate_column(
         &self,
         chunk_key: IVec3,
-        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        seed: WorldSeed,
+        column: IVec2,
+        surface: i32,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     );
+
+    /// The structures this biome places on top of the terrain.
+    fn structures(&self) -> &[StructurePlacement] {
+        &[]
+    }
 }
 
 /// Utility trait for boxing biome generators.
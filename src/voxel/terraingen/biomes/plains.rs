@@ -1,21 +1,22 @@
 use crate::voxel::{
     material::VoxelMaterial,
-    materials::{Dirt, Grass, Leaves, Rock, Wood},
+    materials::{Dirt, Grass, Leaves, Rock, TallGrass, Wood},
     storage::VoxelBuffer,
     terraingen::{
-        common::{make_rock, make_tree},
-        noise,
+        common::{feature_voxels, make_rock, make_tree},
+        noise, WorldSeed,
     },
     ChunkShape, Voxel,
 };
-use bevy::math::{IVec3, UVec3, Vec2, Vec3Swizzles};
-use ilattice::prelude::UVec3 as ILUVec3;
+use bevy::math::{IVec3, Vec2, Vec3Swizzles};
 
 use super::LayeredBiomeTerrainGenerator;
 
 pub struct BasicPlainsBiomeTerrainGenerator;
 
 impl LayeredBiomeTerrainGenerator for BasicPlainsBiomeTerrainGenerator {
+    const NAME: &'static str = "plains";
+
     fn fill_strata(&self, layer: u32) -> Voxel {
         match layer {
             0..=1 => Grass::into_voxel(),
@@ -26,41 +27,42 @@ impl LayeredBiomeTerrainGenerator for BasicPlainsBiomeTerrainGenerator {
     fn place_decoration(
         &self,
         key: IVec3,
-        pos: UVec3,
+        seed: WorldSeed,
+        pos: IVec3,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     ) {
         let spawn_chance = noise::rand2to1(
             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
             Vec2::new(12.989, 78.233),
+            seed,
         );
 
-        let grass_blade_height = ((noise::rand2to1(
+        let grass_spawn_chance = ((noise::rand2to1(
             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
             Vec2::new(42.478_2, 8_472.243),
+            seed,
         ) * 100.) as u32)
             .rem_euclid(4);
 
-        if grass_blade_height > 1 && pos.y <= 29 {
-            for y in 0..grass_blade_height {
-                let position = ILUVec3::from_array(pos.to_array()) + ILUVec3::new(0, y, 0);
-                *buffer.voxel_at_mut(position) = Grass::into_voxel();
-            }
+        if grass_spawn_chance > 1 {
+            feature_voxels(pos.as_vec3(), pos.as_vec3())
+                .for_each(|voxel| *buffer.voxel_at_mut(voxel) = TallGrass::into_voxel());
         }
 
         // Let's put some rock boulders in the plains to populate a lil bit
         let rock_spawn_chance = noise::rand2to1(
             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
             Vec2::new(72_845.48, 8_472.243),
+            seed,
         );
 
         if rock_spawn_chance > 0.995 {
             let rock_size = (1.0f32 - rock_spawn_chance) * 1000.0;
-            make_rock::<Rock>(buffer, ILUVec3::from(pos.to_array()), rock_size);
+            make_rock(buffer, pos, rock_size, Rock::into_voxel());
         }
 
-        if spawn_chance > 0.981 && pos.y <= 13 {
-            // this is a stupid hack but a real fix would be to allow terrain decoration to work vertically
-            make_tree::<Wood, Leaves>(buffer, ILUVec3::from(pos.to_array()));
+        if spawn_chance > 0.981 {
+            make_tree(buffer, pos, Wood::into_voxel(), Leaves::into_voxel());
         }
     }
 }
@@ -2,17 +2,25 @@ use crate::voxel::{
     material::VoxelMaterial,
     materials::{Dirt, Grass, PineLeaves, PineWood, Snow},
     storage::VoxelBuffer,
-    terraingen::{common::make_pine_tree, noise},
+    terraingen::{common::make_pine_tree, noise, WorldSeed},
     ChunkShape, Voxel,
 };
-use bevy::math::{IVec3, UVec3, Vec2, Vec3Swizzles};
-use ilattice::prelude::UVec3 as ILUVec3;
+use bevy::math::{IVec3, Vec2, Vec3Swizzles};
 
-use super::LayeredBiomeTerrainGenerator;
+use super::{BiomeRelief, LayeredBiomeTerrainGenerator};
 
 pub struct BasicSnowyPlainsBiomeTerrainGenerator;
 
 impl LayeredBiomeTerrainGenerator for BasicSnowyPlainsBiomeTerrainGenerator {
+    const NAME: &'static str = "snowy_plains";
+
+    // higher and hillier than the plains.
+    const RELIEF: BiomeRelief = BiomeRelief {
+        base_height: 144.0,
+        amplitude: 26.0,
+        roughness: 1.3,
+    };
+
     fn fill_strata(&self, layer: u32) -> Voxel {
         match layer {
             0 => Snow::into_voxel(),
@@ -24,17 +32,23 @@ impl LayeredBiomeTerrainGenerator for BasicSnowyPlainsBiomeTerrainGenerator {
     fn place_decoration(
         &self,
         key: IVec3,
-        pos: UVec3,
+        seed: WorldSeed,
+        pos: IVec3,
         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     ) {
         let spawn_chance = noise::rand2to1(
             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
             Vec2::new(12.989, 78.233),
+            seed,
         );
 
-        if spawn_chance > 0.981 && pos.y <= 13 {
-            // this is a stupid hack but a real fix would be to allow terrain decoration to work vertically
-            make_pine_tree::<PineWood, PineLeaves>(buffer, ILUVec3::from(pos.to_array()));
+        if spawn_chance > 0.981 {
+            make_pine_tree(
+                buffer,
+                pos,
+                PineWood::into_voxel(),
+                PineLeaves::into_voxel(),
+            );
         }
     }
 }
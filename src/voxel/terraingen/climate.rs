@@ -0,0 +1,109 @@
+use bevy::math::{IVec2, Vec3};
+use noise::{Fbm, MultiFractal, NoiseFn, SuperSimplex};
+use serde::Deserialize;
+
+use super::WorldSeed;
+
+/// How much colder the climate gets with altitude, so the mountains get snowy.
+const ALTITUDE_COOLING: f32 = 0.35;
+
+/// The climate of a column of the world, each parameter roughly ranging from -1 to 1.
+/// Biomes register the climate they thrive in, and the columns get the biomes with the closest climates.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Climate {
+    /// How hot the column is, cold being negative.
+    pub temperature: f32,
+    /// How wet the column is, dry being negative.
+    pub humidity: f32,
+    /// How high the terrain of the column rises above its biome relief, low being negative.
+    pub altitude: f32,
+}
+
+impl Climate {
+    pub const fn new(temperature: f32, humidity: f32, altitude: f32) -> Self {
+        Self {
+            temperature,
+            humidity,
+            altitude,
+        }
+    }
+
+    /// The distance between two climates in the climate space.
+    pub fn distance(&self, other: &Self) -> f32 {
+        Vec3::new(self.temperature, self.humidity, self.altitude).distance(Vec3::new(
+            other.temperature,
+            other.humidity,
+            other.altitude,
+        ))
+    }
+}
+
+/// A region of the climate space a biome thrives in, each parameter ranging between a minimum and a maximum.
+/// The columns whose climate lies in the range are all equally close to the biome.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct ClimateRange {
+    pub temperature: (f32, f32),
+    pub humidity: (f32, f32),
+    pub altitude: (f32, f32),
+}
+
+impl ClimateRange {
+    /// The distance between a climate and the closest climate of the range in the climate space.
+    pub fn distance(&self, climate: &Climate) -> f32 {
+        let clamp = |(min, max): (f32, f32), x: f32| x.max(min).min(max);
+
+        climate.distance(&Climate::new(
+            clamp(self.temperature, climate.temperature),
+            clamp(self.humidity, climate.humidity),
+            clamp(self.altitude, climate.altitude),
+        ))
+    }
+}
+
+impl From<Climate> for ClimateRange {
+    fn from(climate: Climate) -> Self {
+        Self {
+            temperature: (climate.temperature, climate.temperature),
+            humidity: (climate.humidity, climate.humidity),
+            altitude: (climate.altitude, climate.altitude),
+        }
+    }
+}
+
+/// The noise maps giving the climate of the columns of the world.
+/// The maps vary slowly over the world, so neighbouring biomes have close climates.
+pub struct ClimateMaps {
+    temperature: Fbm<SuperSimplex>,
+    humidity: Fbm<SuperSimplex>,
+    altitude: Fbm<SuperSimplex>,
+}
+
+impl ClimateMaps {
+    pub fn new(seed: WorldSeed) -> Self {
+        let map = |salt: u64, frequency: f64| {
+            Fbm::<SuperSimplex>::new(seed.derive(salt).noise_seed())
+                .set_octaves(3)
+                .set_frequency(frequency)
+                .set_persistence(0.5)
+                .set_lacunarity(2.0)
+        };
+
+        Self {
+            temperature: map(10, 0.0008),
+            humidity: map(11, 0.0011),
+            altitude: map(12, 0.0006),
+        }
+    }
+
+    /// Returns the climate of a column of the world.
+    pub fn climate_at(&self, column: IVec2) -> Climate {
+        let point = column.as_dvec2().to_array();
+        let altitude = self.altitude.get(point) as f32;
+
+        Climate {
+            temperature: self.temperature.get(point) as f32 - altitude.max(0.0) * ALTITUDE_COOLING,
+            humidity: self.humidity.get(point) as f32,
+            altitude,
+        }
+    }
+}
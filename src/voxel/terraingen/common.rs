@@ -1,5 +1,5 @@
 use bevy::math::{IVec3, Vec3};
-use ilattice::{glam::UVec2, glam::UVec3, prelude::Extent};
+use ilattice::{glam::UVec3, prelude::Extent};
 
 use crate::voxel::{
     material::VoxelMaterial,
@@ -9,7 +9,10 @@ use crate::voxel::{
     ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_LENGTH_U,
 };
 
-use super::noise::Heightmap;
+use super::{
+    density::{TerrainColumn, TerrainDensity},
+    noise::Heightmap,
+};
 
 /// Generate the world bottom border for a chunk.
 pub fn terrain_generate_world_bottom_border(buffer: &mut VoxelBuffer<Voxel, ChunkShape>) {
@@ -19,121 +22,118 @@ pub fn terrain_generate_world_bottom_border(buffer: &mut VoxelBuffer<Voxel, Chun
     );
 }
 
-/// Carve the general terrain shape for a chunk.
-pub fn terrain_carve_heightmap(
+/// Carve the general terrain shape for a chunk from the density field, drowning the terrain under the water levels.
+pub fn terrain_carve_density(
     buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
     key: IVec3,
     heighmap: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+    water_levels: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+    density: &TerrainDensity,
 ) {
-    // drown the terrain under sea level.
-    if key.y <= 96 {
-        buffer.fill_extent(
-            Extent::from_min_and_shape(UVec3::ZERO, UVec3::splat(CHUNK_LENGTH)),
-            Water::into_voxel(),
-        );
-    }
-
-    // carve the terrain.
-    Extent::from_min_and_shape(UVec2::ZERO, UVec2::new(CHUNK_LENGTH, CHUNK_LENGTH))
-        .iter2()
+    Extent::from_min_and_shape(UVec3::ZERO, UVec3::splat(CHUNK_LENGTH))
+        .iter3()
         .for_each(|pos| {
-            let local_height = heighmap
-                .get(pos.into())
-                .checked_sub(key.y as u32)
-                .unwrap_or_default()
-                .min(CHUNK_LENGTH);
+            let column = TerrainColumn {
+                height: heighmap.get([pos.x, pos.z]) as f32,
+                water_level: water_levels.get([pos.x, pos.z]) as f32,
+            };
+            let world_pos = key + IVec3::from_array(pos.as_ivec3().to_array());
 
-            for h in 0..local_height {
-                *buffer.voxel_at_mut([pos.x, h, pos.y].into()) = Rock::into_voxel();
+            if density.is_solid(world_pos, column) {
+                *buffer.voxel_at_mut(pos) = Rock::into_voxel();
+            } else if density.is_drowned(world_pos, column) {
+                *buffer.voxel_at_mut(pos) = Water::into_voxel();
             }
         });
 }
 
-pub fn make_pine_tree<T: VoxelMaterial, L: VoxelMaterial>(
-    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-    origin: UVec3,
-) {
-    let origin = Vec3::from(origin.as_vec3().to_array());
-    Extent::from_min_and_shape(UVec3::ZERO, UVec3::splat(CHUNK_LENGTH)) //may want to calculate an extent encompassing the tree instead of iterating over the complete 32^3 volume
-        .iter3()
-        .map(|x| Vec3::from_array(x.as_vec3().to_array()))
-        .map(|position| {
-            let trunk_distance =
-                sdf::sdf_capped_cylinder(position - (origin + 2.0 * Vec3::Y), 1.5, 8.0) < 0.;
-            let leaves_distance =
-                sdf::sdf_vcone(position - (origin + 6.0 * Vec3::Y), 7.0, 17.0) < 0.;
-            (trunk_distance, leaves_distance, position)
-        })
-        .map(|(trunk_distance, leaves_distance, position)| {
-            (
-                trunk_distance,
-                leaves_distance,
-                UVec3::from(position.as_uvec3().to_array()),
+/// Returns the voxels of a chunk within the bounds of a feature, the bounds being local to the chunk.
+/// The bounds may reach outside of the chunk so the features straddling chunk borders get clipped to the chunk,
+/// the neighbouring chunks generating the rest of the feature.
+pub fn feature_voxels(min: Vec3, max: Vec3) -> impl Iterator<Item = UVec3> {
+    let min = min.floor().max(Vec3::ZERO);
+    let max = max.ceil().min(Vec3::splat(CHUNK_LENGTH as f32 - 1.0));
+
+    // nothing to iterate over when the feature doesn't reach into the chunk.
+    min.cmple(max)
+        .all()
+        .then(|| {
+            Extent::from_min_and_max(
+                UVec3::from(min.as_uvec3().to_array()),
+                UVec3::from(max.as_uvec3().to_array()),
             )
+            .iter3()
         })
-        .for_each(|(trunk_distance, leaves_distance, position)| {
-            if trunk_distance {
-                *buffer.voxel_at_mut(position) = T::into_voxel();
-            }
+        .into_iter()
+        .flatten()
+}
 
-            if leaves_distance {
-                *buffer.voxel_at_mut(position) = L::into_voxel();
-            }
-        });
+pub fn make_pine_tree(
+    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    origin: IVec3,
+    trunk: Voxel,
+    leaves: Voxel,
+) {
+    let origin = origin.as_vec3();
+    feature_voxels(
+        origin - Vec3::new(7.0, 6.0, 7.0),
+        origin + Vec3::new(7.0, 23.0, 7.0),
+    )
+    .for_each(|voxel| {
+        let position = Vec3::from_array(voxel.as_vec3().to_array());
+        let trunk_distance =
+            sdf::sdf_capped_cylinder(position - (origin + 2.0 * Vec3::Y), 1.5, 8.0) < 0.;
+        let leaves_distance = sdf::sdf_vcone(position - (origin + 6.0 * Vec3::Y), 7.0, 17.0) < 0.;
+
+        if trunk_distance {
+            *buffer.voxel_at_mut(voxel) = trunk;
+        }
+
+        if leaves_distance {
+            *buffer.voxel_at_mut(voxel) = leaves;
+        }
+    });
 }
 
 /// Make a tree using SDF functions
-pub fn make_tree<T: VoxelMaterial, L: VoxelMaterial>(
+pub fn make_tree(
     buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-    origin: UVec3,
+    origin: IVec3,
+    trunk: Voxel,
+    leaves: Voxel,
 ) {
-    let origin = Vec3::from(origin.as_vec3().to_array());
-    Extent::from_min_and_shape(UVec3::ZERO, UVec3::splat(CHUNK_LENGTH)) //may want to calculate an extent encompassing the tree instead of iterating over the complete 32^3 volume
-        .iter3()
-        .map(|x| Vec3::from_array(x.as_vec3().to_array()))
-        .map(|position| {
-            let trunk_distance =
-                sdf::sdf_capped_cylinder(position - (origin + 2.0 * Vec3::Y), 1.5, 8.0) < 0.;
-            let leaves_distance = sdf::sdf_sphere(position - (origin + 14.0 * Vec3::Y), 6.0) < 0.;
-            (trunk_distance, leaves_distance, position)
-        })
-        .map(|(trunk_distance, leaves_distance, position)| {
-            (
-                trunk_distance,
-                leaves_distance,
-                UVec3::from(position.as_uvec3().to_array()),
-            )
-        })
-        .for_each(|(trunk_distance, leaves_distance, position)| {
-            if trunk_distance {
-                *buffer.voxel_at_mut(position) = T::into_voxel();
-            }
+    let origin = origin.as_vec3();
+    feature_voxels(
+        origin - Vec3::new(6.0, 6.0, 6.0),
+        origin + Vec3::new(6.0, 20.0, 6.0),
+    )
+    .for_each(|voxel| {
+        let position = Vec3::from_array(voxel.as_vec3().to_array());
+        let trunk_distance =
+            sdf::sdf_capped_cylinder(position - (origin + 2.0 * Vec3::Y), 1.5, 8.0) < 0.;
+        let leaves_distance = sdf::sdf_sphere(position - (origin + 14.0 * Vec3::Y), 6.0) < 0.;
 
-            if leaves_distance {
-                *buffer.voxel_at_mut(position) = L::into_voxel();
-            }
-        });
+        if trunk_distance {
+            *buffer.voxel_at_mut(voxel) = trunk;
+        }
+
+        if leaves_distance {
+            *buffer.voxel_at_mut(voxel) = leaves;
+        }
+    });
 }
 
-pub fn make_rock<V: VoxelMaterial>(
+pub fn make_rock(
     buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-    origin: UVec3,
+    origin: IVec3,
     size: f32,
+    rock: Voxel,
 ) {
-    let origin = Vec3::from(origin.as_vec3().to_array());
-    Extent::from_min_and_shape(UVec3::ZERO, UVec3::splat(CHUNK_LENGTH)) //may want to calculate an extent encompassing the tree instead of iterating over the complete 32^3 volume
-        .iter3()
-        .map(|x| Vec3::from_array(x.as_vec3().to_array()))
-        .map(|position| {
-            let trunk_distance = sdf::sdf_sphere(position - origin, size) < 0.;
-            (trunk_distance, position)
-        })
-        .map(|(rock_distance, position)| {
-            (rock_distance, UVec3::from(position.as_uvec3().to_array()))
-        })
-        .for_each(|(rock, position)| {
-            if rock {
-                *buffer.voxel_at_mut(position) = V::into_voxel();
-            }
-        });
+    let origin = origin.as_vec3();
+    feature_voxels(origin - size, origin + size).for_each(|voxel| {
+        let position = Vec3::from_array(voxel.as_vec3().to_array());
+        if sdf::sdf_sphere(position - origin, size) < 0. {
+            *buffer.voxel_at_mut(voxel) = rock;
+        }
+    });
 }
@@ -0,0 +1,121 @@
+use bevy::math::{DVec3, IVec2, IVec3};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, SuperSimplex};
+
+use super::WorldSeed;
+
+/// The height of the sea surface (in voxels), the terrain below it gets drowned.
+pub const SEA_LEVEL: i32 = 128;
+
+/// How far from the heightmap surface (in voxels) the overhangs can grow or dig into the terrain.
+const OVERHANG_DEPTH: f32 = 12.0;
+const OVERHANG_FREQUENCY: f64 = 0.035;
+
+const CAVE_FREQUENCY: f64 = 0.018;
+/// The radius of the tunnels in noise units, the tunnels get carved where both cave noises are close to zero.
+const CAVE_RADIUS: f64 = 0.085;
+/// The depth of rock (in voxels) kept between the caves and the water floors so the caves never get flooded.
+const CAVE_SEAL_DEPTH: f32 = 6.0;
+/// The lowest voxels of the world are never carved so the caves don't breach the bedrock.
+const CAVE_FLOOR: i32 = 4;
+
+/// The shape of a column of the terrain, the density field perturbing it into caves and overhangs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainColumn {
+    /// The height (in voxels) of the terrain surface.
+    pub height: f32,
+    /// The height (in voxels) of the water surface of the column, the sea level unless a river or a lake flows there.
+    pub water_level: f32,
+}
+
+/// The 3D density field shaping the terrain: the heightmap surface perturbed by 3D noise into cliffs and overhangs,
+/// minus the tunnels of the cave networks.
+pub struct TerrainDensity {
+    overhangs: Fbm<SuperSimplex>,
+    tunnels: [Perlin; 2],
+    /// Whether the heightmap surface gets perturbed, the terrain being solid right up to the surface otherwise.
+    perturbed: bool,
+}
+
+impl TerrainDensity {
+    pub fn new(seed: WorldSeed) -> Self {
+        Self {
+            overhangs: Fbm::<SuperSimplex>::new(seed.derive(1).noise_seed())
+                .set_octaves(3)
+                .set_frequency(OVERHANG_FREQUENCY)
+                .set_persistence(0.5)
+                .set_lacunarity(2.0),
+            tunnels: [
+                Perlin::new(seed.derive(2).noise_seed()),
+                Perlin::new(seed.derive(3).noise_seed()),
+            ],
+            perturbed: true,
+        }
+    }
+
+    /// Turns off the caves and overhangs, e.g. for terrains which must follow their heightmap exactly.
+    pub fn unperturbed(self) -> Self {
+        Self {
+            perturbed: false,
+            ..self
+        }
+    }
+
+    /// Returns whether the voxel at the specified world position is solid, given the shape of its column.
+    pub fn is_solid(&self, pos: IVec3, column: TerrainColumn) -> bool {
+        if !self.perturbed {
+            return (pos.y as f32) < column.height;
+        }
+
+        self.surface_density(pos, column) > 0.0 && !self.is_cave(pos, column)
+    }
+
+    /// Returns whether the voxel at the specified world position is drowned when it isn't solid.
+    /// Only the voxels above the terrain surface get flooded, the caves below it are kept dry.
+    pub fn is_drowned(&self, pos: IVec3, column: TerrainColumn) -> bool {
+        pos.y < column.water_level.round() as i32 && pos.y as f32 >= column.height
+    }
+
+    /// Returns the world height of the voxel above the topmost solid voxel of a column, as seen from the sky.
+    pub fn surface_height(&self, pos: IVec2, column: TerrainColumn) -> i32 {
+        // the overhangs can't grow above this height.
+        let top = (column.height + OVERHANG_DEPTH).ceil() as i32;
+
+        (0..=top)
+            .rev()
+            .find(|y| self.is_solid(IVec3::new(pos.x, *y, pos.y), column))
+            .map_or(0, |y| y + 1)
+    }
+
+    /// The density of the terrain without the caves, positive inside the terrain.
+    fn surface_density(&self, pos: IVec3, column: TerrainColumn) -> f32 {
+        let depth = column.height - pos.y as f32;
+
+        // the overhangs fade out under the water so the sea floor and the river banks stay closed.
+        let strength = ((pos.y as f32 - column.water_level) / 8.0).clamp(0.0, 1.0);
+        if strength == 0.0 || depth.abs() > OVERHANG_DEPTH {
+            return depth;
+        }
+
+        let noise = self.overhangs.get(pos.as_dvec3().to_array()) as f32;
+        depth + noise.clamp(-1.0, 1.0) * OVERHANG_DEPTH * strength
+    }
+
+    fn is_cave(&self, pos: IVec3, column: TerrainColumn) -> bool {
+        if pos.y < CAVE_FLOOR {
+            return false;
+        }
+
+        // the caves keep away from the sea and river floors, they would otherwise be flooded.
+        if column.height < column.water_level + CAVE_SEAL_DEPTH
+            && pos.y as f32 > column.height - CAVE_SEAL_DEPTH
+        {
+            return false;
+        }
+
+        // the tunnels are squashed vertically so they mostly run horizontally.
+        let point = (pos.as_dvec3() * CAVE_FREQUENCY * DVec3::new(1.0, 1.6, 1.0)).to_array();
+        self.tunnels
+            .iter()
+            .all(|tunnel| tunnel.get(point).abs() < CAVE_RADIUS)
+    }
+}
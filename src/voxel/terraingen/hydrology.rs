@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bevy::math::{DVec2, IVec2, Vec2};
+use noise::{NoiseFn, Perlin};
+
+use super::{
+    density::{TerrainColumn, SEA_LEVEL},
+    WorldSeed,
+};
+
+/// The size (in voxels) of the cells of the world holding at most one river spring each.
+const RIVER_CELL: i32 = 256;
+const RIVER_CHANCE: f32 = 0.45;
+/// How high (in voxels) above the sea the springs of the rivers lie at least.
+const RIVER_SPRING_HEIGHT: f32 = 12.0;
+/// The length (in voxels) of the segments of the river courses.
+const RIVER_STEP: f32 = 8.0;
+/// The maximum number of segments of a river course.
+const RIVER_STEPS: usize = 96;
+/// How much the rivers keep their direction rather than turning downhill, which carries them across the flats and
+/// through the ridges blocking their way.
+const RIVER_MOMENTUM: f32 = 2.0;
+/// The half width (in voxels) of the river channels.
+const RIVER_WIDTH: f32 = 5.0;
+/// The length (in voxels) over which the rivers widen from their spring.
+const RIVER_SPRING_TAPER: f32 = 96.0;
+const RIVER_DEPTH: f32 = 4.0;
+/// How much the river levels sink under the broad shape of the terrain, for the rivers to run in valleys.
+const RIVER_SINK: f32 = 3.0;
+const MEANDER_FREQUENCY: f64 = 0.006;
+/// How far (in voxels) the rivers meander away from their course.
+const MEANDER_AMPLITUDE: f64 = 12.0;
+
+/// The size (in voxels) of the cells of the world holding at most one lake each.
+const LAKE_CELL: i32 = 384;
+const LAKE_CHANCE: f32 = 0.4;
+const LAKE_DEPTH: f32 = 7.0;
+/// How much the lake levels sink under the broad shape of the terrain at their center.
+const LAKE_SINK: f32 = 2.0;
+
+/// The width (in voxels) of the banks rising from the water to the surrounding terrain.
+const BANK_WIDTH: f32 = 8.0;
+/// The width (in voxels) past the banks over which the raised terrain around the water blends back into the terrain.
+const LEVEE_WIDTH: f32 = 8.0;
+
+/// How far (in voxels) a column may lie from the river course whose channel or banks it is part of.
+const RIVER_SHORE_REACH: f32 = RIVER_WIDTH + BANK_WIDTH + LEVEE_WIDTH + MEANDER_AMPLITUDE as f32;
+
+/// A point of the course of a river.
+#[derive(Clone, Copy, Debug)]
+struct RiverPoint {
+    pos: Vec2,
+    /// The water level of the river, which never rises along the course.
+    level: f32,
+    /// The length (in voxels) of the course from the spring.
+    length: f32,
+}
+
+/// A river traced downhill from its spring, as the polyline of its course.
+#[derive(Debug)]
+pub struct River {
+    course: Vec<RiverPoint>,
+    /// The bounds of the columns shaped by the river.
+    min: Vec2,
+    max: Vec2,
+}
+
+impl River {
+    /// Returns whether the river shapes some of the columns of an area.
+    pub fn reaches(&self, min: IVec2, max: IVec2) -> bool {
+        self.min.cmple(max.as_vec2()).all() && self.max.cmpge(min.as_vec2()).all()
+    }
+
+    /// Returns the distance (in voxels) from a position to the river shore, negative in the river, along with the
+    /// water level of the river there.
+    fn distance(&self, pos: Vec2) -> Option<(f32, f32)> {
+        if pos.cmplt(self.min).any() || pos.cmpgt(self.max).any() {
+            return None;
+        }
+
+        let (distance, point) = self
+            .course
+            .windows(2)
+            .map(|segment| {
+                let [a, b] = [segment[0], segment[1]];
+                let t = ((pos - a.pos).dot(b.pos - a.pos) / a.pos.distance_squared(b.pos))
+                    .clamp(0.0, 1.0);
+                let closest = a.pos.lerp(b.pos, t);
+                let point = RiverPoint {
+                    pos: closest,
+                    level: lerp(a.level, b.level, t),
+                    length: lerp(a.length, b.length, t),
+                };
+                (pos.distance(closest), point)
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))?;
+
+        let width = RIVER_WIDTH * smoothstep(0.0, RIVER_SPRING_TAPER, point.length);
+        Some((distance - width, point.level))
+    }
+}
+
+/// The maximum number of river cells kept by a [`RiverCache`], many times the cells around the loaded chunks.
+const RIVER_CACHE_CAPACITY: usize = 1024;
+
+/// The rivers traced from the river cells of the world, tracing a river being much costlier than carving it.
+/// The least recently used cells get evicted once the cache is full, so it doesn't grow as the world gets explored.
+/// The cache has to be cleared whenever the broad shape of the terrain changes.
+#[derive(Default, Clone)]
+pub struct RiverCache(Arc<Mutex<RiverCells>>);
+
+impl RiverCache {
+    /// Starts over with an empty cache, the copies of the cache keep the rivers they traced.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[derive(Default)]
+struct RiverCells {
+    /// The seed of the world the rivers were traced for.
+    seed: Option<WorldSeed>,
+    /// The river of each traced cell, along with the last lookup of the cell.
+    rivers: HashMap<IVec2, (Option<Arc<River>>, u64)>,
+    lookups: u64,
+}
+
+impl RiverCells {
+    /// Empties the cache if its rivers were traced for another seed.
+    fn use_seed(&mut self, seed: WorldSeed) {
+        if self.seed != Some(seed) {
+            self.rivers.clear();
+            self.seed = Some(seed);
+        }
+    }
+
+    /// Returns the river of a cell, or `None` if the cell wasn't traced yet.
+    fn get(&mut self, cell: IVec2) -> Option<Option<Arc<River>>> {
+        self.lookups += 1;
+        let lookups = self.lookups;
+        self.rivers.get_mut(&cell).map(|(river, last_lookup)| {
+            *last_lookup = lookups;
+            river.clone()
+        })
+    }
+
+    fn insert(&mut self, cell: IVec2, river: Option<Arc<River>>) {
+        self.lookups += 1;
+        self.rivers.insert(cell, (river, self.lookups));
+
+        if self.rivers.len() > RIVER_CACHE_CAPACITY {
+            // evicting a quarter of the cells at once spreads the cost of finding them over many insertions.
+            let mut last_lookups: Vec<u64> = self.rivers.values().map(|(_, last)| *last).collect();
+            let oldest = *last_lookups.select_nth_unstable(RIVER_CACHE_CAPACITY / 4).1;
+            self.rivers
+                .retain(|_, (_, last_lookup)| *last_lookup > oldest);
+        }
+    }
+}
+
+/// The rivers and lakes of the world, carving their beds and valleys into the terrain.
+///
+/// The rivers spring in the highlands and get traced downhill along the broad shape of the terrain (the terrain
+/// height without its small hills) until they reach the sea. Their water level never rises along their course, the
+/// rivers cutting gorges through the ridges in their way rather than climbing over them.
+/// The lakes are scattered in the basins of the world, each lake having its own water level.
+pub struct Hydrology {
+    seed: WorldSeed,
+    meanders: [Perlin; 2],
+    shores: Perlin,
+}
+
+impl Hydrology {
+    pub fn new(seed: WorldSeed) -> Self {
+        Self {
+            seed,
+            meanders: [
+                Perlin::new(seed.derive(22).noise_seed()),
+                Perlin::new(seed.derive(23).noise_seed()),
+            ],
+            shores: Perlin::new(seed.derive(24).noise_seed()),
+        }
+    }
+
+    /// Returns the rivers shaping the columns of an area, `valley_level` returning the broad shape of the terrain
+    /// at a column. The rivers are traced once and then looked up from the cache.
+    pub fn rivers_around(
+        &self,
+        min: IVec2,
+        max: IVec2,
+        cache: &RiverCache,
+        valley_level: impl Fn(IVec2) -> f32,
+    ) -> Vec<Arc<River>> {
+        // the farthest a river shapes the terrain from its spring.
+        let reach = (RIVER_STEP * RIVER_STEPS as f32 + RIVER_SHORE_REACH).ceil() as i32;
+        let min_cell = (min - reach).div_euclid(IVec2::splat(RIVER_CELL));
+        let max_cell = (max + reach).div_euclid(IVec2::splat(RIVER_CELL));
+
+        let mut rivers = Vec::new();
+        let mut untraced = Vec::new();
+        {
+            let mut cells = cache.0.lock().unwrap();
+            cells.use_seed(self.seed);
+            for x in min_cell.x..=max_cell.x {
+                for z in min_cell.y..=max_cell.y {
+                    let cell = IVec2::new(x, z);
+                    match cells.get(cell) {
+                        Some(river) => rivers.extend(river),
+                        None => untraced.push(cell),
+                    }
+                }
+            }
+        }
+
+        // the rivers are traced without holding the lock, so the other generation tasks can keep using the cache.
+        if !untraced.is_empty() {
+            let traced: Vec<_> = untraced
+                .into_iter()
+                .map(|cell| (cell, self.trace_river(cell, &valley_level).map(Arc::new)))
+                .collect();
+
+            let mut cells = cache.0.lock().unwrap();
+            cells.use_seed(self.seed);
+            for (cell, river) in traced {
+                cells.insert(cell, river.clone());
+                rivers.extend(river);
+            }
+        }
+
+        rivers.retain(|river| river.reaches(min, max));
+        rivers
+    }
+
+    /// Traces the river springing in a cell of the world, if any.
+    /// The river flows down the slope of the broad shape of the terrain until it reaches the sea, its water level
+    /// following the terrain down but never rising.
+    fn trace_river(&self, cell: IVec2, valley_level: impl Fn(IVec2) -> f32) -> Option<River> {
+        let mut seed = self
+            .seed
+            .derive(26)
+            .derive(cell.x as u32 as u64)
+            .derive(cell.y as u32 as u64);
+        let mut next = || {
+            seed = seed.derive(1);
+            (seed.0 >> 40) as f32 / (1u64 << 24) as f32
+        };
+
+        let spawns = next() < RIVER_CHANCE;
+        let spring = (cell * RIVER_CELL).as_vec2()
+            + (Vec2::new(next(), next()) * 0.5 + 0.25) * RIVER_CELL as f32;
+        let valley_level = |pos: Vec2| valley_level(pos.round().as_ivec2());
+
+        let mut level = valley_level(spring) - RIVER_SINK;
+        if !spawns || level < SEA_LEVEL as f32 + RIVER_SPRING_HEIGHT {
+            return None;
+        }
+
+        let mut course = vec![RiverPoint {
+            pos: spring,
+            level,
+            length: 0.0,
+        }];
+        let mut pos = spring;
+        let mut direction = Vec2::ZERO;
+
+        for step in 1..=RIVER_STEPS {
+            let slope = Vec2::new(
+                valley_level(pos + Vec2::X * RIVER_STEP) - valley_level(pos - Vec2::X * RIVER_STEP),
+                valley_level(pos + Vec2::Y * RIVER_STEP) - valley_level(pos - Vec2::Y * RIVER_STEP),
+            );
+            let downhill = -slope.normalize_or_zero();
+            let Some(next_direction) = (direction * RIVER_MOMENTUM + downhill).try_normalize()
+            else {
+                break;
+            };
+
+            direction = next_direction;
+            pos += direction * RIVER_STEP;
+            let ground = valley_level(pos) - RIVER_SINK;
+            level = level.min(ground).max(SEA_LEVEL as f32);
+            course.push(RiverPoint {
+                pos,
+                level,
+                length: step as f32 * RIVER_STEP,
+            });
+
+            if ground <= SEA_LEVEL as f32 {
+                break;
+            }
+        }
+
+        if course.len() < 2 {
+            return None;
+        }
+
+        let (min, max) = course.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), point| (min.min(point.pos), max.max(point.pos)),
+        );
+
+        Some(River {
+            course,
+            min: min - RIVER_SHORE_REACH,
+            max: max + RIVER_SHORE_REACH,
+        })
+    }
+
+    /// Carves the rivers and lakes into a column of the world given its terrain height, `rivers` being the rivers
+    /// around the column and `valley_level` returning the broad shape of the terrain at other columns.
+    pub fn carve(
+        &self,
+        column: IVec2,
+        height: f32,
+        rivers: &[Arc<River>],
+        valley_level: impl Fn(IVec2) -> f32,
+    ) -> TerrainColumn {
+        let mut carved = TerrainColumn {
+            height,
+            water_level: SEA_LEVEL as f32,
+        };
+
+        let pos = self.meandered_pos(column);
+        let mut channels: Vec<(f32, f32)> = rivers
+            .iter()
+            .filter_map(|river| river.distance(pos))
+            .filter(|(distance, _)| *distance < BANK_WIDTH + LEVEE_WIDTH)
+            .collect();
+        // the lower rivers carve last so the rivers flowing into them end at their level.
+        channels.sort_unstable_by(|(_, a), (_, b)| b.total_cmp(a));
+        for (distance, level) in channels {
+            carved = carve_shore(carved, level, distance, RIVER_DEPTH);
+        }
+
+        // the lakes carve after the rivers so the rivers flowing into a lake end at its shore.
+        for (center, radius) in self.lakes_around(column) {
+            let distance = self.lake_distance(column, center, radius);
+            if distance >= BANK_WIDTH + LEVEE_WIDTH {
+                continue;
+            }
+
+            let level = valley_level(center.as_ivec2()) - LAKE_SINK;
+            if level > SEA_LEVEL as f32 + LAKE_SINK {
+                carved = carve_shore(carved, level, distance, LAKE_DEPTH);
+            }
+        }
+
+        carved
+    }
+
+    /// Returns the position of a column wobbled around so the rivers meander along their course.
+    fn meandered_pos(&self, column: IVec2) -> Vec2 {
+        let p = column.as_dvec2();
+        let warp = p * MEANDER_FREQUENCY;
+        let meander = DVec2::new(
+            self.meanders[0].get(warp.to_array()),
+            self.meanders[1].get(warp.to_array()),
+        ) * MEANDER_AMPLITUDE;
+        (p + meander).as_vec2()
+    }
+
+    /// Returns the center and radius of the lakes of the cells around a column.
+    fn lakes_around(&self, column: IVec2) -> impl Iterator<Item = (Vec2, f32)> + '_ {
+        let cell = column.div_euclid(IVec2::splat(LAKE_CELL));
+
+        (-1..=1)
+            .flat_map(move |x| (-1..=1).map(move |z| cell + IVec2::new(x, z)))
+            .filter_map(|cell| {
+                let mut seed = self
+                    .seed
+                    .derive(25)
+                    .derive(cell.x as u32 as u64)
+                    .derive(cell.y as u32 as u64);
+                let mut next = || {
+                    seed = seed.derive(1);
+                    (seed.0 >> 40) as f32 / (1u64 << 24) as f32
+                };
+
+                let spawns = next() < LAKE_CHANCE;
+                // keep the lakes away from the cell borders so they don't overlap.
+                let center = (cell * LAKE_CELL).as_vec2()
+                    + (Vec2::new(next(), next()) * 0.5 + 0.25) * LAKE_CELL as f32;
+                let radius = 14.0 + next() * 22.0;
+
+                spawns.then_some((center, radius))
+            })
+    }
+
+    /// Returns the distance (in voxels) from a column to the shore of a lake, negative in the lake.
+    fn lake_distance(&self, column: IVec2, center: Vec2, radius: f32) -> f32 {
+        // wobble the shore so the lakes aren't perfect circles.
+        let wobble = self.shores.get((column.as_dvec2() * 0.04).to_array()) as f32;
+        column.as_vec2().distance(center) - radius * (1.0 + wobble * 0.3)
+    }
+}
+
+/// Carves a body of water with the specified level into a column, `distance` being the distance (in voxels) from the
+/// column to the shore, negative in the water. The terrain around the water gets raised above the water level when
+/// needed so the water is always held between banks.
+fn carve_shore(column: TerrainColumn, level: f32, distance: f32, depth: f32) -> TerrainColumn {
+    let level = level.round();
+    let banks = column.height.max(level + 1.0);
+
+    if distance < 0.0 {
+        // the bed gets deeper away from the shore.
+        TerrainColumn {
+            height: level - 1.0 - (depth - 1.0) * smoothstep(0.0, depth * 2.0, -distance),
+            water_level: level,
+        }
+    } else if distance < BANK_WIDTH {
+        let height = lerp(level + 1.0, banks, smoothstep(0.0, BANK_WIDTH, distance));
+
+        TerrainColumn {
+            height,
+            // keeps the overhangs from digging through the banks, without flooding them.
+            water_level: shore_water_level(column, level, height),
+        }
+    } else if distance < BANK_WIDTH + LEVEE_WIDTH {
+        let height = lerp(
+            banks,
+            column.height,
+            smoothstep(BANK_WIDTH, BANK_WIDTH + LEVEE_WIDTH, distance),
+        );
+
+        TerrainColumn {
+            height,
+            water_level: shore_water_level(column, level, height),
+        }
+    } else {
+        column
+    }
+}
+
+/// The water level of a column around a body of water, which must not flood the raised terrain above the sea.
+fn shore_water_level(column: TerrainColumn, level: f32, height: f32) -> f32 {
+    column
+        .water_level
+        .max(level)
+        .min(height)
+        .max(SEA_LEVEL as f32)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
@@ -0,0 +1,434 @@
+use std::sync::Arc;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, ReadAssetBytesError},
+    math::{IVec2, Vec2},
+    prelude::*,
+};
+use image::ImageFormat;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::voxel::RegenerateTerrain;
+
+use super::TERRAIN_GENERATOR;
+
+/// The radius (in voxels) of the blur smoothing the imported heights into the broad shape of the terrain.
+const VALLEY_BLUR_RADIUS: f32 = 24.0;
+
+/// How the terrain is sampled outside of the heightmap image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum HeightmapEdges {
+    /// The pixels at the edges of the image stretch out to the rest of the world.
+    #[default]
+    Clamp,
+    /// The image repeats over the whole world.
+    Tile,
+}
+
+/// A terrain authored as a greyscale heightmap image, used in place of the height noise.
+/// The pixels are mapped to the columns of the world from the `origin` column (the top left pixel) with `scale` voxels
+/// per pixel, black being the bottom of the vertical range and white its top.
+pub struct ImportedTerrain {
+    size: UVec2,
+    /// The normalized height of the pixels, in rows along the x axis.
+    heights: Vec<f32>,
+    /// The biome of the pixels as an index into `biome_names`, if the pixel colour is mapped to a biome.
+    biomes: Option<Vec<Option<u8>>>,
+    biome_names: Vec<String>,
+    scale: f32,
+    heights_range: (f32, f32),
+    origin: IVec2,
+    edges: HeightmapEdges,
+    /// The heights blurred into the broad shape of the terrain the rivers and lakes follow, if they are carved.
+    valleys: Option<Vec<f32>>,
+    density: bool,
+}
+
+impl ImportedTerrain {
+    /// Returns the height of the terrain at a column of the world, interpolated between the pixels.
+    pub fn height(&self, column: IVec2) -> f32 {
+        self.sample_heights(&self.heights, column)
+    }
+
+    /// Returns the height of the broad shape of the terrain at a column of the world, without its small hills.
+    pub fn valley_height(&self, column: IVec2) -> f32 {
+        self.sample_heights(self.valleys.as_ref().unwrap_or(&self.heights), column)
+    }
+
+    /// Whether the rivers and lakes get carved into the terrain.
+    pub fn hydrology(&self) -> bool {
+        self.valleys.is_some()
+    }
+
+    /// Whether the density field perturbs the terrain into caves and overhangs.
+    pub fn density(&self) -> bool {
+        self.density
+    }
+
+    /// Returns the world height of a column from normalized heights laid out like the pixels,
+    /// interpolated between the pixels.
+    fn sample_heights(&self, heights: &[f32], column: IVec2) -> f32 {
+        let pos = self.pixel_pos(column) - 0.5;
+        let base = pos.floor();
+        let t = pos - base;
+        let base = base.as_ivec2();
+
+        let sample = |offset: IVec2| heights[self.pixel_index(base + offset)];
+        let top = sample(IVec2::ZERO) + (sample(IVec2::X) - sample(IVec2::ZERO)) * t.x;
+        let bottom = sample(IVec2::Y) + (sample(IVec2::ONE) - sample(IVec2::Y)) * t.x;
+        let height = top + (bottom - top) * t.y;
+
+        let (min, max) = self.heights_range;
+        min + (max - min) * height
+    }
+
+    /// Blurs the heights with two passes of a box blur along each axis, which is close to a gaussian blur.
+    fn blur_heights(&self, radius: i32) -> Vec<f32> {
+        let size = self.size.as_ivec2();
+        let mut heights = self.heights.clone();
+
+        for _ in 0..2 {
+            for axis in [IVec2::X, IVec2::Y] {
+                let mut blurred = vec![0.0; heights.len()];
+                let across = IVec2::ONE - axis;
+
+                // the sum of the heights in the window slides along each line of pixels.
+                for line in 0..(size * across).max_element() {
+                    let start = across * line;
+                    let height = |offset: i32| heights[self.pixel_index(start + axis * offset)];
+                    let mut sum: f32 = (-radius..=radius).map(height).sum();
+
+                    for offset in 0..(size * axis).max_element() {
+                        blurred[self.pixel_index(start + axis * offset)] =
+                            sum / (2 * radius + 1) as f32;
+                        sum += height(offset + radius + 1) - height(offset - radius);
+                    }
+                }
+
+                heights = blurred;
+            }
+        }
+
+        heights
+    }
+
+    /// Returns the name of the biome painted on the biome map at a column of the world, if any.
+    pub fn biome_at(&self, column: IVec2) -> Option<&str> {
+        let biomes = self.biomes.as_ref()?;
+        let index = biomes[self.pixel_index(self.pixel_pos(column).floor().as_ivec2())]?;
+        Some(&self.biome_names[index as usize])
+    }
+
+    /// Returns the position of a column of the world in the image, in pixels.
+    fn pixel_pos(&self, column: IVec2) -> Vec2 {
+        // the columns are sampled at their center.
+        ((column - self.origin).as_vec2() + 0.5) / self.scale
+    }
+
+    /// Returns the index of a pixel, applying the edge mode to the pixels outside of the image.
+    fn pixel_index(&self, pixel: IVec2) -> usize {
+        let size = self.size.as_ivec2();
+        let pixel = match self.edges {
+            HeightmapEdges::Clamp => pixel.clamp(IVec2::ZERO, size - 1),
+            HeightmapEdges::Tile => pixel.rem_euclid(size),
+        };
+        (pixel.y * size.x + pixel.x) as usize
+    }
+}
+
+/// The settings of an imported terrain, read from a `.heightmap.ron` file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImportedTerrainSettings {
+    /// The path (relative to the `assets` folder) of the greyscale PNG heightmap, 8 or 16 bits per pixel.
+    pub heightmap: String,
+    /// The path of a PNG image the same size as the heightmap whose colours give the biomes of the columns.
+    #[serde(default)]
+    pub biome_map: Option<String>,
+    /// The biomes the colours of the biome map stand for, the columns with other colours get a biome from the climate.
+    #[serde(default)]
+    pub biome_colors: Vec<((u8, u8, u8), String)>,
+    /// The size (in voxels) of a pixel of the images.
+    #[serde(default = "ImportedTerrainSettings::default_scale")]
+    pub scale: f32,
+    /// The world heights (in voxels) of the black and white pixels of the heightmap.
+    /// The terrain isn't generated above the `MAX_TERRAIN_CHUNK_Y` height.
+    pub heights: (f32, f32),
+    /// The world column the top left pixel of the images lies at.
+    #[serde(default)]
+    pub origin: (i32, i32),
+    #[serde(default)]
+    pub edges: HeightmapEdges,
+    /// Whether rivers and lakes get carved into the terrain, following the heightmap blurred into its broad shape.
+    #[serde(default = "ImportedTerrainSettings::enabled")]
+    pub hydrology: bool,
+    /// Whether the density field perturbs the terrain into caves and overhangs.
+    /// Disabling it keeps the terrain surface exactly at the heights of the heightmap.
+    #[serde(default = "ImportedTerrainSettings::enabled")]
+    pub density: bool,
+}
+
+impl ImportedTerrainSettings {
+    fn default_scale() -> f32 {
+        1.0
+    }
+
+    fn enabled() -> bool {
+        true
+    }
+}
+
+/// An imported terrain along with its decoded images.
+#[derive(Asset, TypePath)]
+pub struct ImportedTerrainAsset(pub Arc<ImportedTerrain>);
+
+#[derive(Debug, Error)]
+pub enum ImportedTerrainLoaderError {
+    #[error("could not read the heightmap settings: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the heightmap settings: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not read the image: {0}")]
+    Read(#[from] ReadAssetBytesError),
+    #[error("could not decode the image: {0}")]
+    Decode(#[from] image::ImageError),
+    #[error("invalid heightmap settings: {0}")]
+    Invalid(&'static str),
+}
+
+/// Loads the imported terrains from the `.heightmap.ron` files, decoding the images they refer to.
+#[derive(Default)]
+pub struct ImportedTerrainLoader;
+
+impl AssetLoader for ImportedTerrainLoader {
+    type Asset = ImportedTerrainAsset;
+    type Settings = ();
+    type Error = ImportedTerrainLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let settings: ImportedTerrainSettings = ron::de::from_bytes(&bytes)?;
+
+        if settings.scale <= 0.0 {
+            return Err(ImportedTerrainLoaderError::Invalid(
+                "the scale must be positive",
+            ));
+        }
+        if settings.biome_colors.len() > u8::MAX as usize {
+            return Err(ImportedTerrainLoaderError::Invalid(
+                "there are too many biome colours",
+            ));
+        }
+
+        // the images are read as dependencies so editing them reloads the terrain.
+        let bytes = load_context
+            .read_asset_bytes(settings.heightmap.clone())
+            .await?;
+        let heightmap =
+            image::load_from_memory_with_format(&bytes, ImageFormat::Png)?.into_luma16();
+        let size = UVec2::from(heightmap.dimensions());
+        if size.cmpeq(UVec2::ZERO).any() {
+            return Err(ImportedTerrainLoaderError::Invalid(
+                "the heightmap is empty",
+            ));
+        }
+        let heights = heightmap
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / u16::MAX as f32)
+            .collect();
+
+        let biomes = match &settings.biome_map {
+            Some(path) => {
+                let bytes = load_context.read_asset_bytes(path.clone()).await?;
+                let biome_map =
+                    image::load_from_memory_with_format(&bytes, ImageFormat::Png)?.into_rgb8();
+                if UVec2::from(biome_map.dimensions()) != size {
+                    return Err(ImportedTerrainLoaderError::Invalid(
+                        "the biome map must be the same size as the heightmap",
+                    ));
+                }
+
+                Some(
+                    biome_map
+                        .pixels()
+                        .map(|pixel| {
+                            let [r, g, b] = pixel.0;
+                            settings
+                                .biome_colors
+                                .iter()
+                                .position(|(color, _)| *color == (r, g, b))
+                                .map(|index| index as u8)
+                        })
+                        .collect(),
+                )
+            }
+            None => None,
+        };
+
+        let mut terrain = ImportedTerrain {
+            size,
+            heights,
+            biomes,
+            biome_names: settings
+                .biome_colors
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect(),
+            scale: settings.scale,
+            heights_range: settings.heights,
+            origin: IVec2::from(settings.origin),
+            edges: settings.edges,
+            valleys: None,
+            density: settings.density,
+        };
+
+        // the rivers and lakes follow the heights without their small hills, like they do with the height noise.
+        if settings.hydrology {
+            let radius = (VALLEY_BLUR_RADIUS / settings.scale).ceil() as i32;
+            terrain.valleys = Some(terrain.blur_heights(radius));
+        }
+
+        Ok(ImportedTerrainAsset(Arc::new(terrain)))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["heightmap.ron"]
+    }
+}
+
+/// The path (relative to the `assets` folder) of the `.heightmap.ron` file the terrain gets imported from,
+/// the terrain being generated from the height noise when there is none.
+/// Setting the path (e.g. from a startup system) imports the terrain and regenerates the loaded chunks.
+#[derive(Resource, Default)]
+pub struct TerrainHeightmap {
+    pub path: Option<String>,
+    handle: Option<Handle<ImportedTerrainAsset>>,
+}
+
+/// Starts loading the imported terrain whenever its path changes.
+fn load_imported_terrain(
+    asset_server: Res<AssetServer>,
+    mut heightmap: ResMut<TerrainHeightmap>,
+    mut regenerate: EventWriter<RegenerateTerrain>,
+) {
+    if !heightmap.is_changed() {
+        return;
+    }
+
+    // storing the handle mustn't count as a change of the path.
+    let handle = heightmap.path.clone().map(|path| asset_server.load(path));
+    heightmap.bypass_change_detection().handle = handle;
+
+    // the terrain gets generated from the noise again until the new heightmap is loaded.
+    if Arc::make_mut(&mut TERRAIN_GENERATOR.write().unwrap())
+        .set_imported_terrain(None)
+        .is_some()
+    {
+        regenerate.send(RegenerateTerrain);
+    }
+}
+
+/// Plugs the imported terrain into the terrain generator as it gets loaded or modified.
+fn apply_imported_terrain(
+    mut events: EventReader<AssetEvent<ImportedTerrainAsset>>,
+    assets: Res<Assets<ImportedTerrainAsset>>,
+    heightmap: Res<TerrainHeightmap>,
+    mut regenerate: EventWriter<RegenerateTerrain>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Added { id } | AssetEvent::Modified { id } = *event {
+            if heightmap.handle.as_ref().map(Handle::id) != Some(id) {
+                continue;
+            }
+
+            if let Some(ImportedTerrainAsset(terrain)) = assets.get(id) {
+                Arc::make_mut(&mut TERRAIN_GENERATOR.write().unwrap())
+                    .set_imported_terrain(Some(terrain.clone()));
+                info!("Imported terrain from {:?}", heightmap.path);
+                regenerate.send(RegenerateTerrain);
+            }
+        }
+    }
+}
+
+/// Imports the terrain from the heightmap set in the [`TerrainHeightmap`] resource.
+/// The terrain gets imported again when the files change if the `file_watcher` feature of bevy is enabled.
+pub struct ImportedTerrainPlugin;
+
+impl Plugin for ImportedTerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ImportedTerrainAsset>()
+            .init_asset_loader::<ImportedTerrainLoader>()
+            .init_resource::<TerrainHeightmap>()
+            .add_systems(
+                Update,
+                (load_imported_terrain, apply_imported_terrain).chain(),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4 x 3 pixels terrain whose heights are the indices of the pixels, with 2 voxels per pixel.
+    fn terrain(edges: HeightmapEdges) -> ImportedTerrain {
+        ImportedTerrain {
+            size: UVec2::new(4, 3),
+            heights: (0..12).map(|index| index as f32).collect(),
+            biomes: None,
+            biome_names: Vec::new(),
+            scale: 2.0,
+            heights_range: (0.0, 1.0),
+            origin: IVec2::ZERO,
+            edges,
+            valleys: None,
+            density: true,
+        }
+    }
+
+    #[test]
+    fn clamped_pixels_stretch_the_edges() {
+        let terrain = terrain(HeightmapEdges::Clamp);
+        assert_eq!(terrain.pixel_index(IVec2::new(1, 2)), 9);
+        assert_eq!(terrain.pixel_index(IVec2::new(-1, 0)), 0);
+        assert_eq!(terrain.pixel_index(IVec2::new(-7, 1)), 4);
+        assert_eq!(terrain.pixel_index(IVec2::new(2, -5)), 2);
+        assert_eq!(terrain.pixel_index(IVec2::new(9, 9)), 11);
+
+        // the columns left of the image take the height of its left edge.
+        assert_eq!(
+            terrain.height(IVec2::new(-1, 2)),
+            terrain.height(IVec2::new(0, 2))
+        );
+        assert_eq!(terrain.height(IVec2::new(-20, 2)), 3.0);
+    }
+
+    #[test]
+    fn tiled_pixels_wrap_around_negative_columns() {
+        let terrain = terrain(HeightmapEdges::Tile);
+        assert_eq!(terrain.pixel_index(IVec2::new(1, 2)), 9);
+        assert_eq!(terrain.pixel_index(IVec2::new(-1, 0)), 3);
+        assert_eq!(terrain.pixel_index(IVec2::new(-4, 0)), 0);
+        assert_eq!(terrain.pixel_index(IVec2::new(-5, -1)), 11);
+        assert_eq!(terrain.pixel_index(IVec2::new(5, 4)), 5);
+
+        // the column just left of the image samples its last pixel rather than the first one twice.
+        assert_eq!(
+            terrain.pixel_pos(IVec2::new(-1, 0)).floor().as_ivec2(),
+            IVec2::new(-1, 0)
+        );
+        for column in [IVec2::new(-1, -1), IVec2::new(-3, 5), IVec2::new(-17, -9)] {
+            assert_eq!(
+                terrain.height(column),
+                terrain.height(column + IVec2::new(8, 6))
+            );
+        }
+    }
+}
@@ -1,21 +1,40 @@
 use float_ord::FloatOrd;
-use std::{collections::BTreeMap, sync::RwLock};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, RwLock},
+};
 
+use ::noise::{Fbm, MultiFractal, NoiseFn, SuperSimplex};
 use bevy::{
-    math::{IVec3, Vec3Swizzles},
-    prelude::Plugin,
+    log::{info, warn},
+    math::{IVec2, IVec3, Vec2, Vec3Swizzles},
+    prelude::{Plugin, Resource},
 };
 use once_cell::sync::Lazy;
 
 use self::{
     biomes::{BiomeTerrainGenerator, IntoBoxedTerrainGenerator},
+    climate::{Climate, ClimateMaps, ClimateRange},
     common::terrain_generate_world_bottom_border,
-    noise::{generate_heightmap_data, Heightmap},
+    density::{TerrainColumn, TerrainDensity, SEA_LEVEL},
+    hydrology::{Hydrology, River, RiverCache},
+    imported::ImportedTerrain,
+    noise::Heightmap,
+    ores::OreDistribution,
+    structures::StructurePlacement,
 };
 
-use super::{storage::VoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH_U};
+use super::{
+    material::VoxelMaterial,
+    materials::{CoalOre, GoldOre, IronOre},
+    storage::VoxelBuffer,
+    ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_LENGTH_U,
+};
 
 mod biomes;
+pub use biomes::biomes_loaded;
 
 /// noise functions ported over from C / GLSL code
 pub mod noise;
@@ -23,48 +42,434 @@ pub mod noise;
 /// common functions used by all terrain generators
 pub mod common;
 
+/// the 3D density field carving caves and overhangs into the heightmap terrain
+pub mod density;
+
+/// ore veins scattered through the underground rock
+pub mod ores;
+
+/// rivers and lakes carving their beds into the terrain
+pub mod hydrology;
+
+/// the climate maps the biomes get picked from
+pub mod climate;
+
+/// hand-built structures placed on top of the terrain
+pub mod structures;
+
+/// terrain imported from greyscale heightmap images
+pub mod imported;
+
+/// The seed of the world generation, a given seed always generates the same world.
+/// It has to be set before the world starts generating, e.g. when loading a world save. Unless it is inserted before
+/// the [`TerrainGeneratorPlugin`], the seed is read from the `--seed <seed>` command line argument or picked randomly.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// Returns a random seed, different on every run.
+    pub fn random() -> Self {
+        Self(RandomState::new().build_hasher().finish())
+    }
+
+    /// Returns the seed passed on the command line with `--seed <seed>`, if any.
+    pub fn from_args() -> Option<Self> {
+        let arg = std::env::args().skip_while(|arg| arg != "--seed").nth(1)?;
+        match arg.parse() {
+            Ok(seed) => Some(Self(seed)),
+            Err(err) => {
+                warn!("Ignoring the invalid world seed {arg:?}: {err}");
+                None
+            }
+        }
+    }
+
+    /// Scrambles the bits of the seed (splitmix64) so close seeds give unrelated worlds.
+    const fn mix(self) -> u64 {
+        let mut z = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Derives an unrelated seed from this one, for world generation stages needing their own noise.
+    pub const fn derive(self, salt: u64) -> Self {
+        Self(self.mix() ^ salt.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    /// Returns the seed of the noise functions from the `noise` crate.
+    pub const fn noise_seed(self) -> u32 {
+        self.mix() as u32
+    }
+
+    /// Returns the offset applied to the positions hashed by the [`noise`] functions.
+    /// The offset is kept small enough for the hashes not to lose precision.
+    pub fn offset(self) -> Vec2 {
+        let bits = self.mix() >> 32;
+        Vec2::new((bits & 0xffff) as f32, (bits >> 16) as f32) / 64.0
+    }
+}
+
+/// The width of the transition band between two biomes, as a difference of distance in the climate space.
+const BIOME_BLEND_BAND: f32 = 0.12;
+/// How far (in voxels) the altitude climate raises or sinks the terrain.
+const ALTITUDE_RELIEF: f32 = 16.0;
+/// How far (in voxels) the decorations (e.g. the leaves of the trees) may spread sideways and below their column.
+const DECORATION_REACH: i32 = 8;
+/// How far (in voxels) around a chunk the columns may get sampled, for the decorations and structures reaching into it.
+const COLUMN_REACH: i32 = 64;
+
 // Terrain generator singleton.
-pub static TERRAIN_GENERATOR: Lazy<RwLock<TerrainGenerator>> = Lazy::new(Default::default);
+// The generation tasks each work with the generator as it was when they got spawned, so editing the generator doesn't
+// wait for them: the edits go through `Arc::make_mut`, which copies the generator while tasks still use it.
+pub static TERRAIN_GENERATOR: Lazy<RwLock<Arc<TerrainGenerator>>> = Lazy::new(Default::default);
 
-#[derive(Default)]
+/// The biome generating the terrain while no biome is registered, e.g. after the data biome overriding the last
+/// built-in biome got removed.
+static FALLBACK_BIOME: Lazy<Box<dyn BiomeTerrainGenerator>> =
+    Lazy::new(|| biomes::BasicPlainsBiomeTerrainGenerator.into_boxed_generator());
+
+#[derive(Default, Clone)]
 pub struct TerrainGenerator {
-    biomes_map: BTreeMap<FloatOrd<f32>, Box<dyn BiomeTerrainGenerator>>,
+    biomes: Vec<(ClimateRange, Arc<dyn BiomeTerrainGenerator>)>,
+    ores: Vec<OreDistribution>,
+    /// The terrain imported from a heightmap, replacing the height noise.
+    imported: Option<Arc<ImportedTerrain>>,
+    /// The rivers follow the broad shape of the terrain, so they get traced again when the biomes or the imported
+    /// terrain change.
+    rivers: RiverCache,
 }
 
 impl TerrainGenerator {
+    /// Registers a biome thriving in the specified climate or climate range.
+    /// A biome may be registered for several climates, e.g. to spread it over disjoint regions of the climate space.
     pub fn register_biome_generator(
         &mut self,
-        chance: f32,
+        climate: impl Into<ClimateRange>,
         biome: Box<dyn BiomeTerrainGenerator>,
     ) -> &mut Self {
-        self.biomes_map.insert(FloatOrd(chance), biome);
+        self.biomes.push((climate.into(), biome.into()));
+        self.rivers.clear();
+        self
+    }
+
+    /// Unregisters every biome with the specified name, e.g. before registering a new definition of the biome.
+    pub fn unregister_biome_generator(&mut self, name: &str) -> &mut Self {
+        self.biomes.retain(|(_, biome)| biome.name() != name);
+        if self.biomes.is_empty() {
+            warn!(
+                "No biome left after unregistering {}, generating the terrain from the fallback biome",
+                name
+            );
+        }
+        self.rivers.clear();
         self
     }
 
-    //returns the biome with the closest temp / humidity
-    #[allow(clippy::borrowed_box)]
-    fn biome_at(&self, chunk_key: IVec3) -> &Box<dyn BiomeTerrainGenerator> {
-        const BIOME_INVSCALE: f32 = 0.001;
+    pub fn register_ore(&mut self, ore: OreDistribution) -> &mut Self {
+        // the veins are looked up from the neighbouring chunks only.
+        assert!(
+            ore.vein_reach() <= CHUNK_LENGTH as f32,
+            "ore veins can't reach further than a chunk"
+        );
+        self.ores.push(ore);
+        self
+    }
+
+    /// Sets the imported terrain used in place of the height noise, returning the previous one.
+    pub fn set_imported_terrain(
+        &mut self,
+        imported: Option<Arc<ImportedTerrain>>,
+    ) -> Option<Arc<ImportedTerrain>> {
+        self.rivers.clear();
+        std::mem::replace(&mut self.imported, imported)
+    }
+
+    /// Returns the biome painted at a column on the biome map of the imported terrain, if any.
+    fn imported_biome_at(&self, column: IVec2) -> Option<&dyn BiomeTerrainGenerator> {
+        let name = self.imported.as_ref()?.biome_at(column)?;
+        self.biomes
+            .iter()
+            .find(|(_, biome)| biome.name() == name)
+            .map(|(_, biome)| biome.as_ref())
+    }
+
+    /// Returns the structure placements of the registered biomes.
+    /// The biomes registered for several climates only place their structures once.
+    fn structures(&self) -> impl Iterator<Item = &StructurePlacement> {
+        self.biomes
+            .iter()
+            .enumerate()
+            .filter(|(index, (_, biome))| {
+                self.biomes[..*index]
+                    .iter()
+                    .all(|(_, other)| other.name() != biome.name())
+            })
+            .flat_map(|(_, (_, biome))| biome.structures())
+    }
+
+    /// Returns the biome with the closest climate to a world position.
+    fn biome_at(&self, pos: IVec3, climate_maps: &ClimateMaps) -> &dyn BiomeTerrainGenerator {
+        if let Some(biome) = self.imported_biome_at(pos.xz()) {
+            return biome;
+        }
+
+        let climate = climate_maps.climate_at(pos.xz());
+
+        self.biomes
+            .iter()
+            .min_by_key(|(range, _)| FloatOrd(range.distance(&climate)))
+            .map_or(FALLBACK_BIOME.as_ref(), |(_, biome)| biome.as_ref())
+    }
+
+    /// Returns the biomes blended at a column of the world along with their weights, which sum up to one.
+    /// The weight of a biome fades out as its climate gets farther than the closest one,
+    /// so a column away from the biome borders only has a single biome.
+    fn column_biomes(&self, climate: Climate) -> Vec<(&dyn BiomeTerrainGenerator, f32)> {
+        if self.biomes.is_empty() {
+            return vec![(FALLBACK_BIOME.as_ref(), 1.0)];
+        }
+
+        let distances: Vec<f32> = self
+            .biomes
+            .iter()
+            .map(|(range, _)| range.distance(&climate))
+            .collect();
+        let min_distance = distances.iter().copied().fold(f32::INFINITY, f32::min);
+
+        let mut biomes: Vec<(&dyn BiomeTerrainGenerator, f32)> = Vec::with_capacity(1);
+        for ((_, biome), distance) in self.biomes.iter().zip(distances) {
+            let weight = (1.0 - (distance - min_distance) / BIOME_BLEND_BAND).clamp(0.0, 1.0);
+            // smoothstep for the blending not to show sharp creases at the edges of the band.
+            let weight = weight * weight * (3.0 - 2.0 * weight);
+            if weight <= 0.0 {
+                continue;
+            }
+
+            match biomes
+                .iter_mut()
+                .find(|(other, _)| other.name() == biome.name())
+            {
+                Some((_, total)) => *total += weight,
+                None => biomes.push((biome.as_ref(), weight)),
+            }
+        }
+
+        let total: f32 = biomes.iter().map(|(_, weight)| weight).sum();
+        biomes.iter_mut().for_each(|(_, weight)| *weight /= total);
+        biomes
+    }
+
+    /// Returns the height of a column of the world blended between its biomes, using the specified height noise.
+    fn blended_height(
+        biomes: &[(&dyn BiomeTerrainGenerator, f32)],
+        climate: Climate,
+        height_noise: &impl NoiseFn<f64, 2>,
+        column: IVec2,
+    ) -> f32 {
+        biomes
+            .iter()
+            .map(|(biome, weight)| biome.relief().height(height_noise, column.as_vec2()) * weight)
+            .sum::<f32>()
+            + climate.altitude * ALTITUDE_RELIEF
+    }
+
+    /// Returns the broad shape of the terrain at a column of the world, i.e. its height without the small hills.
+    fn valley_level(&self, column: IVec2, noise: &WorldNoise) -> f32 {
+        if let Some(imported) = &self.imported {
+            return imported.valley_height(column);
+        }
+
+        let climate = noise.climate.climate_at(column);
+        let biomes = self.column_biomes(climate);
+        Self::blended_height(&biomes, climate, &noise.valleys, column)
+    }
+
+    /// Whether the rivers and lakes get carved into the terrain, the imported terrains may leave them out.
+    fn hydrology(&self) -> bool {
+        self.imported
+            .as_ref()
+            .is_none_or(|imported| imported.hydrology())
+    }
+
+    /// Returns the rivers shaping the columns around a chunk.
+    fn rivers_around(&self, chunk_key: IVec3, noise: &WorldNoise) -> Vec<Arc<River>> {
+        if !self.hydrology() {
+            return Vec::new();
+        }
+
+        let min = chunk_key.xz() - COLUMN_REACH;
+        let max = chunk_key.xz() + CHUNK_LENGTH as i32 - 1 + COLUMN_REACH;
+        noise
+            .hydrology
+            .rivers_around(min, max, &self.rivers, |column| {
+                self.valley_level(column, noise)
+            })
+    }
+
+    /// Returns the shape of a column of the world and the biome giving its strata and decorations,
+    /// `rivers` being the rivers around the column.
+    fn sample_column(
+        &self,
+        column: IVec2,
+        noise: &WorldNoise,
+        rivers: &[Arc<River>],
+    ) -> (TerrainColumn, &dyn BiomeTerrainGenerator) {
+        let climate = noise.climate.climate_at(column);
+        let biomes = self.column_biomes(climate);
 
-        let coords = noise::voronoi(chunk_key.xzy().truncate().as_vec2() * BIOME_INVSCALE);
-        let p = FloatOrd(noise::rand2to1i(coords));
+        // the biomes each shape the terrain, their heights get blended near the borders.
+        let height = match &self.imported {
+            Some(imported) => imported.height(column),
+            None => Self::blended_height(&biomes, climate, &noise.height, column),
+        };
+        let terrain = if self.hydrology() {
+            noise.hydrology.carve(column, height, rivers, |column| {
+                self.valley_level(column, noise)
+            })
+        } else {
+            TerrainColumn {
+                height,
+                water_level: SEA_LEVEL as f32,
+            }
+        };
 
-        self.biomes_map
-            .range(..=p)
-            .last()
-            .map_or(self.biomes_map.first_key_value().unwrap().1, |x| x.1)
+        if let Some(biome) = self.imported_biome_at(column) {
+            return (terrain, biome);
+        }
+
+        // the strata and decorations of a column come from a single biome picked at random by weight,
+        // which dithers the biomes across the transition band.
+        let mut pick = noise::rand2to1(
+            column.as_vec2() * 0.1,
+            Vec2::new(91.173, 37.431),
+            noise.seed,
+        )
+        .abs();
+        let (biome, _) = *biomes
+            .iter()
+            .find(|(_, weight)| {
+                pick -= weight;
+                pick < 0.0
+            })
+            .unwrap_or(biomes.last().unwrap());
+
+        (terrain, biome)
     }
 
-    pub fn generate(&self, chunk_key: IVec3, buffer: &mut VoxelBuffer<Voxel, ChunkShape>) {
-        let biome = self.biome_at(chunk_key);
-        let noise = generate_heightmap_data(chunk_key, CHUNK_LENGTH_U);
+    pub fn generate(
+        &self,
+        chunk_key: IVec3,
+        seed: WorldSeed,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let mut noise = WorldNoise::new(seed);
+        if self
+            .imported
+            .as_ref()
+            .is_some_and(|imported| !imported.density())
+        {
+            noise.density = noise.density.unperturbed();
+        }
+        let rivers = self.rivers_around(chunk_key, &noise);
+        let sample_column =
+            |column: IVec2| self.sample_column(chunk_key.xz() + column, &noise, &rivers);
+        let surface_height = |column: IVec2, terrain: TerrainColumn| {
+            let world_column = chunk_key.xz() + column;
+            noise.density.surface_height(world_column, terrain)
+        };
+
+        // laid out in rows along the x axis like the heightmaps.
+        let columns: Vec<_> = (0..CHUNK_LENGTH as i32)
+            .flat_map(|z| (0..CHUNK_LENGTH as i32).map(move |x| IVec2::new(x, z)))
+            .map(|column| {
+                let (terrain, biome) = sample_column(column);
+                (column, terrain, biome)
+            })
+            .collect();
 
-        let noise_map = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&noise);
+        let heights: Vec<f32> = columns
+            .iter()
+            .map(|(_, terrain, _)| terrain.height)
+            .collect();
+        let water_levels: Vec<f32> = columns
+            .iter()
+            .map(|(_, terrain, _)| terrain.water_level)
+            .collect();
 
-        common::terrain_carve_heightmap(buffer, chunk_key, &noise_map);
+        common::terrain_carve_density(
+            buffer,
+            chunk_key,
+            &Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&heights),
+            &Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&water_levels),
+            &noise.density,
+        );
 
-        biome.carve_terrain(chunk_key, noise_map, buffer);
-        biome.decorate_terrain(chunk_key, noise_map, buffer);
+        // the biomes work on the actual surface of the terrain rather than on the heightmap.
+        let surface: Vec<i32> = columns
+            .iter()
+            .map(|(column, terrain, _)| surface_height(*column, *terrain))
+            .collect();
+
+        for ((column, _, biome), surface) in columns.iter().zip(&surface) {
+            biome.carve_column(chunk_key, column.as_uvec2(), *surface, buffer);
+        }
+
+        for ore in &self.ores {
+            ore.place_veins(
+                chunk_key,
+                seed,
+                |pos| self.biome_at(pos, &noise.climate).name(),
+                buffer,
+            );
+        }
+
+        // the decorations of the columns around the chunk may reach into it, they get generated again here and
+        // clipped to the chunk so the decorations straddling the chunk borders come out whole.
+        // the columns are decorated in the same order from every chunk for the overlapping decorations to agree.
+        let reach = if chunk_key.y + CHUNK_LENGTH as i32 + DECORATION_REACH > SEA_LEVEL {
+            DECORATION_REACH
+        } else {
+            0
+        };
+
+        for z in -reach..CHUNK_LENGTH as i32 + reach {
+            for x in -reach..CHUNK_LENGTH as i32 + reach {
+                let column = IVec2::new(x, z);
+                let (terrain, surface, biome) = if column.cmpge(IVec2::ZERO).all()
+                    && column.cmplt(IVec2::splat(CHUNK_LENGTH as i32)).all()
+                {
+                    let index = (z * CHUNK_LENGTH as i32 + x) as usize;
+                    (columns[index].1, surface[index], columns[index].2)
+                } else {
+                    let (terrain, biome) = sample_column(column);
+                    (terrain, surface_height(column, terrain), biome)
+                };
+
+                // nothing grows under water.
+                if surface >= terrain.water_level.round() as i32 {
+                    biome.decorate_column(chunk_key, seed, column, surface, buffer);
+                }
+            }
+        }
+
+        // the structures are placed last so they don't get covered by the decorations.
+        let surface_at = |column: IVec2| {
+            let (terrain, _) = self.sample_column(column, &noise, &rivers);
+            let surface = noise.density.surface_height(column, terrain);
+            (surface >= terrain.water_level.round() as i32).then_some(surface)
+        };
+
+        for structure in self.structures() {
+            structure.place_structures(
+                chunk_key,
+                seed,
+                |pos| self.biome_at(pos, &noise.climate).name(),
+                surface_at,
+                buffer,
+            );
+        }
 
         if chunk_key.y == 0 {
             terrain_generate_world_bottom_border(buffer);
@@ -72,24 +477,76 @@ impl TerrainGenerator {
     }
 }
 
+/// The noise functions of the world generation, set up for the seed of the world being generated.
+struct WorldNoise {
+    seed: WorldSeed,
+    height: Fbm<SuperSimplex>,
+    /// The height noise without its details, giving the broad shape of the terrain the rivers and lakes follow.
+    valleys: Fbm<SuperSimplex>,
+    climate: ClimateMaps,
+    hydrology: Hydrology,
+    density: TerrainDensity,
+}
+
+impl WorldNoise {
+    fn new(seed: WorldSeed) -> Self {
+        let height = noise::height_noise(seed);
+        let valleys = height.clone().set_octaves(1);
+
+        Self {
+            seed,
+            height,
+            valleys,
+            climate: ClimateMaps::new(seed),
+            hydrology: Hydrology::new(seed),
+            density: TerrainDensity::new(seed),
+        }
+    }
+}
+
 pub struct TerrainGeneratorPlugin;
 
 impl Plugin for TerrainGeneratorPlugin {
-    fn build(&self, _: &mut bevy::prelude::App) {
-        TERRAIN_GENERATOR
-            .write()
-            .unwrap()
+    fn build(&self, app: &mut bevy::prelude::App) {
+        if !app.world().contains_resource::<WorldSeed>() {
+            let seed = WorldSeed::from_args().unwrap_or_else(WorldSeed::random);
+            app.insert_resource(seed);
+        }
+        info!(
+            "Generating the world from seed {}",
+            app.world().resource::<WorldSeed>().0
+        );
+
+        app.add_plugins(biomes::DataBiomesPlugin)
+            .add_plugins(imported::ImportedTerrainPlugin);
+
+        Arc::make_mut(&mut TERRAIN_GENERATOR.write().unwrap())
             .register_biome_generator(
-                0.0f32,
+                Climate::new(0.0, 0.1, 0.0),
                 biomes::BasicPlainsBiomeTerrainGenerator.into_boxed_generator(),
             )
             .register_biome_generator(
-                0.8f32,
+                Climate::new(0.45, -0.4, -0.1),
                 biomes::BasicDesertBiomeTerrainGenerator.into_boxed_generator(),
             )
             .register_biome_generator(
-                3.21,
+                Climate::new(-0.45, 0.0, 0.25),
                 biomes::BasicSnowyPlainsBiomeTerrainGenerator.into_boxed_generator(),
+            )
+            .register_ore(OreDistribution::new(
+                CoalOre::into_voxel(),
+                8..160,
+                2.5,
+                6.0,
+            ))
+            .register_ore(OreDistribution::new(
+                IronOre::into_voxel(),
+                4..112,
+                2.0,
+                3.0,
+            ))
+            .register_ore(
+                OreDistribution::new(GoldOre::into_voxel(), 4..64, 1.5, 1.5).in_biomes(&["desert"]),
             );
     }
 }
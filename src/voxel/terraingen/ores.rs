@@ -0,0 +1,215 @@
+use std::ops::Range;
+
+use bevy::math::{IVec3, Vec3};
+use ilattice::glam::UVec3;
+
+use crate::voxel::{
+    material::VoxelMaterial, materials::Rock, storage::VoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH,
+};
+
+use super::WorldSeed;
+
+/// The number of blobs making up a vein, each blob being offset from the previous one along the vein.
+const VEIN_BLOBS: u32 = 3;
+
+/// Describes how an ore gets scattered in veins through the rock of the world.
+#[derive(Clone, Debug)]
+pub struct OreDistribution {
+    /// The voxel the veins are made of.
+    pub ore: Voxel,
+    /// The world heights (in voxels) the veins start at.
+    pub heights: Range<i32>,
+    /// The radius (in voxels) of the blobs making up a vein.
+    pub vein_size: f32,
+    /// The average number of veins per chunk-sized region of the world.
+    pub frequency: f32,
+    /// The names of the biomes the ore spawns in, or every biome if empty.
+    pub biomes: Vec<&'static str>,
+}
+
+impl OreDistribution {
+    pub fn new(ore: Voxel, heights: Range<i32>, vein_size: f32, frequency: f32) -> Self {
+        Self {
+            ore,
+            heights,
+            vein_size,
+            frequency,
+            biomes: Vec::new(),
+        }
+    }
+
+    /// Restricts the ore to the specified biomes.
+    pub fn in_biomes(mut self, biomes: &[&'static str]) -> Self {
+        self.biomes.extend_from_slice(biomes);
+        self
+    }
+
+    /// The farthest distance (in voxels) a vein reaches from its starting point.
+    pub fn vein_reach(&self) -> f32 {
+        self.vein_size * VEIN_BLOBS as f32
+    }
+
+    /// Places the veins of this ore reaching into a chunk, replacing the rock voxels of the chunk.
+    /// The veins are generated per chunk-sized cell of the world and may straddle the neighbouring chunks,
+    /// the biome of a vein is the one of the cell it starts in so it is the same from any chunk it reaches.
+    pub fn place_veins<'a>(
+        &self,
+        chunk_key: IVec3,
+        seed: WorldSeed,
+        biome_at: impl Fn(IVec3) -> &'a str,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let cell = chunk_key.div_euclid(IVec3::splat(CHUNK_LENGTH as i32));
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let cell = cell + IVec3::new(x, y, z);
+                    let cell_key = cell * CHUNK_LENGTH as i32;
+
+                    if !self.biomes.is_empty()
+                        && !self.biomes.iter().any(|biome| *biome == biome_at(cell_key))
+                    {
+                        continue;
+                    }
+
+                    for (center, radius) in self.cell_veins(cell, seed) {
+                        self.place_blob(chunk_key, center, radius, buffer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the blobs (center and radius) of the veins starting in a chunk-sized cell of the world.
+    fn cell_veins(&self, cell: IVec3, seed: WorldSeed) -> Vec<(Vec3, f32)> {
+        let mut rng = VeinRng(
+            seed.derive(self.ore.0 as u64)
+                .derive(cell.x as u32 as u64)
+                .derive(cell.y as u32 as u64)
+                .derive(cell.z as u32 as u64),
+        );
+
+        let count = self.frequency.floor() as u32 + (rng.next() < self.frequency.fract()) as u32;
+
+        (0..count)
+            .flat_map(|_| {
+                let origin =
+                    (cell * CHUNK_LENGTH as i32).as_vec3() + rng.next_vec3() * CHUNK_LENGTH as f32;
+                let direction = (rng.next_vec3() * 2.0 - 1.0).normalize_or_zero();
+                let radii =
+                    [(); VEIN_BLOBS as usize].map(|_| self.vein_size * (0.6 + rng.next() * 0.4));
+
+                let spawns = self.heights.contains(&(origin.y.floor() as i32));
+                (0..VEIN_BLOBS).filter(move |_| spawns).map(move |i| {
+                    (
+                        origin + direction * self.vein_size * i as f32,
+                        radii[i as usize],
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn place_blob(
+        &self,
+        chunk_key: IVec3,
+        center: Vec3,
+        radius: f32,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let local = center - chunk_key.as_vec3();
+        let min = (local - radius).floor().as_ivec3().max(IVec3::ZERO);
+        let max = (local + radius)
+            .ceil()
+            .as_ivec3()
+            .min(IVec3::splat(CHUNK_LENGTH as i32 - 1));
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let pos = IVec3::new(x, y, z);
+                    if (pos.as_vec3() + 0.5 - local).length_squared() > radius * radius {
+                        continue;
+                    }
+
+                    let voxel = buffer.voxel_at_mut(UVec3::from_array(pos.as_uvec3().to_array()));
+                    if *voxel == Rock::into_voxel() {
+                        *voxel = self.ore;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A tiny deterministic random number generator, so a vein comes out the same from every chunk it reaches.
+struct VeinRng(WorldSeed);
+
+impl VeinRng {
+    /// Returns a random number in the `[0; 1)` range.
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.derive(1);
+        (self.0 .0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn next_vec3(&mut self) -> Vec3 {
+        Vec3::new(self.next(), self.next(), self.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::materials::IronOre;
+
+    #[test]
+    fn veins_agree_across_chunk_borders() {
+        let ore = OreDistribution::new(IronOre::into_voxel(), 0..64, 3.0, 12.0);
+        let seed = WorldSeed(42);
+        let keys = [IVec3::ZERO, IVec3::new(CHUNK_LENGTH as i32, 0, 0)];
+        let chunks = keys.map(|key| {
+            let mut buffer =
+                VoxelBuffer::<Voxel, ChunkShape>::new(ChunkShape {}, Rock::into_voxel());
+            ore.place_veins(key, seed, |_| "plains", &mut buffer);
+            buffer
+        });
+
+        // the blobs of the veins around both chunks, as seen from the world rather than from a chunk.
+        let blobs: Vec<(Vec3, f32)> = (-1..=2)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .flat_map(|cell| ore.cell_veins(cell, seed))
+            .collect();
+        let is_ore = |pos: IVec3| {
+            blobs.iter().any(|(center, radius)| {
+                (pos.as_vec3() + 0.5 - *center).length_squared() <= radius * radius
+            })
+        };
+
+        assert!(
+            blobs.iter().any(|(center, radius)| {
+                (center.x - CHUNK_LENGTH as f32).abs() < *radius
+                    && center.y > 0.0
+                    && center.z > 0.0
+                    && center.y < CHUNK_LENGTH as f32
+                    && center.z < CHUNK_LENGTH as f32
+            }),
+            "no vein straddles the border between the chunks"
+        );
+
+        for (key, chunk) in keys.iter().zip(&chunks) {
+            for x in 0..CHUNK_LENGTH {
+                for y in 0..CHUNK_LENGTH {
+                    for z in 0..CHUNK_LENGTH {
+                        let pos = *key + IVec3::new(x as i32, y as i32, z as i32);
+                        assert_eq!(
+                            chunk.voxel_at(UVec3::new(x, y, z)) == ore.ore,
+                            is_ore(pos),
+                            "the chunks disagree about the ore at {pos}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
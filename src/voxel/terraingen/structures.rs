@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use bevy::math::{IVec2, IVec3, UVec3, Vec2, Vec3Swizzles};
+use serde::Deserialize;
+
+use crate::voxel::{storage::VoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH};
+
+use super::WorldSeed;
+
+/// A hand-built structure (e.g. a ruin or a well) made of voxels, placed in the world by the biomes.
+#[derive(Clone, Debug)]
+pub struct StructureTemplate {
+    /// The name of the template, every template placed in a world needs its own name.
+    name: String,
+    size: UVec3,
+    /// The position in the template of the voxel resting right above the surface of the terrain.
+    anchor: IVec3,
+    /// The voxels of the template laid out along the x axis, then the z axis, then the y axis.
+    /// The voxels outside the replace mask are `None` and keep the voxels of the world.
+    voxels: Vec<Option<Voxel>>,
+}
+
+impl StructureTemplate {
+    pub fn new(name: String, size: UVec3, anchor: IVec3, voxels: Vec<Option<Voxel>>) -> Self {
+        assert_eq!(
+            voxels.len(),
+            (size.x * size.y * size.z) as usize,
+            "the voxels of a structure template must fill its size"
+        );
+        Self {
+            name,
+            size,
+            anchor,
+            voxels,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The farthest horizontal distance (in voxels) a voxel of the template lies from its anchor, whatever its rotation.
+    pub fn reach(&self) -> i32 {
+        let min = -self.anchor.xz();
+        let max = self.size.as_ivec3().xz() - 1 - self.anchor.xz();
+        min.abs().max(max.abs()).max_element()
+    }
+
+    /// Returns the horizontal corners of the template relative to its anchor.
+    fn corners(&self) -> [IVec2; 4] {
+        let min = -self.anchor.xz();
+        let max = self.size.as_ivec3().xz() - 1 - self.anchor.xz();
+        [min, IVec2::new(max.x, min.y), IVec2::new(min.x, max.y), max]
+    }
+
+    /// Returns the voxels in the replace mask of the template, positioned relative to its anchor.
+    fn masked_voxels(&self) -> impl Iterator<Item = (IVec3, Voxel)> + '_ {
+        let size = self.size.as_ivec3();
+        self.voxels
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, voxel)| {
+                let index = index as i32;
+                let pos = IVec3::new(
+                    index % size.x,
+                    index / (size.x * size.z),
+                    index / size.x % size.z,
+                );
+                voxel.map(|voxel| (pos - self.anchor, voxel))
+            })
+    }
+
+    /// Places the part of the template reaching into a chunk, `origin` being the world position of its anchor.
+    fn place(
+        &self,
+        chunk_key: IVec3,
+        origin: IVec3,
+        rotation: u32,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        for (pos, voxel) in self.masked_voxels() {
+            let local = origin + rotate(pos, rotation) - chunk_key;
+
+            if local.cmpge(IVec3::ZERO).all()
+                && local.cmplt(IVec3::splat(CHUNK_LENGTH as i32)).all()
+            {
+                *buffer.voxel_at_mut(local.as_uvec3().to_array().into()) = voxel;
+            }
+        }
+    }
+}
+
+/// Rotates a position by quarter turns around the y axis.
+fn rotate(pos: IVec3, rotation: u32) -> IVec3 {
+    match rotation % 4 {
+        0 => pos,
+        1 => IVec3::new(-pos.z, pos.y, pos.x),
+        2 => IVec3::new(-pos.x, pos.y, -pos.z),
+        _ => IVec3::new(pos.z, pos.y, -pos.x),
+    }
+}
+
+/// How a structure gets aligned with the surface of the terrain it is placed on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum SurfaceAlignment {
+    /// The anchor rests on the surface of its column.
+    #[default]
+    Anchor,
+    /// The anchor rests on the lowest surface under the corners of the structure, so it doesn't float above slopes.
+    Lowest,
+    /// The anchor rests on the highest surface under the corners of the structure, so it doesn't get buried in slopes.
+    Highest,
+}
+
+/// Describes how a structure gets scattered over the world.
+#[derive(Clone, Debug)]
+pub struct StructurePlacement {
+    pub template: Arc<StructureTemplate>,
+    /// The size (in voxels) of the cells of the world holding at most one structure each.
+    /// The structures are kept in the middle of their cells so they lie at least half the spacing apart.
+    pub spacing: u32,
+    /// The chance of a cell to hold the structure, from 0 to 1.
+    pub chance: f32,
+    pub alignment: SurfaceAlignment,
+    /// How far (in voxels) the structure rises above its aligned position, negative values sinking it into the ground.
+    pub offset: i32,
+    /// Whether the structure gets randomly rotated by quarter turns.
+    pub rotate: bool,
+    /// The names of the biomes the structure spawns in, or every biome if empty.
+    pub biomes: Vec<String>,
+}
+
+impl StructurePlacement {
+    pub fn new(template: Arc<StructureTemplate>, spacing: u32, chance: f32) -> Self {
+        assert!(spacing > 0, "structures need a non zero spacing");
+        Self {
+            template,
+            spacing,
+            chance,
+            alignment: SurfaceAlignment::default(),
+            offset: 0,
+            rotate: false,
+            biomes: Vec::new(),
+        }
+    }
+
+    /// Places the structures reaching into a chunk.
+    /// The structures are generated per cell of the world and may straddle the neighbouring chunks,
+    /// their biome and position only depend on the columns they stand on so they are the same from any chunk they reach.
+    /// `surface_at` returns the surface height of a world column, or `None` if the column is under water.
+    pub fn place_structures<'a>(
+        &self,
+        chunk_key: IVec3,
+        seed: WorldSeed,
+        biome_at: impl Fn(IVec3) -> &'a str,
+        surface_at: impl Fn(IVec2) -> Option<i32>,
+        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let spacing = self.spacing as i32;
+        let reach = self.template.reach();
+        let chunk_min = chunk_key.xz() - reach;
+        let chunk_max = chunk_key.xz() + CHUNK_LENGTH as i32 - 1 + reach;
+        let salt = template_salt(self.template.name());
+
+        for cell_x in chunk_min.x.div_euclid(spacing)..=chunk_max.x.div_euclid(spacing) {
+            for cell_z in chunk_min.y.div_euclid(spacing)..=chunk_max.y.div_euclid(spacing) {
+                let mut seed = seed
+                    .derive(salt)
+                    .derive(cell_x as u32 as u64)
+                    .derive(cell_z as u32 as u64);
+                let mut next = || {
+                    seed = seed.derive(1);
+                    (seed.0 >> 40) as f32 / (1u64 << 24) as f32
+                };
+
+                let spawns = next() < self.chance;
+                let column = IVec2::new(cell_x, cell_z) * spacing
+                    + ((Vec2::new(next(), next()) * 0.5 + 0.25) * spacing as f32).as_ivec2();
+                let rotation = if self.rotate {
+                    (next() * 4.0) as u32
+                } else {
+                    0
+                };
+
+                if !spawns
+                    || column.cmplt(chunk_min).any()
+                    || column.cmpgt(chunk_max).any()
+                    || !self.spawns_in(biome_at(column.extend(0).xzy()))
+                {
+                    continue;
+                }
+
+                if let Some(surface) = self.aligned_surface(column, rotation, &surface_at) {
+                    let origin = IVec3::new(column.x, surface + self.offset, column.y);
+                    self.template.place(chunk_key, origin, rotation, buffer);
+                }
+            }
+        }
+    }
+
+    fn spawns_in(&self, biome: &str) -> bool {
+        self.biomes.is_empty() || self.biomes.iter().any(|other| other == biome)
+    }
+
+    /// Returns the height the anchor of a structure standing on a column rests at, or `None` if the structure would
+    /// stand in the water.
+    fn aligned_surface(
+        &self,
+        column: IVec2,
+        rotation: u32,
+        surface_at: impl Fn(IVec2) -> Option<i32>,
+    ) -> Option<i32> {
+        let anchor = surface_at(column)?;
+        let mut corners = self
+            .template
+            .corners()
+            .into_iter()
+            .map(|corner| surface_at(column + rotate(corner.extend(0).xzy(), rotation).xz()));
+
+        match self.alignment {
+            SurfaceAlignment::Anchor => Some(anchor),
+            SurfaceAlignment::Lowest => {
+                corners.try_fold(anchor, |lowest, surface| Some(lowest.min(surface?)))
+            }
+            SurfaceAlignment::Highest => {
+                corners.try_fold(anchor, |highest, surface| Some(highest.max(surface?)))
+            }
+        }
+    }
+}
+
+/// Hashes the name of a template (FNV-1a) so every template gets scattered differently over the world.
+fn template_salt(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_turns_a_quarter_around_the_y_axis() {
+        let pos = IVec3::new(1, 2, 3);
+        assert_eq!(rotate(pos, 0), pos);
+        assert_eq!(rotate(pos, 1), IVec3::new(-3, 2, 1));
+        assert_eq!(rotate(pos, 2), IVec3::new(-1, 2, -3));
+        assert_eq!(rotate(pos, 3), IVec3::new(3, 2, -1));
+
+        for rotation in 0..4 {
+            assert_eq!(rotate(rotate(pos, rotation), 1), rotate(pos, rotation + 1));
+            assert_eq!(rotate(rotate(pos, rotation), 4 - rotation), pos);
+        }
+    }
+
+    #[test]
+    fn masked_voxels_are_laid_out_along_x_then_z_then_y() {
+        let size = UVec3::new(2, 3, 4);
+        let voxels = (0..size.x * size.y * size.z)
+            .map(|index| (index != 5).then_some(Voxel(index as u16)))
+            .collect();
+        let template =
+            StructureTemplate::new("test".to_string(), size, IVec3::new(1, 0, 2), voxels);
+
+        let masked: Vec<_> = template.masked_voxels().collect();
+        assert_eq!(masked.len(), 23);
+        assert!(masked.iter().all(|(_, voxel)| voxel.0 != 5));
+        assert_eq!(masked[0], (IVec3::new(-1, 0, -2), Voxel(0)));
+        assert_eq!(masked[1], (IVec3::new(0, 0, -2), Voxel(1)));
+        assert_eq!(masked[2], (IVec3::new(-1, 0, -1), Voxel(2)));
+        // the first voxel of the second layer follows the 2 x 4 voxels of the first one, minus the masked voxel.
+        assert_eq!(masked[7], (IVec3::new(-1, 1, -2), Voxel(8)));
+        assert_eq!(masked[22], (IVec3::new(0, 2, 1), Voxel(23)));
+    }
+}
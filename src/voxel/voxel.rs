@@ -1,7 +1,7 @@
 use block_mesh::{MergeVoxel, Voxel as MeshableVoxel};
 
 #[derive(Copy, Clone, Hash, Debug, PartialEq, Eq)]
-pub struct Voxel(pub u8);
+pub struct Voxel(pub u16);
 
 impl Voxel {
     pub const EMPTY_VOXEL: Self = Self(0);
@@ -24,20 +24,10 @@ impl MeshableVoxel for Voxel {
 }
 
 impl MergeVoxel for Voxel {
-    type MergeValue = u8;
+    type MergeValue = u16;
 
     #[inline]
     fn merge_value(&self) -> Self::MergeValue {
         self.0
     }
 }
-
-pub trait MaterialVoxel: MergeVoxel + MeshableVoxel {
-    fn as_mat_id(&self) -> u8;
-}
-
-impl MaterialVoxel for Voxel {
-    fn as_mat_id(&self) -> u8 {
-        self.0
-    }
-}
@@ -1,8 +1,8 @@
 use bevy::{
     math::IVec3,
     prelude::{
-        Changed, Commands, Entity, GlobalTransform, IntoSystemConfigs, Last, Plugin, PostUpdate,
-        Query, Res, ResMut, Resource, SystemSet, Update, With,
+        Changed, Commands, DespawnRecursiveExt, Entity, GlobalTransform, IntoSystemConfigs, Last,
+        Plugin, PostUpdate, Query, Res, ResMut, Resource, SystemSet, Update, With,
     },
     utils::{HashMap, HashSet},
 };
@@ -10,7 +10,7 @@ use float_ord::FloatOrd;
 
 use super::{player::PlayerController, Chunk, ChunkShape, CHUNK_LENGTH};
 use crate::voxel::storage::ChunkMap;
-use crate::voxel::Voxel;
+use crate::voxel::{Voxel, VoxelLight};
 
 /// Updates the current chunk position for the current player.
 fn update_player_pos(
@@ -97,13 +97,16 @@ fn create_chunks(
 fn destroy_chunks(
     mut chunks_command_queue: ResMut<ChunkCommandQueue>,
     mut chunks: ResMut<ChunkMap<Voxel, ChunkShape>>,
+    mut lights: ResMut<ChunkMap<VoxelLight, ChunkShape>>,
     mut chunk_entities: ResMut<ChunkEntities>,
     mut cmds: Commands,
 ) {
     for command in chunks_command_queue.destroy.drain(..) {
+        // the liquid mesh of the chunk is a child entity.
         cmds.entity(chunk_entities.detach_entity(command).unwrap())
-            .despawn();
+            .despawn_recursive();
         chunks.remove(command);
+        lights.remove(command);
     }
 }
 
@@ -1,7 +1,7 @@
 use bevy::{
     prelude::{
         Commands, Component, Entity, IntoSystemConfigs, IntoSystemSetConfigs, Plugin, PostUpdate,
-        Query, RemovedComponents, Res, SystemSet, Transform, Update, Visibility,
+        Query, RemovedComponents, Res, SystemSet, Transform, Update, Visibility, Without,
     },
     time::Time,
 };
@@ -19,17 +19,24 @@ pub struct ChunkSpawnAnimation {
     start_time: f32,
 }
 
+/// Marks the chunks which already appeared, so remeshing them (e.g. after an edit) doesn't animate them again.
+#[derive(Component)]
+pub struct ChunkAppeared;
+
 fn attach_chunk_animation(
-    mut ready_chunks: Query<(&mut Transform, &mut Visibility, &Chunk)>,
+    mut ready_chunks: Query<(&mut Transform, &mut Visibility, &Chunk), Without<ChunkAppeared>>,
     mut removed_chunk_meshes: RemovedComponents<ChunkMeshingTask>,
     time: Res<Time>,
     mut commands: Commands,
 ) {
     removed_chunk_meshes.read().for_each(|entity| {
         if ready_chunks.contains(entity) {
-            commands.entity(entity).insert(ChunkSpawnAnimation {
-                start_time: time.elapsed_seconds(),
-            });
+            commands.entity(entity).insert((
+                ChunkSpawnAnimation {
+                    start_time: time.elapsed_seconds(),
+                },
+                ChunkAppeared,
+            ));
             if let Ok((mut transform, mut visibility, chunk)) = ready_chunks.get_mut(entity) {
                 *visibility = Visibility::Visible;
                 transform.translation.y = chunk.0.y as f32 - ANIMATION_HEIGHT;
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    math::IVec3,
+    prelude::{
+        Component, IntoSystemConfigs, Plugin, Query, Res, Resource, SystemSet, Update, Visibility,
+    },
+    utils::HashSet,
+};
+use block_mesh::{Voxel as MeshableVoxel, VoxelVisibility};
+use ndshape::ConstShape;
+
+use super::{
+    chunks::{ChunkEntities, ChunkLoadRadius, CurrentLocalPlayerChunk},
+    chunks_anim::ChunkAppearanceAnimatorSet,
+    meshing::ChunkMeshingSet,
+    Chunk, ChunkShape, CHUNK_LENGTH,
+};
+use crate::voxel::{
+    material::{VoxelMeshingMode, VoxelShape},
+    render::MeshingMaterials,
+    storage::VoxelBuffer,
+    Voxel,
+};
+
+/// The offsets to the neighbouring chunks for the faces in the `RIGHT_HANDED_Y_UP_CONFIG` order (-X, -Y, -Z, +X, +Y, +Z).
+const FACE_OFFSETS: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::NEG_Y,
+    IVec3::NEG_Z,
+    IVec3::X,
+    IVec3::Y,
+    IVec3::Z,
+];
+
+/// Returns the face on the other side of a chunk.
+#[inline]
+const fn opposite_face(face: usize) -> usize {
+    (face + 3) % 6
+}
+
+/// Which faces of a chunk can be seen from one another through the empty space of the chunk.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkFaceConnectivity([u8; 6]);
+
+impl ChunkFaceConnectivity {
+    /// Every face sees every other face, as for an empty chunk.
+    pub const ALL: Self = Self([0b111111; 6]);
+
+    /// Computes the connectivity of the chunk faces by flood filling the voxels which don't hide what's behind them.
+    pub fn from_voxels(
+        voxels: &VoxelBuffer<Voxel, ChunkShape>,
+        materials: &MeshingMaterials,
+    ) -> Self {
+        let occludes = |voxel: Voxel| {
+            let info = materials.get(voxel.0);
+            voxel.get_visibility() == VoxelVisibility::Opaque
+                && info.mode == VoxelMeshingMode::Blocky
+                && info.shape == VoxelShape::Cube
+                && !info.liquid
+        };
+
+        let mut visited: Vec<bool> = voxels
+            .slice()
+            .iter()
+            .map(|voxel| occludes(*voxel))
+            .collect();
+        if visited.iter().all(|visited| !visited) {
+            return Self::ALL;
+        }
+
+        let mut connectivity = Self([0; 6]);
+        let mut stack = Vec::new();
+
+        for start in 0..ChunkShape::SIZE {
+            if visited[start as usize] {
+                continue;
+            }
+
+            visited[start as usize] = true;
+            stack.push(start);
+            let mut faces = 0u8;
+
+            while let Some(index) = stack.pop() {
+                let pos = ChunkShape::delinearize(index);
+
+                for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+                    let axis = face % 3;
+                    let neighbour = pos[axis] as i32 + offset[axis];
+
+                    if neighbour < 0 || neighbour >= CHUNK_LENGTH as i32 {
+                        faces |= 1 << face;
+                        continue;
+                    }
+
+                    let mut neighbour_pos = pos;
+                    neighbour_pos[axis] = neighbour as u32;
+                    let neighbour_index = ChunkShape::linearize(neighbour_pos);
+                    if !visited[neighbour_index as usize] {
+                        visited[neighbour_index as usize] = true;
+                        stack.push(neighbour_index);
+                    }
+                }
+            }
+
+            for face in 0..6 {
+                if faces & (1 << face) != 0 {
+                    connectivity.0[face] |= faces;
+                }
+            }
+        }
+
+        connectivity
+    }
+
+    /// Returns whether a face of the chunk can be seen from another one.
+    #[inline]
+    pub fn connects(&self, from: usize, to: usize) -> bool {
+        self.0[from] & (1 << to) != 0
+    }
+}
+
+/// Settings of the culling of the chunks hidden behind the terrain.
+#[derive(Resource)]
+pub struct ChunkCullingSettings {
+    pub enabled: bool,
+}
+
+impl Default for ChunkCullingSettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Walks the chunk face connectivity graphs from the camera chunk, never going back in a direction already taken,
+/// and returns the keys of the chunks which can be seen from the camera.
+fn visible_chunks(
+    origin: IVec3,
+    load_radius: &ChunkLoadRadius,
+    connectivity: impl Fn(IVec3) -> Option<ChunkFaceConnectivity>,
+) -> HashSet<IVec3> {
+    let chunk_length = CHUNK_LENGTH as i32;
+    let in_range = |key: IVec3| {
+        let offset = (key - origin) / chunk_length;
+        key.y >= 0
+            && offset.x.abs() <= load_radius.horizontal
+            && offset.z.abs() <= load_radius.horizontal
+            && offset.y.abs() <= load_radius.vertical
+    };
+
+    let mut visible = HashSet::from([origin]);
+    // the chunks to visit along with the face they are entered from and the directions taken to reach them.
+    let mut queue = VecDeque::from([(origin, None, 0u8)]);
+
+    while let Some((key, entry, directions)) = queue.pop_front() {
+        // chunks which aren't meshed yet don't hide anything.
+        let chunk_connectivity = connectivity(key).unwrap_or(ChunkFaceConnectivity::ALL);
+
+        for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+            if directions & (1 << opposite_face(face)) != 0 {
+                continue;
+            }
+
+            if entry.is_some_and(|entry| !chunk_connectivity.connects(entry, face)) {
+                continue;
+            }
+
+            let neighbour = key + *offset * chunk_length;
+            if in_range(neighbour) && visible.insert(neighbour) {
+                queue.push_back((neighbour, Some(opposite_face(face)), directions | 1 << face));
+            }
+        }
+    }
+
+    visible
+}
+
+/// Hides the meshed chunks which can't be seen from the camera chunk through the empty space of the chunks in between.
+fn cull_hidden_chunks(
+    settings: Res<ChunkCullingSettings>,
+    player_chunk: Res<CurrentLocalPlayerChunk>,
+    load_radius: Res<ChunkLoadRadius>,
+    chunk_entities: Res<ChunkEntities>,
+    mut chunks: Query<(&Chunk, &ChunkFaceConnectivity, &mut Visibility)>,
+) {
+    let visible = settings.enabled.then(|| {
+        visible_chunks(player_chunk.chunk_min, &load_radius, |key| {
+            chunk_entities
+                .entity(key)
+                .and_then(|entity| chunks.get(entity).ok())
+                .map(|(_, connectivity, _)| *connectivity)
+        })
+    });
+
+    for (chunk, _, mut visibility) in chunks.iter_mut() {
+        let target = match &visible {
+            Some(visible) if !visible.contains(&chunk.0) => Visibility::Hidden,
+            _ => Visibility::Visible,
+        };
+
+        if *visibility != target {
+            *visibility = target;
+        }
+    }
+}
+
+/// The set of systems culling the chunks hidden behind the terrain.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash, SystemSet)]
+pub struct ChunkCullingSet;
+
+/// Handles culling the chunks which can't be seen from the camera, such as the caves below the player.
+pub struct VoxelWorldCullingPlugin;
+
+impl Plugin for VoxelWorldCullingPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<ChunkCullingSettings>().add_systems(
+            Update,
+            cull_hidden_chunks
+                .in_set(ChunkCullingSet)
+                .after(ChunkMeshingSet)
+                .after(ChunkAppearanceAnimatorSet),
+        );
+    }
+}
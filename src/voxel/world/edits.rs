@@ -0,0 +1,96 @@
+use bevy::{
+    math::IVec3,
+    prelude::{Event, EventWriter, IntoSystemConfigs, Plugin, ResMut, Resource, SystemSet, Update},
+};
+
+use super::{
+    chunks::{ChunkLoadingSet, DirtyChunks},
+    terrain::TerrainGenSet,
+    ChunkShape, CHUNK_LENGTH,
+};
+use crate::voxel::{storage::ChunkMap, Voxel};
+
+/// A queue of voxel edits to apply to the loaded world.
+#[derive(Default, Resource)]
+pub struct VoxelEditQueue(Vec<(IVec3, Voxel)>);
+
+#[allow(dead_code)]
+impl VoxelEditQueue {
+    /// Queues setting the voxel at the specified world position.
+    pub fn set_voxel(&mut self, pos: IVec3, voxel: Voxel) {
+        self.0.push((pos, voxel));
+    }
+}
+
+/// Sent for every voxel changed by an edit of the [`VoxelEditQueue`].
+#[derive(Event, Clone, Copy, Debug)]
+pub struct VoxelEdited {
+    pub pos: IVec3,
+    pub previous: Voxel,
+    pub voxel: Voxel,
+}
+
+/// Returns the keys of the chunks whose mesh depends on the voxel at the specified position,
+/// which are its own chunk and the neighbouring chunks it borders.
+pub fn chunks_touching_voxel(pos: IVec3) -> impl Iterator<Item = IVec3> {
+    let chunk_mask = !IVec3::splat((CHUNK_LENGTH - 1) as i32);
+    let chunk = pos & chunk_mask;
+    let local = pos - chunk;
+
+    std::iter::once(chunk).chain((0..3).flat_map(move |axis| {
+        let mut offset = IVec3::ZERO;
+        offset[axis] = match local[axis] {
+            0 => -(CHUNK_LENGTH as i32),
+            x if x == CHUNK_LENGTH as i32 - 1 => CHUNK_LENGTH as i32,
+            _ => 0,
+        };
+        (offset != IVec3::ZERO).then_some(chunk + offset)
+    }))
+}
+
+/// Applies the queued voxel edits to the loaded chunks. Edits targeting chunks which aren't loaded are dropped.
+fn apply_voxel_edits(
+    mut edits: ResMut<VoxelEditQueue>,
+    mut chunks: ResMut<ChunkMap<Voxel, ChunkShape>>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut edited: EventWriter<VoxelEdited>,
+) {
+    for (pos, voxel) in edits.0.drain(..) {
+        let Some(current) = chunks.voxel_at_mut(pos) else {
+            continue;
+        };
+
+        if *current == voxel {
+            continue;
+        }
+
+        let previous = std::mem::replace(current, voxel);
+        chunks_touching_voxel(pos).for_each(|chunk| dirty_chunks.mark_dirty(chunk));
+        edited.send(VoxelEdited {
+            pos,
+            previous,
+            voxel,
+        });
+    }
+}
+
+/// Systems applying the voxel edits.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash, SystemSet)]
+pub struct VoxelEditSet;
+
+/// Handles editing the voxels of the loaded world.
+pub struct VoxelWorldEditsPlugin;
+
+impl Plugin for VoxelWorldEditsPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<VoxelEditQueue>()
+            .add_event::<VoxelEdited>()
+            .add_systems(
+                Update,
+                apply_voxel_edits
+                    .in_set(VoxelEditSet)
+                    .after(ChunkLoadingSet)
+                    .after(TerrainGenSet),
+            );
+    }
+}
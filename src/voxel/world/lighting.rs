@@ -0,0 +1,724 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    math::IVec3,
+    prelude::{
+        EventReader, IntoSystemConfigs, IntoSystemSetConfigs, Plugin, Res, ResMut, SystemSet,
+        Update,
+    },
+    utils::HashSet,
+};
+use ndshape::ConstShape;
+
+use super::{
+    chunks::{ChunkEntities, DirtyChunks},
+    edits::{chunks_touching_voxel, VoxelEditSet, VoxelEdited},
+    meshing::ChunkMeshingSet,
+    terrain::{TerrainGenSet, MAX_TERRAIN_CHUNK_Y},
+    ChunkShape, CHUNK_LENGTH,
+};
+use crate::voxel::{
+    material::{VoxelMaterialFlags, VoxelMaterialRegistry, VoxelShape},
+    storage::{ChunkMap, VoxelBuffer},
+    Voxel, VoxelLight,
+};
+
+pub(super) const NEIGHBOUR_OFFSETS: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::NEG_Y,
+    IVec3::NEG_Z,
+    IVec3::X,
+    IVec3::Y,
+    IVec3::Z,
+];
+
+/// The lighting related properties of a voxel material.
+#[derive(Clone, Copy, Default, PartialEq)]
+struct LightingMaterial {
+    /// The block light emitted by the voxels of this material.
+    emission: VoxelLight,
+    /// Whether light can travel through the voxels of this material.
+    transparent: bool,
+}
+
+fn lighting_materials(registry: &VoxelMaterialRegistry) -> Vec<LightingMaterial> {
+    registry
+        .iter_mats()
+        .enumerate()
+        .map(|(id, material)| {
+            let emissive = material.emissive.to_srgba();
+            LightingMaterial {
+                emission: VoxelLight::from_block_light(
+                    [emissive.red, emissive.green, emissive.blue]
+                        .map(|x| (x.clamp(0.0, 1.0) * VoxelLight::MAX_LEVEL as f32).round() as u8),
+                ),
+                transparent: id == Voxel::EMPTY_VOXEL.0 as usize
+                    || material.shape != VoxelShape::Cube
+                    || material.flags.contains(VoxelMaterialFlags::LIQUID),
+            }
+        })
+        .collect()
+}
+
+/// Returns the light a voxel spreads to its transparent neighbour in the direction of `offset`,
+/// or `None` if that wouldn't make the neighbour brighter.
+fn spread_light(light: VoxelLight, neighbour: VoxelLight, offset: IVec3) -> Option<VoxelLight> {
+    let mut spread = neighbour;
+    for channel in 0..VoxelLight::CHANNELS {
+        let level = light.level(channel);
+        // full sunlight goes straight down without getting dimmer.
+        let spread_level = if channel == VoxelLight::SKY_CHANNEL
+            && offset == IVec3::NEG_Y
+            && level == VoxelLight::MAX_LEVEL
+        {
+            level
+        } else {
+            level.saturating_sub(1)
+        };
+
+        if spread_level > neighbour.level(channel) {
+            spread.set_level(channel, spread_level);
+        }
+    }
+
+    (spread != neighbour).then_some(spread)
+}
+
+/// Returns whether the light level of a neighbour in the direction of `offset` could have come from the removed light level.
+fn is_lit_by(level: u8, neighbour_level: u8, channel: usize, offset: IVec3) -> bool {
+    neighbour_level != 0
+        && (neighbour_level < level
+            || (channel == VoxelLight::SKY_CHANNEL
+                && offset == IVec3::NEG_Y
+                && level == VoxelLight::MAX_LEVEL
+                && neighbour_level == VoxelLight::MAX_LEVEL))
+}
+
+/// Computes the light of a chunk on its own, from its emissive voxels and the sky if nothing is above it.
+/// Light coming from the neighbouring chunks is spread afterwards by [`LightPropagation::link_chunk`].
+fn light_chunk(
+    voxels: &VoxelBuffer<Voxel, ChunkShape>,
+    materials: &[LightingMaterial],
+    open_sky: bool,
+) -> VoxelBuffer<VoxelLight, ChunkShape> {
+    let material = |voxel: &Voxel| materials.get(voxel.0 as usize).copied().unwrap_or_default();
+    let transparent: Vec<bool> = voxels
+        .slice()
+        .iter()
+        .map(|voxel| material(voxel).transparent)
+        .collect();
+
+    let mut lights = VoxelBuffer::<VoxelLight, ChunkShape>::new_empty(ChunkShape {});
+    let mut queue = VecDeque::new();
+
+    for (index, voxel) in voxels.slice().iter().enumerate() {
+        let emission = material(voxel).emission;
+        if emission != VoxelLight::NONE {
+            lights.slice_mut()[index] = emission;
+            queue.push_back(index as u32);
+        }
+    }
+
+    // sunlight shines down the columns until it hits an opaque voxel.
+    if open_sky {
+        for x in 0..CHUNK_LENGTH {
+            for z in 0..CHUNK_LENGTH {
+                for y in (0..CHUNK_LENGTH).rev() {
+                    let index = ChunkShape::linearize([x, y, z]);
+                    if !transparent[index as usize] {
+                        break;
+                    }
+                    lights.slice_mut()[index as usize]
+                        .set_level(VoxelLight::SKY_CHANNEL, VoxelLight::MAX_LEVEL);
+                    queue.push_back(index);
+                }
+            }
+        }
+    }
+
+    while let Some(index) = queue.pop_front() {
+        let pos = IVec3::from_array(ChunkShape::delinearize(index).map(|x| x as i32));
+        let light = lights.slice()[index as usize];
+
+        for offset in NEIGHBOUR_OFFSETS {
+            let neighbour = pos + offset;
+            if neighbour.cmplt(IVec3::ZERO).any()
+                || neighbour.cmpge(IVec3::splat(CHUNK_LENGTH as i32)).any()
+            {
+                continue;
+            }
+
+            let neighbour_index = ChunkShape::linearize(neighbour.as_uvec3().to_array());
+            if !transparent[neighbour_index as usize] {
+                continue;
+            }
+
+            if let Some(spread) =
+                spread_light(light, lights.slice()[neighbour_index as usize], offset)
+            {
+                lights.slice_mut()[neighbour_index as usize] = spread;
+                queue.push_back(neighbour_index);
+            }
+        }
+    }
+
+    lights
+}
+
+/// Incrementally updates the light of the loaded chunks by flood filling it through the chunk borders.
+struct LightPropagation<'a> {
+    voxels: &'a ChunkMap<Voxel, ChunkShape>,
+    lights: &'a mut ChunkMap<VoxelLight, ChunkShape>,
+    chunk_entities: &'a ChunkEntities,
+    materials: &'a [LightingMaterial],
+    /// Positions whose light needs to be spread to their neighbours.
+    add_queue: VecDeque<IVec3>,
+    /// Positions whose light got removed, along with the channel and previous level of the removed light.
+    remove_queue: VecDeque<(IVec3, usize, u8)>,
+    /// The chunks whose meshes need to be updated to account for the light changes.
+    changed_chunks: HashSet<IVec3>,
+}
+
+impl<'a> LightPropagation<'a> {
+    fn new(
+        voxels: &'a ChunkMap<Voxel, ChunkShape>,
+        lights: &'a mut ChunkMap<VoxelLight, ChunkShape>,
+        chunk_entities: &'a ChunkEntities,
+        materials: &'a [LightingMaterial],
+    ) -> Self {
+        Self {
+            voxels,
+            lights,
+            chunk_entities,
+            materials,
+            add_queue: VecDeque::new(),
+            remove_queue: VecDeque::new(),
+            changed_chunks: HashSet::default(),
+        }
+    }
+
+    fn material(&self, voxel: Voxel) -> LightingMaterial {
+        self.materials
+            .get(voxel.0 as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns whether light can travel through the voxel at the specified position.
+    /// Light doesn't travel into chunks which aren't loaded.
+    fn is_transparent(&self, pos: IVec3) -> bool {
+        self.voxels
+            .voxel_at(pos)
+            .is_some_and(|voxel| self.material(voxel).transparent)
+    }
+
+    fn emission(&self, pos: IVec3) -> VoxelLight {
+        self.voxels
+            .voxel_at(pos)
+            .map_or(VoxelLight::NONE, |voxel| self.material(voxel).emission)
+    }
+
+    /// Returns whether the chunk above the specified chunk is considered to be open sky: either it is above the generated terrain,
+    /// or it is out of the loaded area so it is assumed to be empty until it gets loaded.
+    fn is_open_sky_above(&self, key: IVec3) -> bool {
+        let above = key + IVec3::Y * CHUNK_LENGTH as i32;
+        !self.voxels.exists(above)
+            && (above.y >= MAX_TERRAIN_CHUNK_Y || self.chunk_entities.entity(above).is_none())
+    }
+
+    fn set_light(&mut self, pos: IVec3, light: VoxelLight) {
+        if let Some(current) = self.lights.voxel_at_mut(pos) {
+            *current = light;
+            self.changed_chunks.extend(chunks_touching_voxel(pos));
+        }
+    }
+
+    /// Starts spreading light from an emissive voxel.
+    fn add_emitter(&mut self, pos: IVec3) {
+        let emission = self.emission(pos);
+        if emission != VoxelLight::NONE {
+            let light = self.lights.voxel_at(pos).unwrap_or_default().max(emission);
+            self.set_light(pos, light);
+            self.add_queue.push_back(pos);
+        }
+    }
+
+    /// Removes a channel of the light of the voxel at the specified position.
+    fn remove_channel(&mut self, pos: IVec3, channel: usize) {
+        let Some(mut light) = self.lights.voxel_at(pos) else {
+            return;
+        };
+
+        let level = light.level(channel);
+        if level > 0 {
+            light.set_level(channel, 0);
+            self.set_light(pos, light);
+            self.remove_queue.push_back((pos, channel, level));
+        }
+    }
+
+    /// Queues the lit neighbours of a voxel to spread their light again (e.g. after it became transparent).
+    fn relight_from_neighbours(&mut self, pos: IVec3) {
+        for offset in NEIGHBOUR_OFFSETS {
+            if self
+                .lights
+                .voxel_at(pos + offset)
+                .is_some_and(|light| light != VoxelLight::NONE)
+            {
+                self.add_queue.push_back(pos + offset);
+            }
+        }
+    }
+
+    /// Darkens the voxels lit by the removed lights, queueing the brighter voxels around for re-propagation.
+    fn propagate_removed(&mut self) {
+        while let Some((pos, channel, level)) = self.remove_queue.pop_front() {
+            for offset in NEIGHBOUR_OFFSETS {
+                let neighbour = pos + offset;
+                let Some(mut light) = self.lights.voxel_at(neighbour) else {
+                    continue;
+                };
+
+                let neighbour_level = light.level(channel);
+                if is_lit_by(level, neighbour_level, channel, offset) {
+                    light.set_level(channel, 0);
+                    self.set_light(neighbour, light);
+                    self.remove_queue
+                        .push_back((neighbour, channel, neighbour_level));
+                    // emitters got darkened along with the other voxels and need to shine again.
+                    if self.emission(neighbour).level(channel) > 0 {
+                        self.add_emitter(neighbour);
+                    }
+                } else if neighbour_level >= level {
+                    self.add_queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Spreads the light of the queued voxels to their transparent neighbours.
+    fn propagate_added(&mut self) {
+        while let Some(pos) = self.add_queue.pop_front() {
+            let Some(light) = self.lights.voxel_at(pos) else {
+                continue;
+            };
+
+            for offset in NEIGHBOUR_OFFSETS {
+                let neighbour = pos + offset;
+                if !self.is_transparent(neighbour) {
+                    continue;
+                }
+
+                let Some(neighbour_light) = self.lights.voxel_at(neighbour) else {
+                    continue;
+                };
+
+                if let Some(spread) = spread_light(light, neighbour_light, offset) {
+                    self.set_light(neighbour, spread);
+                    self.add_queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Inserts the light of a newly loaded chunk and spreads the light across its borders with the loaded neighbours.
+    fn link_chunk(&mut self, key: IVec3, lights: VoxelBuffer<VoxelLight, ChunkShape>) {
+        self.lights.insert(key, lights);
+        self.changed_chunks.insert(key);
+
+        let length = CHUNK_LENGTH as i32;
+        for (index, offset) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+            let neighbour_key = key + *offset * length;
+            if !self.lights.exists(neighbour_key) {
+                continue;
+            }
+
+            // the layers of voxels touching each other on both sides of the border.
+            let axis = index % 3;
+            let (layer, neighbour_layer) = if offset[axis] < 0 {
+                (0, -1)
+            } else {
+                (length - 1, length)
+            };
+
+            for u in 0..length {
+                for v in 0..length {
+                    let mut pos = key;
+                    pos[(axis + 1) % 3] += u;
+                    pos[(axis + 2) % 3] += v;
+                    let mut neighbour = pos;
+                    pos[axis] += layer;
+                    neighbour[axis] += neighbour_layer;
+
+                    let light = self.lights.voxel_at(pos).unwrap_or_default();
+                    let neighbour_light = self.lights.voxel_at(neighbour).unwrap_or_default();
+
+                    // only the borders where light flows to the other side need to be propagated.
+                    if self.is_transparent(neighbour)
+                        && spread_light(light, neighbour_light, *offset).is_some()
+                    {
+                        self.add_queue.push_back(pos);
+                    }
+                    if self.is_transparent(pos)
+                        && spread_light(neighbour_light, light, -*offset).is_some()
+                    {
+                        self.add_queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+
+        self.propagate_added();
+
+        // the chunk below may have been lit assuming there was open sky above it.
+        let below = key - IVec3::Y * length;
+        if self.lights.exists(below) {
+            for x in 0..length {
+                for z in 0..length {
+                    let pos = key + IVec3::new(x, 0, z);
+                    let sky_light = self.lights.voxel_at(pos).unwrap_or_default().sky_light();
+                    let below_sky_light = self
+                        .lights
+                        .voxel_at(pos - IVec3::Y)
+                        .unwrap_or_default()
+                        .sky_light();
+
+                    if below_sky_light == VoxelLight::MAX_LEVEL && sky_light < below_sky_light {
+                        self.remove_channel(pos - IVec3::Y, VoxelLight::SKY_CHANNEL);
+                    }
+                }
+            }
+
+            self.propagate_removed();
+            self.propagate_added();
+        }
+    }
+
+    /// Updates the light around an edited voxel.
+    fn apply_edit(&mut self, pos: IVec3) {
+        for channel in 0..VoxelLight::CHANNELS {
+            self.remove_channel(pos, channel);
+        }
+        self.propagate_removed();
+
+        if self.is_transparent(pos) {
+            self.relight_from_neighbours(pos);
+
+            // voxels at the top of a chunk below open sky get direct sunlight.
+            let key = pos & self.voxels.shape_mask();
+            if pos.y == key.y + CHUNK_LENGTH as i32 - 1 && self.is_open_sky_above(key) {
+                let mut light = self.lights.voxel_at(pos).unwrap_or_default();
+                light.set_level(VoxelLight::SKY_CHANNEL, VoxelLight::MAX_LEVEL);
+                self.set_light(pos, light);
+                self.add_queue.push_back(pos);
+            }
+        }
+        self.add_emitter(pos);
+        self.propagate_added();
+    }
+}
+
+/// Keeps the block light and skylight of the loaded chunks up to date with chunk loads and voxel edits.
+fn update_light(
+    voxels: Res<ChunkMap<Voxel, ChunkShape>>,
+    mut lights: ResMut<ChunkMap<VoxelLight, ChunkShape>>,
+    chunk_entities: Res<ChunkEntities>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut edits: EventReader<VoxelEdited>,
+) {
+    let mut new_chunks: Vec<IVec3> = dirty_chunks
+        .iter_dirty()
+        .filter(|key| voxels.exists(**key) && !lights.exists(**key))
+        .copied()
+        .collect();
+
+    if new_chunks.is_empty() && edits.is_empty() {
+        return;
+    }
+
+    // linking the chunks from the top lets sunlight flow down through the chunks loaded on the same frame.
+    new_chunks.sort_unstable_by_key(|key| -key.y);
+
+    let materials = lighting_materials(&registry);
+    let mut propagation = LightPropagation::new(&voxels, &mut lights, &chunk_entities, &materials);
+
+    for key in new_chunks {
+        let open_sky = propagation.is_open_sky_above(key);
+        let chunk_lights = light_chunk(voxels.buffer_at(key).unwrap(), &materials, open_sky);
+        propagation.link_chunk(key, chunk_lights);
+    }
+
+    let lighting_material = |voxel: Voxel| materials.get(voxel.0 as usize).copied();
+    for edit in edits.read() {
+        // replacing a voxel by one with the same lighting properties doesn't change the light.
+        if lighting_material(edit.previous) != lighting_material(edit.voxel) {
+            propagation.apply_edit(edit.pos);
+        }
+    }
+
+    for chunk in propagation.changed_chunks {
+        dirty_chunks.mark_dirty(chunk);
+    }
+}
+
+/// Systems computing the light levels of the voxels.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash, SystemSet)]
+pub struct ChunkLightingSet;
+
+/// Handles the propagation of block light emitted by emissive materials and of the skylight.
+pub struct VoxelWorldLightingPlugin;
+
+impl Plugin for VoxelWorldLightingPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.insert_resource(ChunkMap::<VoxelLight, ChunkShape>::new(ChunkShape {}))
+            .configure_sets(
+                Update,
+                ChunkLightingSet
+                    .after(TerrainGenSet)
+                    .after(VoxelEditSet)
+                    .before(ChunkMeshingSet),
+            )
+            .add_systems(Update, update_light.in_set(ChunkLightingSet));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: Voxel = Voxel(1);
+    const LAMP: Voxel = Voxel(2);
+
+    /// Empty voxels, opaque stone and an opaque lamp glowing orange.
+    fn materials() -> Vec<LightingMaterial> {
+        vec![
+            LightingMaterial {
+                emission: VoxelLight::NONE,
+                transparent: true,
+            },
+            LightingMaterial {
+                emission: VoxelLight::NONE,
+                transparent: false,
+            },
+            LightingMaterial {
+                emission: VoxelLight::from_block_light([15, 10, 0]),
+                transparent: false,
+            },
+        ]
+    }
+
+    fn empty_chunk() -> VoxelBuffer<Voxel, ChunkShape> {
+        VoxelBuffer::new(ChunkShape {}, Voxel::EMPTY_VOXEL)
+    }
+
+    /// Lights and links the chunks the same way [`update_light`] does.
+    fn light_world(
+        chunks: Vec<(IVec3, VoxelBuffer<Voxel, ChunkShape>)>,
+    ) -> (
+        ChunkMap<Voxel, ChunkShape>,
+        ChunkMap<VoxelLight, ChunkShape>,
+    ) {
+        let materials = materials();
+        let mut voxels = ChunkMap::new(ChunkShape {});
+        let mut lights = ChunkMap::new(ChunkShape {});
+        let mut keys: Vec<IVec3> = chunks.iter().map(|(key, _)| *key).collect();
+        keys.sort_unstable_by_key(|key| -key.y);
+        for (key, buffer) in chunks {
+            voxels.insert(key, buffer);
+        }
+
+        let chunk_entities = ChunkEntities::default();
+        let mut propagation =
+            LightPropagation::new(&voxels, &mut lights, &chunk_entities, &materials);
+        for key in keys {
+            let open_sky = propagation.is_open_sky_above(key);
+            let chunk_lights = light_chunk(voxels.buffer_at(key).unwrap(), &materials, open_sky);
+            propagation.link_chunk(key, chunk_lights);
+        }
+
+        (voxels, lights)
+    }
+
+    fn edit(
+        voxels: &mut ChunkMap<Voxel, ChunkShape>,
+        lights: &mut ChunkMap<VoxelLight, ChunkShape>,
+        pos: IVec3,
+        voxel: Voxel,
+    ) {
+        *voxels.voxel_at_mut(pos).unwrap() = voxel;
+        let materials = materials();
+        let chunk_entities = ChunkEntities::default();
+        LightPropagation::new(voxels, lights, &chunk_entities, &materials).apply_edit(pos);
+    }
+
+    fn same_light(
+        lights: &ChunkMap<VoxelLight, ChunkShape>,
+        original: &ChunkMap<VoxelLight, ChunkShape>,
+        keys: &[IVec3],
+    ) -> bool {
+        keys.iter().all(|key| {
+            lights.buffer_at(*key).unwrap().slice() == original.buffer_at(*key).unwrap().slice()
+        })
+    }
+
+    #[test]
+    fn spread_light_dims_by_one_level_per_voxel() {
+        let light = VoxelLight::from_block_light([15, 4, 0]);
+        assert_eq!(
+            spread_light(light, VoxelLight::NONE, IVec3::X),
+            Some(VoxelLight::from_block_light([14, 3, 0]))
+        );
+        // only the channels which get brighter are spread.
+        assert_eq!(
+            spread_light(light, VoxelLight::from_block_light([2, 9, 5]), IVec3::NEG_Z),
+            Some(VoxelLight::from_block_light([14, 9, 5]))
+        );
+        assert_eq!(
+            spread_light(light, VoxelLight::from_block_light([14, 3, 0]), IVec3::Y),
+            None
+        );
+    }
+
+    #[test]
+    fn spread_light_keeps_full_sunlight_going_down() {
+        let mut sky = VoxelLight::NONE;
+        sky.set_level(VoxelLight::SKY_CHANNEL, VoxelLight::MAX_LEVEL);
+
+        assert_eq!(spread_light(sky, VoxelLight::NONE, IVec3::NEG_Y), Some(sky));
+        assert_eq!(spread_light(sky, sky, IVec3::NEG_Y), None);
+        assert_eq!(
+            spread_light(sky, VoxelLight::NONE, IVec3::X).map(VoxelLight::sky_light),
+            Some(VoxelLight::MAX_LEVEL - 1)
+        );
+
+        let mut dim_sky = VoxelLight::NONE;
+        dim_sky.set_level(VoxelLight::SKY_CHANNEL, 10);
+        assert_eq!(
+            spread_light(dim_sky, VoxelLight::NONE, IVec3::NEG_Y).map(VoxelLight::sky_light),
+            Some(9)
+        );
+    }
+
+    #[test]
+    fn is_lit_by_only_matches_dimmer_neighbours() {
+        assert!(is_lit_by(10, 9, 0, IVec3::X));
+        assert!(is_lit_by(10, 3, 0, IVec3::X));
+        assert!(!is_lit_by(10, 0, 0, IVec3::X));
+        assert!(!is_lit_by(10, 10, 0, IVec3::X));
+        assert!(!is_lit_by(10, 12, 0, IVec3::X));
+
+        let sky = VoxelLight::SKY_CHANNEL;
+        let max = VoxelLight::MAX_LEVEL;
+        // full sunlight below full sunlight comes from it, but not on the sides or above.
+        assert!(is_lit_by(max, max, sky, IVec3::NEG_Y));
+        assert!(!is_lit_by(max, max, sky, IVec3::X));
+        assert!(!is_lit_by(max, max, sky, IVec3::Y));
+        assert!(!is_lit_by(max, max, 0, IVec3::NEG_Y));
+    }
+
+    #[test]
+    fn light_chunk_spreads_the_emitters_around() {
+        let mut voxels = empty_chunk();
+        *voxels.voxel_at_mut([8, 8, 8].into()) = LAMP;
+        *voxels.voxel_at_mut([8, 8, 10].into()) = STONE;
+
+        let lights = light_chunk(&voxels, &materials(), false);
+        assert_eq!(
+            lights.voxel_at([8, 8, 8].into()),
+            VoxelLight::from_block_light([15, 10, 0])
+        );
+        assert_eq!(
+            lights.voxel_at([11, 8, 8].into()),
+            VoxelLight::from_block_light([12, 7, 0])
+        );
+        assert_eq!(
+            lights.voxel_at([10, 9, 7].into()),
+            VoxelLight::from_block_light([11, 6, 0])
+        );
+        // the opaque voxels stay dark and the light goes around them.
+        assert_eq!(lights.voxel_at([8, 8, 10].into()), VoxelLight::NONE);
+        assert_eq!(
+            lights.voxel_at([8, 8, 11].into()),
+            VoxelLight::from_block_light([10, 5, 0])
+        );
+        assert_eq!(lights.voxel_at([30, 30, 30].into()), VoxelLight::NONE);
+    }
+
+    #[test]
+    fn light_chunk_shines_the_sky_down_the_open_columns() {
+        let mut voxels = empty_chunk();
+        *voxels.voxel_at_mut([5, 30, 5].into()) = STONE;
+
+        let lights = light_chunk(&voxels, &materials(), true);
+        let sky_light = |pos: [u32; 3]| lights.voxel_at(pos.into()).sky_light();
+        assert_eq!(sky_light([5, 31, 5]), VoxelLight::MAX_LEVEL);
+        assert_eq!(sky_light([5, 30, 5]), 0);
+        assert_eq!(sky_light([5, 29, 5]), VoxelLight::MAX_LEVEL - 1);
+        assert_eq!(sky_light([5, 0, 5]), VoxelLight::MAX_LEVEL - 1);
+        assert_eq!(sky_light([6, 0, 5]), VoxelLight::MAX_LEVEL);
+
+        let lights = light_chunk(&voxels, &materials(), false);
+        assert_eq!(lights.voxel_at([0, 31, 0].into()), VoxelLight::NONE);
+    }
+
+    #[test]
+    fn adding_then_removing_an_emitter_restores_the_light() {
+        let keys = [IVec3::ZERO, IVec3::X * CHUNK_LENGTH as i32];
+        let world = || {
+            let mut chunk = empty_chunk();
+            *chunk.voxel_at_mut([28, 8, 8].into()) = LAMP;
+            light_world(vec![(keys[0], chunk), (keys[1], empty_chunk())])
+        };
+        let (mut voxels, mut lights) = world();
+        let (_, original) = world();
+
+        let block_light = |lights: &ChunkMap<VoxelLight, ChunkShape>, pos: IVec3| {
+            let light = lights.voxel_at(pos).unwrap();
+            [0, 1, 2].map(|channel| light.level(channel))
+        };
+        // the light of the first lamp crosses the chunk border.
+        assert_eq!(block_light(&lights, IVec3::new(35, 8, 8)), [8, 3, 0]);
+
+        let pos = IVec3::new(33, 8, 8);
+        edit(&mut voxels, &mut lights, pos, LAMP);
+        assert_eq!(block_light(&lights, pos), [15, 10, 0]);
+        assert_eq!(block_light(&lights, IVec3::new(30, 8, 8)), [13, 8, 0]);
+        assert!(!same_light(&lights, &original, &keys));
+
+        edit(&mut voxels, &mut lights, pos, Voxel::EMPTY_VOXEL);
+        assert!(same_light(&lights, &original, &keys));
+    }
+
+    #[test]
+    fn adding_then_removing_a_sky_blocker_restores_the_light() {
+        let keys = [IVec3::ZERO, IVec3::NEG_Y * CHUNK_LENGTH as i32];
+        let (mut voxels, mut lights) = light_world(keys.map(|key| (key, empty_chunk())).to_vec());
+        let (_, original) = light_world(keys.map(|key| (key, empty_chunk())).to_vec());
+
+        let sky_light = |lights: &ChunkMap<VoxelLight, ChunkShape>, pos: IVec3| {
+            lights.voxel_at(pos).unwrap().sky_light()
+        };
+        assert_eq!(
+            sky_light(&lights, IVec3::new(8, -30, 8)),
+            VoxelLight::MAX_LEVEL
+        );
+
+        let pos = IVec3::new(8, 20, 8);
+        edit(&mut voxels, &mut lights, pos, STONE);
+        assert_eq!(sky_light(&lights, pos + IVec3::Y), VoxelLight::MAX_LEVEL);
+        assert_eq!(sky_light(&lights, pos), 0);
+        // the shadow of the blocker reaches down into the chunk below.
+        assert_eq!(
+            sky_light(&lights, pos - IVec3::Y),
+            VoxelLight::MAX_LEVEL - 1
+        );
+        assert_eq!(
+            sky_light(&lights, IVec3::new(8, -30, 8)),
+            VoxelLight::MAX_LEVEL - 1
+        );
+
+        edit(&mut voxels, &mut lights, pos, Voxel::EMPTY_VOXEL);
+        assert!(same_light(&lights, &original, &keys));
+    }
+}
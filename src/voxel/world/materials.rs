@@ -4,7 +4,9 @@ use bevy::{
 };
 
 use crate::{
-    voxel::material::{MaterialRegistryInfo, VoxelMaterialFlags, VoxelMaterialRegistry},
+    voxel::material::{
+        MaterialRegistryInfo, VoxelMaterialFlags, VoxelMaterialRegistry, VoxelModelBox, VoxelShape,
+    },
     voxel_material,
 };
 
@@ -21,6 +23,19 @@ voxel_material!(Wood, 10);
 voxel_material!(Leaves, 11);
 voxel_material!(PineLeaves, 12);
 voxel_material!(PineWood, 13);
+voxel_material!(TallGrass, 14);
+voxel_material!(CoalOre, 15);
+voxel_material!(IronOre, 16);
+voxel_material!(GoldOre, 17);
+voxel_material!(RockSlab, 18);
+voxel_material!(WoodStairs, 19);
+voxel_material!(WoodFence, 20);
+
+/// A post standing in the middle of the voxel.
+const FENCE_MODEL: &[VoxelModelBox] = &[VoxelModelBox {
+    min: [0.375, 0.0, 0.375],
+    max: [0.625, 1.0, 0.625],
+}];
 
 pub struct VoxelWorldBaseMaterialsPlugin;
 
@@ -154,5 +169,79 @@ impl Plugin for VoxelWorldBaseMaterialsPlugin {
             metallic: 0.46,
             ..Default::default()
         });
+
+        registry.register_material::<TallGrass>(MaterialRegistryInfo {
+            base_color: Color::srgb_u8(96, 180, 42),
+            name: TallGrass::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.8,
+            reflectance: 0.2,
+            shape: VoxelShape::Cross,
+            ..Default::default()
+        });
+
+        registry.register_material::<CoalOre>(MaterialRegistryInfo {
+            base_color: Color::srgb_u8(54, 52, 52),
+            name: CoalOre::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.85,
+            reflectance: 0.3,
+            ..Default::default()
+        });
+
+        registry.register_material::<IronOre>(MaterialRegistryInfo {
+            base_color: Color::srgb_u8(176, 134, 110),
+            name: IronOre::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.6,
+            metallic: 0.4,
+            ..Default::default()
+        });
+
+        registry.register_material::<GoldOre>(MaterialRegistryInfo {
+            base_color: Color::srgb_u8(232, 190, 62),
+            name: GoldOre::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.35,
+            metallic: 0.9,
+            ..Default::default()
+        });
+
+        registry.register_material::<RockSlab>(MaterialRegistryInfo {
+            base_color: css::GRAY.into(),
+            name: RockSlab::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.85,
+            metallic: 0.6,
+            shape: VoxelShape::Slab,
+            ..Default::default()
+        });
+
+        registry.register_material::<WoodStairs>(MaterialRegistryInfo {
+            base_color: Color::srgb_u8(188, 147, 97),
+            name: WoodStairs::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.7,
+            metallic: 0.46,
+            shape: VoxelShape::Stair,
+            ..Default::default()
+        });
+
+        registry.register_material::<WoodFence>(MaterialRegistryInfo {
+            base_color: Color::srgb_u8(188, 147, 97),
+            name: WoodFence::NAME,
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            perceptual_roughness: 0.7,
+            metallic: 0.46,
+            shape: VoxelShape::Custom(FENCE_MODEL),
+            ..Default::default()
+        });
     }
 }
@@ -1,13 +1,18 @@
-use std::cell::RefCell;
-
 use super::{
     chunks::{ChunkEntities, ChunkLoadingSet, DirtyChunks},
+    culling::ChunkFaceConnectivity,
     terrain::TerrainGenSet,
     Chunk, ChunkShape, Voxel, CHUNK_LENGTH,
 };
 use crate::voxel::{
-    render::{mesh_buffer, ChunkMaterialSingleton, MeshBuffers},
-    storage::ChunkMap,
+    material::VoxelMaterialRegistry,
+    render::{
+        mesh_shaped_voxels, mesh_smooth_voxels, ChunkMaterialSingleton, ChunkMeshOutput,
+        ChunkMesher, MeshingMaterials, PaddedChunkBuffer, PaddedChunkShape, PaddedLightBuffer,
+        SelectedChunkMesher, SurfaceNetsBuffers, WaterMaterialSingleton,
+    },
+    storage::{ChunkMap, VoxelBuffer},
+    VoxelLight,
 };
 use bevy::{
     pbr::NotShadowCaster,
@@ -18,41 +23,215 @@ use bevy::{
     tasks::{AsyncComputeTaskPool, Task},
 };
 use futures_lite::future;
-use once_cell::sync::Lazy;
+use ndcopy::{copy3, fill3};
+use std::{cell::RefCell, sync::Arc};
 use thread_local::ThreadLocal;
 
+/// The mesh of the liquids of a chunk, rendered by a child entity of the chunk.
+#[derive(Component)]
+pub struct ChunkLiquidMesh(Handle<Mesh>);
+
 /// Attaches to the newly inserted chunk entities components required for rendering.
 pub fn prepare_chunks(
     chunks: Query<(Entity, &Chunk), Added<Chunk>>,
     mut meshes: ResMut<Assets<Mesh>>,
     material: Res<ChunkMaterialSingleton>,
+    water_material: Res<WaterMaterialSingleton>,
     mut cmds: Commands,
 ) {
     for (chunk, chunk_key) in chunks.iter() {
-        let mut entity_commands = cmds.entity(chunk);
-        entity_commands.insert((
-            MaterialMeshBundle {
-                material: (**material).clone(),
-                mesh: meshes.add(Mesh::new(
-                    PrimitiveTopology::TriangleList,
-                    RenderAssetUsages::default(),
-                )),
-                transform: Transform::from_translation(chunk_key.0.as_vec3()),
-                visibility: Visibility::Hidden,
-                ..Default::default()
-            },
-            Aabb::from_min_max(Vec3::ZERO, Vec3::splat(CHUNK_LENGTH as f32)),
+        let liquid_mesh = meshes.add(Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
         ));
-        // There is no need to cast shadows for chunks below the surface.
-        if chunk_key.0.y <= 64 {
-            entity_commands.insert(NotShadowCaster);
+
+        cmds.entity(chunk)
+            .insert((
+                MaterialMeshBundle {
+                    material: (**material).clone(),
+                    mesh: meshes.add(Mesh::new(
+                        PrimitiveTopology::TriangleList,
+                        RenderAssetUsages::default(),
+                    )),
+                    transform: Transform::from_translation(chunk_key.0.as_vec3()),
+                    visibility: Visibility::Hidden,
+                    ..Default::default()
+                },
+                Aabb::from_min_max(Vec3::ZERO, Vec3::splat(CHUNK_LENGTH as f32)),
+                ChunkLiquidMesh(liquid_mesh.clone()),
+            ))
+            .with_children(|chunk| {
+                chunk.spawn((
+                    MaterialMeshBundle {
+                        material: (**water_material).clone(),
+                        mesh: liquid_mesh,
+                        ..Default::default()
+                    },
+                    Aabb::from_min_max(Vec3::ZERO, Vec3::splat(CHUNK_LENGTH as f32)),
+                    NotShadowCaster,
+                ));
+            });
+    }
+}
+
+/// Returns whether the sun can shine on the chunk surfaces, that is whether the chunk
+/// or the voxels bordering it receive direct skylight.
+fn is_exposed_to_sky(lights: &ChunkMap<VoxelLight, ChunkShape>, key: IVec3) -> bool {
+    let sunlit = |light: VoxelLight| light.sky_light() == VoxelLight::MAX_LEVEL;
+
+    if lights
+        .buffer_at(key)
+        .is_some_and(|buffer| buffer.slice().iter().copied().any(sunlit))
+    {
+        return true;
+    }
+
+    (0..3)
+        .flat_map(|axis| [(axis, -1), (axis, 1)])
+        .any(|(axis, direction)| {
+            let mut offset = IVec3::ZERO;
+            offset[axis] = direction * CHUNK_LENGTH as i32;
+            let Some(neighbour) = lights.buffer_at(key + offset) else {
+                return false;
+            };
+
+            // the layer of the neighbouring chunk touching this chunk.
+            let layer = if direction < 0 { CHUNK_LENGTH - 1 } else { 0 };
+            (0..CHUNK_LENGTH * CHUNK_LENGTH).any(|i| {
+                let mut pos = UVec3::splat(i % CHUNK_LENGTH);
+                pos[axis] = layer;
+                pos[(axis + 2) % 3] = i / CHUNK_LENGTH;
+                sunlit(neighbour.voxel_at(pos.to_array().into()))
+            })
+        })
+}
+
+/// Only lets the chunks exposed to the sky cast shadows, the buried ones can't shadow anything the surface doesn't.
+fn update_chunk_shadow_casters(
+    mut commands: Commands,
+    dirty_chunks: Res<DirtyChunks>,
+    chunk_entities: Res<ChunkEntities>,
+    lights: Res<ChunkMap<VoxelLight, ChunkShape>>,
+    shadow_casters: Query<Has<NotShadowCaster>, With<Chunk>>,
+) {
+    for key in dirty_chunks.iter_dirty() {
+        let Some(entity) = chunk_entities.entity(*key) else {
+            continue;
+        };
+        let Ok(not_shadow_caster) = shadow_casters.get(entity) else {
+            continue;
+        };
+
+        match (is_exposed_to_sky(&lights, *key), not_shadow_caster) {
+            (true, true) => {
+                commands.entity(entity).remove::<NotShadowCaster>();
+            }
+            (false, false) => {
+                commands.entity(entity).insert(NotShadowCaster);
+            }
+            _ => {}
         }
     }
 }
 
-// a pool of mesh buffers shared between meshing tasks.
-static SHARED_MESH_BUFFERS: Lazy<ThreadLocal<RefCell<MeshBuffers<Voxel, ChunkShape>>>> =
-    Lazy::new(ThreadLocal::default);
+/// The buffers of a chunk and its neighbours, as returned by [`ChunkMap::shared_neighbourhood_at`].
+pub type ChunkNeighbourhood<V> = [Option<Arc<VoxelBuffer<V, ChunkShape>>>; 27];
+
+/// Copies the voxels of a chunk and the bordering voxels of its neighbours into a padded chunk view.
+/// Voxels of the neighbours which aren't loaded are set to their default value.
+pub fn copy_neighbourhood_to_padded_buffer<V: Copy + Default>(
+    neighbourhood: &ChunkNeighbourhood<V>,
+    padded: &mut VoxelBuffer<V, PaddedChunkShape>,
+) {
+    // the source minimum, destination minimum and length of the copied region along an axis for a neighbour offset.
+    let axis_region = |offset: usize| match offset {
+        0 => (CHUNK_LENGTH - 1, 0, 1),
+        1 => (0, 1, CHUNK_LENGTH),
+        _ => (0, CHUNK_LENGTH + 1, 1),
+    };
+
+    for (index, buffer) in neighbourhood.iter().enumerate() {
+        let regions = [index / 9, index / 3 % 3, index % 3].map(axis_region);
+
+        // the padded views are reused between chunks so the regions of missing neighbours must be cleared.
+        let Some(buffer) = buffer else {
+            fill3(
+                regions.map(|(_, _, length)| length),
+                V::default(),
+                padded.slice_mut(),
+                &PaddedChunkShape {},
+                regions.map(|(_, dst, _)| dst),
+            );
+            continue;
+        };
+
+        copy3(
+            regions.map(|(_, _, length)| length),
+            buffer.slice(),
+            buffer.shape(),
+            regions.map(|(src, _, _)| src),
+            padded.slice_mut(),
+            &PaddedChunkShape {},
+            regions.map(|(_, dst, _)| dst),
+        );
+    }
+}
+
+/// Intermediate buffers for meshing a chunk which are reusable between chunks to not allocate.
+struct ChunkMeshingBuffers {
+    voxels: PaddedChunkBuffer,
+    lights: PaddedLightBuffer,
+    surface_nets: SurfaceNetsBuffers,
+}
+
+impl Default for ChunkMeshingBuffers {
+    fn default() -> Self {
+        Self {
+            voxels: PaddedChunkBuffer::new_empty(PaddedChunkShape {}),
+            lights: PaddedLightBuffer::new_empty(PaddedChunkShape {}),
+            surface_nets: SurfaceNetsBuffers::default(),
+        }
+    }
+}
+
+/// The [`ChunkMeshingBuffers`] of each thread running meshing tasks.
+#[derive(Resource, Clone, Default)]
+struct SharedMeshingBuffers(Arc<ThreadLocal<RefCell<ChunkMeshingBuffers>>>);
+
+impl SharedMeshingBuffers {
+    /// Meshes the chunk at the center of a neighbourhood into `output`: the blocky voxels are meshed by the selected
+    /// [`ChunkMesher`], the voxels with other shapes or a smooth meshing mode are meshed the same way for every mesher.
+    fn mesh_chunk(
+        &self,
+        mesher: &dyn ChunkMesher,
+        voxels: &ChunkNeighbourhood<Voxel>,
+        lights: &ChunkNeighbourhood<VoxelLight>,
+        materials: &MeshingMaterials,
+        output: &mut ChunkMeshOutput,
+    ) {
+        let mut buffers = self.0.get_or_default().borrow_mut();
+        let buffers = &mut *buffers;
+
+        copy_neighbourhood_to_padded_buffer(voxels, &mut buffers.voxels);
+        copy_neighbourhood_to_padded_buffer(lights, &mut buffers.lights);
+        let (voxels, lights) = (&buffers.voxels, &buffers.lights);
+
+        mesher.mesh_chunk(voxels, lights, materials, output);
+        mesh_shaped_voxels(voxels, lights, materials, &mut output.terrain);
+        mesh_smooth_voxels(
+            voxels,
+            lights,
+            &mut buffers.surface_nets,
+            materials,
+            &mut output.terrain,
+        );
+    }
+}
+
+/// Updates the meshing materials snapshot handed to the meshing tasks.
+fn update_meshing_materials(registry: Res<VoxelMaterialRegistry>, mut cmds: Commands) {
+    cmds.insert_resource(MeshingMaterials::from_registry(&registry));
+}
 
 /// Queues meshing tasks for the chunks in need of a remesh.
 fn queue_mesh_tasks(
@@ -60,6 +239,9 @@ fn queue_mesh_tasks(
     dirty_chunks: Res<DirtyChunks>,
     chunk_entities: Res<ChunkEntities>,
     chunks: Res<ChunkMap<Voxel, ChunkShape>>,
+    lights: Res<ChunkMap<VoxelLight, ChunkShape>>,
+    materials: Res<MeshingMaterials>,
+    (mesher, buffers): (Res<SelectedChunkMesher>, Res<SharedMeshingBuffers>),
 ) {
     let task_pool = AsyncComputeTaskPool::get();
 
@@ -67,27 +249,34 @@ fn queue_mesh_tasks(
         .iter_dirty()
         .filter_map(|key| chunk_entities.entity(*key).map(|entity| (key, entity)))
         .filter_map(|(key, entity)| {
-            chunks
-                .buffer_at(*key)
-                .map(|buffer| (buffer.clone(), entity))
+            chunks.shared_buffer_at(*key).map(|buffer| {
+                (
+                    buffer,
+                    chunks.shared_neighbourhood_at(*key),
+                    lights.shared_neighbourhood_at(*key),
+                    entity,
+                )
+            })
         })
-        .map(|(buffer, entity)| {
+        .map(|(buffer, neighbourhood, light_neighbourhood, entity)| {
+            let materials = materials.clone();
+            let mesher = mesher.clone();
+            let buffers = buffers.clone();
             (
                 entity,
                 ChunkMeshingTask(task_pool.spawn(async move {
-                    let mut mesh_buffers = SHARED_MESH_BUFFERS
-                        .get_or(|| {
-                            RefCell::new(MeshBuffers::<Voxel, ChunkShape>::new(ChunkShape {}))
-                        })
-                        .borrow_mut();
-
-                    let mut mesh = Mesh::new(
-                        PrimitiveTopology::TriangleList,
-                        RenderAssetUsages::default(),
+                    let mut mesh_data = ChunkMeshOutput::default();
+                    buffers.mesh_chunk(
+                        &*mesher,
+                        &neighbourhood,
+                        &light_neighbourhood,
+                        &materials,
+                        &mut mesh_data,
                     );
-                    mesh_buffer(&buffer, &mut mesh_buffers, &mut mesh, 1.0);
 
-                    mesh
+                    let connectivity = ChunkFaceConnectivity::from_voxels(&buffer, &materials);
+                    let (terrain, liquid) = mesh_data.into_meshes();
+                    (terrain, liquid, connectivity)
                 })),
             )
         })
@@ -99,15 +288,29 @@ fn queue_mesh_tasks(
 /// Polls and process the generated chunk meshes
 fn process_mesh_tasks(
     mut meshes: ResMut<Assets<Mesh>>,
-    mut chunk_query: Query<(Entity, &Handle<Mesh>, &mut ChunkMeshingTask), With<Chunk>>,
+    mut chunk_query: Query<
+        (
+            Entity,
+            &Handle<Mesh>,
+            &ChunkLiquidMesh,
+            &mut ChunkMeshingTask,
+        ),
+        With<Chunk>,
+    >,
     mut commands: Commands,
 ) {
     chunk_query
         .iter_mut()
-        .for_each(|(entity, handle, mut mesh_task)| {
-            if let Some(mesh) = future::block_on(future::poll_once(&mut mesh_task.0)) {
+        .for_each(|(entity, handle, liquid_handle, mut mesh_task)| {
+            if let Some((mesh, liquid_mesh, connectivity)) =
+                future::block_on(future::poll_once(&mut mesh_task.0))
+            {
                 *meshes.get_mut(handle).unwrap() = mesh;
-                commands.entity(entity).remove::<ChunkMeshingTask>();
+                *meshes.get_mut(&liquid_handle.0).unwrap() = liquid_mesh;
+                commands
+                    .entity(entity)
+                    .insert(connectivity)
+                    .remove::<ChunkMeshingTask>();
             }
         });
 }
@@ -125,9 +328,18 @@ impl Plugin for VoxelWorldMeshingPlugin {
             Update,
             ChunkMeshingSet.after(TerrainGenSet).after(ChunkLoadingSet),
         )
+        .init_resource::<MeshingMaterials>()
+        .init_resource::<SelectedChunkMesher>()
+        .init_resource::<SharedMeshingBuffers>()
         .add_systems(
             Update,
-            (prepare_chunks, queue_mesh_tasks, process_mesh_tasks)
+            (
+                prepare_chunks,
+                update_meshing_materials.run_if(resource_changed::<VoxelMaterialRegistry>),
+                update_chunk_shadow_casters,
+                queue_mesh_tasks,
+                process_mesh_tasks,
+            )
                 .chain()
                 .in_set(ChunkMeshingSet),
         );
@@ -135,4 +347,4 @@ impl Plugin for VoxelWorldMeshingPlugin {
 }
 
 #[derive(Component)]
-pub struct ChunkMeshingTask(Task<Mesh>);
+pub struct ChunkMeshingTask(Task<(Mesh, Mesh, ChunkFaceConnectivity)>);
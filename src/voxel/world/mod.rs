@@ -13,11 +13,18 @@ pub use chunks::{
 };
 
 mod chunks_anim;
+mod culling;
+pub use culling::ChunkCullingSettings;
+mod edits;
+mod lighting;
 pub mod materials;
 mod meshing;
+pub use meshing::ChunkMeshingSet;
 pub mod player;
 mod sky;
+pub use sky::{SkyShadowSettings, TimeOfDay};
 mod terrain;
+pub use terrain::RegenerateTerrain;
 
 /// Registers all resources and systems for simulating and rendering an editable and interactive voxel world.
 pub struct VoxelWorldPlugin;
@@ -27,6 +34,9 @@ impl Plugin for VoxelWorldPlugin {
         app.insert_resource(ChunkMap::<Voxel, ChunkShape>::new(ChunkShape {}))
             .add_plugins(chunks::VoxelWorldChunkingPlugin)
             .add_plugins(meshing::VoxelWorldMeshingPlugin)
+            .add_plugins(edits::VoxelWorldEditsPlugin)
+            .add_plugins(lighting::VoxelWorldLightingPlugin)
+            .add_plugins(culling::VoxelWorldCullingPlugin)
             // ordering of plugin insertion matters here.
             .add_plugins(terraingen::TerrainGeneratorPlugin)
             .add_plugins(terrain::VoxelWorldTerrainGenPlugin)
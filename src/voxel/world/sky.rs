@@ -1,31 +1,189 @@
-use bevy::prelude::{
-    Color, Commands, Deref, DirectionalLight, DirectionalLightBundle, Entity, ParamSet, Plugin,
-    Query, Res, Resource, Startup, Transform, Update, Vec3, With,
+use std::{f32::consts::TAU, time::Duration};
+
+use bevy::{
+    color::Mix,
+    ecs::change_detection::DetectChanges,
+    pbr::{CascadeShadowConfig, CascadeShadowConfigBuilder},
+    prelude::{
+        light_consts, AmbientLight, Color, Commands, Deref, DirectionalLight,
+        DirectionalLightBundle, Entity, IVec3, IntoSystemConfigs, Local, ParamSet, Plugin, Query,
+        Res, ResMut, Resource, Startup, Time, Transform, Update, Vec3, With,
+    },
+    time::common_conditions::on_timer,
 };
+use bevy_atmosphere::prelude::{AtmosphereMut, Nishita};
 
-use super::player::PlayerController;
+use super::{player::PlayerController, ChunkLoadRadius, ChunkShape, CHUNK_LENGTH};
+use crate::voxel::{
+    render::{TerrainFogSet, TerrainFogSettings},
+    storage::ChunkMap,
+    Voxel,
+};
 
 #[derive(Resource, Deref)]
 struct SkyLightEntity(Entity);
 
-fn setup_sky_lighting(mut cmds: Commands) {
-    const _SIZE: f32 = 200.0; //make this dynamic according to view distance???
+/// The time of the day / night cycle.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct TimeOfDay {
+    /// The current hour of the day, from 0.0 to 24.0, noon being 12.0.
+    pub hour: f32,
+    /// The real time it takes for a whole day to pass.
+    pub day_length: Duration,
+    pub paused: bool,
+    /// Whether the day / night cycle drives the [`TerrainFogSettings::color`], overwriting the colour set by the user.
+    pub drives_fog_color: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            hour: 9.0,
+            day_length: Duration::from_secs(20 * 60),
+            paused: false,
+            drives_fog_color: true,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Returns the direction pointing towards the sun.
+    /// The sun rises at 6:00 and sets at 18:00, slightly tilted towards +Z so it never sits straight overhead.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.hour / 24.0 - 0.25) * TAU;
+        Vec3::new(-angle.cos(), angle.sin(), 0.4).normalize()
+    }
+
+    /// Advances the time of the day by the specified real time.
+    fn advance(&mut self, delta: Duration) {
+        let hours = 24.0 * delta.as_secs_f32() / self.day_length.as_secs_f32().max(1.0);
+        self.hour = (self.hour + hours).rem_euclid(24.0);
+    }
+}
+
+/// Settings of the shadows cast by the sky light.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct SkyShadowSettings {
+    pub enabled: bool,
+    pub num_cascades: usize,
+    /// The horizontal distance covered by the shadows, as a fraction of the horizontal [`ChunkLoadRadius`].
+    pub distance: f32,
+    /// The far bound of the first cascade (in voxels) measured from the ground below the camera.
+    pub first_cascade_far_bound: f32,
+    /// The overlap proportion between cascades.
+    pub overlap_proportion: f32,
+}
+
+impl Default for SkyShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            num_cascades: 4,
+            distance: 0.5,
+            first_cascade_far_bound: 24.0,
+            overlap_proportion: 0.2,
+        }
+    }
+}
+
+impl SkyShadowSettings {
+    /// Returns the cascades covering the specified load radius, as seen from the specified height above the ground.
+    fn cascades(&self, load_radius: &ChunkLoadRadius, height: f32) -> CascadeShadowConfigBuilder {
+        let distance = self.distance * (load_radius.horizontal * CHUNK_LENGTH as i32) as f32;
+
+        CascadeShadowConfigBuilder {
+            num_cascades: self.num_cascades,
+            minimum_distance: 0.1,
+            // the shadows need to reach the ground when looking at it from above.
+            maximum_distance: distance.hypot(height).max(1.0),
+            first_cascade_far_bound: self.first_cascade_far_bound + height,
+            overlap_proportion: self.overlap_proportion,
+        }
+    }
+}
+
+/// The sky lighting at a given height of the sun.
+struct SkyKeyframe {
+    /// The y component of the sun direction at which this keyframe applies.
+    sun_height: f32,
+    light_color: Color,
+    illuminance: f32,
+    ambient_brightness: f32,
+    fog_color: Color,
+}
+
+impl SkyKeyframe {
+    const NIGHT: Self = Self {
+        sun_height: -0.25,
+        light_color: Color::srgb(0.55, 0.65, 1.0),
+        illuminance: light_consts::lux::OVERCAST_DAY * 0.5,
+        ambient_brightness: 8.0,
+        fog_color: Color::srgb(0.02, 0.03, 0.07),
+    };
+
+    const DAWN: Self = Self {
+        sun_height: 0.0,
+        light_color: Color::srgb(1.0, 0.7, 0.55),
+        illuminance: light_consts::lux::OVERCAST_DAY * 2.0,
+        ambient_brightness: 35.0,
+        fog_color: Color::srgb(0.7, 0.52, 0.5),
+    };
+
+    const DUSK: Self = Self {
+        sun_height: 0.0,
+        light_color: Color::srgb(1.0, 0.6, 0.35),
+        illuminance: light_consts::lux::OVERCAST_DAY * 2.0,
+        ambient_brightness: 35.0,
+        fog_color: Color::srgb(0.75, 0.45, 0.3),
+    };
+
+    const DAY: Self = Self {
+        sun_height: 0.35,
+        light_color: Color::WHITE,
+        illuminance: light_consts::lux::AMBIENT_DAYLIGHT,
+        ambient_brightness: 80.0,
+        fog_color: Color::srgb(0.4, 0.4, 0.4),
+    };
+
+    /// Blends between two keyframes, `t` going from 0.0 (`self`) to 1.0 (`other`).
+    fn mix(&self, other: &Self, t: f32) -> Self {
+        Self {
+            sun_height: self.sun_height + (other.sun_height - self.sun_height) * t,
+            light_color: self.light_color.mix(&other.light_color, t),
+            illuminance: self.illuminance + (other.illuminance - self.illuminance) * t,
+            ambient_brightness: self.ambient_brightness
+                + (other.ambient_brightness - self.ambient_brightness) * t,
+            fog_color: self.fog_color.mix(&other.fog_color, t),
+        }
+    }
+
+    /// Returns the sky lighting for the specified time of the day.
+    fn at(time: &TimeOfDay) -> Self {
+        let sun_height = time.sun_direction().y;
+        let twilight = if time.hour < 12.0 {
+            &Self::DAWN
+        } else {
+            &Self::DUSK
+        };
+
+        let (from, to) = if sun_height < twilight.sun_height {
+            (&Self::NIGHT, twilight)
+        } else {
+            (twilight, &Self::DAY)
+        };
 
+        let t = (sun_height - from.sun_height) / (to.sun_height - from.sun_height);
+        from.mix(to, t.clamp(0.0, 1.0))
+    }
+}
+
+fn setup_sky_lighting(mut cmds: Commands) {
     let sky_light_entity = cmds
         .spawn(DirectionalLightBundle {
             transform: Transform::IDENTITY.looking_to(Vec3::new(-1.0, -0.6, -1.0), Vec3::Y),
             directional_light: DirectionalLight {
                 color: Color::WHITE,
                 shadows_enabled: true,
-                // shadow_projection: OrthographicProjection {
-                //     // left: -SIZE,
-                //     // right: SIZE,
-                //     // bottom: -SIZE,
-                //     // top: SIZE,
-                //     near: -SIZE,
-                //     far: SIZE,
-                //     ..Default::default()
-                // },
                 ..Default::default()
             },
             ..Default::default()
@@ -55,11 +213,116 @@ fn update_light_position(
     }
 }
 
+fn advance_time_of_day(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if !time_of_day.paused {
+        time_of_day.advance(time.delta());
+    }
+}
+
+/// Returns the distance between the specified position and the ground below it,
+/// or the length of the loaded world below it if there is no ground.
+fn height_above_ground(
+    chunks: &ChunkMap<Voxel, ChunkShape>,
+    load_radius: &ChunkLoadRadius,
+    position: Vec3,
+) -> f32 {
+    let max_depth = load_radius.vertical * CHUNK_LENGTH as i32;
+    let start = position.floor().as_ivec3();
+
+    (0..max_depth)
+        .find(|depth| {
+            chunks
+                .voxel_at(start - IVec3::Y * *depth)
+                .is_some_and(|voxel| voxel != Voxel::EMPTY_VOXEL)
+        })
+        .unwrap_or(max_depth) as f32
+}
+
+/// Fits the shadow cascades of the sky light to the loaded world and the height of the camera above the ground.
+fn update_shadow_cascades(
+    settings: Res<SkyShadowSettings>,
+    load_radius: Res<ChunkLoadRadius>,
+    chunks: Res<ChunkMap<Voxel, ChunkShape>>,
+    sky_light_entity: Res<SkyLightEntity>,
+    player: Query<&Transform, With<PlayerController>>,
+    mut sky_lights: Query<(&mut DirectionalLight, &mut CascadeShadowConfig)>,
+    mut last_height: Local<Option<f32>>,
+) {
+    let Ok((mut light, mut cascades)) = sky_lights.get_mut(**sky_light_entity) else {
+        return;
+    };
+
+    if light.shadows_enabled != settings.enabled {
+        light.shadows_enabled = settings.enabled;
+    }
+
+    let height = player.get_single().map_or(0.0, |transform| {
+        height_above_ground(&chunks, &load_radius, transform.translation)
+    });
+
+    // only rebuild the cascades when the height changed noticeably.
+    let height = (height / 8.0).round() * 8.0;
+    if *last_height == Some(height) && !settings.is_changed() && !load_radius.is_changed() {
+        return;
+    }
+
+    *last_height = Some(height);
+    *cascades = settings.cascades(&load_radius, height).build();
+}
+
+/// Rotates the sun and fades the sky lighting and the fog (if the [`TimeOfDay`] drives it) according to the
+/// [`TimeOfDay`].
+fn update_sky(
+    time_of_day: Res<TimeOfDay>,
+    sky_light_entity: Res<SkyLightEntity>,
+    mut sky_lights: Query<(&mut DirectionalLight, &mut Transform)>,
+    mut atmosphere: AtmosphereMut<Nishita>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut fog: ResMut<TerrainFogSettings>,
+) {
+    let sun_direction = time_of_day.sun_direction();
+    let keyframe = SkyKeyframe::at(&time_of_day);
+    atmosphere.sun_position = sun_direction;
+
+    if let Ok((mut light, mut transform)) = sky_lights.get_mut(**sky_light_entity) {
+        // the moon lights the world from the opposite side of the sun at night.
+        let light_direction = if sun_direction.y >= 0.0 {
+            sun_direction
+        } else {
+            -sun_direction
+        };
+        transform.look_to(-light_direction, Vec3::Y);
+        light.color = keyframe.light_color;
+        light.illuminance = keyframe.illuminance;
+    }
+
+    ambient_light.brightness = keyframe.ambient_brightness;
+
+    if time_of_day.drives_fog_color && fog.color != keyframe.fog_color {
+        fog.color = keyframe.fog_color;
+    }
+}
+
 pub struct InteractiveSkyboxPlugin;
 
 impl Plugin for InteractiveSkyboxPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_systems(Startup, setup_sky_lighting)
-            .add_systems(Update, update_light_position);
+        app.init_resource::<TimeOfDay>()
+            .init_resource::<SkyShadowSettings>()
+            .add_systems(Startup, setup_sky_lighting)
+            .add_systems(
+                Update,
+                (
+                    update_light_position,
+                    update_shadow_cascades,
+                    (
+                        advance_time_of_day,
+                        // regenerating the atmosphere and updating the terrain material are costly.
+                        update_sky.run_if(on_timer(Duration::from_millis(100))),
+                    )
+                        .chain()
+                        .before(TerrainFogSet),
+                ),
+            );
     }
 }
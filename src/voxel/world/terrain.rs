@@ -1,50 +1,84 @@
 use super::{
     chunks::{ChunkLoadingSet, DirtyChunks},
-    Chunk, ChunkShape,
+    lighting::NEIGHBOUR_OFFSETS,
+    Chunk, ChunkShape, CHUNK_LENGTH,
 };
 use crate::voxel::{
     storage::{ChunkMap, VoxelBuffer},
-    terraingen::TERRAIN_GENERATOR,
-    Voxel,
+    terraingen::{biomes_loaded, WorldSeed, TERRAIN_GENERATOR},
+    Voxel, VoxelLight,
 };
 use bevy::{
+    math::IVec3,
     prelude::{
-        Added, Commands, Component, Entity, IntoSystemConfigs, IntoSystemSetConfigs, Plugin, Query,
-        ResMut, SystemSet, Update,
+        Added, Commands, Component, Entity, Event, EventReader, IntoSystemConfigs,
+        IntoSystemSetConfigs, Plugin, Query, Res, ResMut, SystemSet, Update,
     },
     tasks::{AsyncComputeTaskPool, Task},
 };
 use futures_lite::future;
 
-/// Queues the terrain gen async tasks for the newly created chunks.
-fn queue_terrain_gen(mut commands: Commands, new_chunks: Query<(Entity, &Chunk), Added<Chunk>>) {
-    let task_pool = AsyncComputeTaskPool::get();
+/// Chunks at or above this height aren't generated as they only contain air.
+pub const MAX_TERRAIN_CHUNK_Y: i32 = 288;
+
+/// Asks for the terrain of every loaded chunk to be generated again, e.g. after the world generation settings changed.
+/// The voxel edits made to the loaded chunks are lost.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct RegenerateTerrain;
+
+/// Spawns the async task generating the terrain of a chunk.
+fn spawn_terrain_gen(key: IVec3, seed: WorldSeed) -> TerrainGenTask {
+    let generator = TERRAIN_GENERATOR.read().unwrap().clone();
+    TerrainGenTask(AsyncComputeTaskPool::get().spawn(async move {
+        let mut chunk_data = VoxelBuffer::<Voxel, ChunkShape>::new_empty(ChunkShape {});
+        generator.generate(key, seed, &mut chunk_data);
+        chunk_data
+    }))
+}
 
+/// Queues the terrain gen async tasks for the newly created chunks.
+fn queue_terrain_gen(
+    mut commands: Commands,
+    seed: Res<WorldSeed>,
+    new_chunks: Query<(Entity, &Chunk), Added<Chunk>>,
+) {
     new_chunks
         .iter()
-        .filter(|(_, key)| key.0.y < 288)
-        .map(|(entity, key)| (entity, key.0))
-        .map(|(entity, key)| {
-            (
-                entity,
-                (TerrainGenTask(task_pool.spawn(async move {
-                    let mut chunk_data = VoxelBuffer::<Voxel, ChunkShape>::new_empty(ChunkShape {});
-                    TERRAIN_GENERATOR
-                        .read()
-                        .unwrap()
-                        .generate(key, &mut chunk_data);
-                    chunk_data
-                }))),
-            )
-        })
-        .for_each(|(entity, gen_task)| {
-            commands.entity(entity).insert(gen_task);
+        .filter(|(_, key)| key.0.y < MAX_TERRAIN_CHUNK_Y)
+        .for_each(|(entity, key)| {
+            commands
+                .entity(entity)
+                .insert(spawn_terrain_gen(key.0, *seed));
+        });
+}
+
+/// Queues the terrain gen async tasks for every loaded chunk when the terrain has to be regenerated.
+/// The tasks still running get replaced so they don't put back outdated terrain.
+fn queue_terrain_regen(
+    mut commands: Commands,
+    seed: Res<WorldSeed>,
+    mut regen_events: EventReader<RegenerateTerrain>,
+    chunks: Query<(Entity, &Chunk)>,
+) {
+    if regen_events.is_empty() {
+        return;
+    }
+    regen_events.clear();
+
+    chunks
+        .iter()
+        .filter(|(_, key)| key.0.y < MAX_TERRAIN_CHUNK_Y)
+        .for_each(|(entity, key)| {
+            commands
+                .entity(entity)
+                .insert(spawn_terrain_gen(key.0, *seed));
         });
 }
 
 /// Polls for finished gen tasks and put back the generated terrain into the voxel map
 pub fn process_terrain_gen(
     mut chunk_data: ResMut<ChunkMap<Voxel, ChunkShape>>,
+    mut chunk_lights: ResMut<ChunkMap<VoxelLight, ChunkShape>>,
     mut commands: Commands,
     mut dirty_chunks: ResMut<DirtyChunks>,
     mut gen_chunks: Query<(Entity, &Chunk, &mut TerrainGenTask)>,
@@ -54,7 +88,16 @@ pub fn process_terrain_gen(
         .for_each(|(entity, chunk, mut gen_task)| {
             if let Some(data) = future::block_on(future::poll_once(&mut gen_task.0)) {
                 chunk_data.insert(chunk.0, data);
+                // regenerated chunks get lit again from scratch.
+                chunk_lights.remove(chunk.0);
                 dirty_chunks.mark_dirty(chunk.0);
+                // the loaded neighbours mesh their borders against the voxels of this chunk.
+                for offset in NEIGHBOUR_OFFSETS {
+                    let neighbour = chunk.0 + offset * CHUNK_LENGTH as i32;
+                    if chunk_data.exists(neighbour) {
+                        dirty_chunks.mark_dirty(neighbour);
+                    }
+                }
                 commands.entity(entity).remove::<TerrainGenTask>();
             }
         });
@@ -69,10 +112,15 @@ pub struct TerrainGenSet;
 
 impl Plugin for VoxelWorldTerrainGenPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.configure_sets(Update, TerrainGenSet.after(ChunkLoadingSet))
+        app.add_event::<RegenerateTerrain>()
+            .configure_sets(Update, TerrainGenSet.after(ChunkLoadingSet))
             .add_systems(
                 Update,
-                (queue_terrain_gen, process_terrain_gen)
+                (
+                    queue_terrain_gen.run_if(biomes_loaded),
+                    queue_terrain_regen,
+                    process_terrain_gen,
+                )
                     .chain()
                     .in_set(TerrainGenSet),
             );